@@ -16,6 +16,7 @@ pub enum Api {
 
 impl Api {
   pub fn save_candles(&self, query: &mut Query) -> Result<Vec<Candle>> {
+    let token = terminal::jobs::current();
     let mut tries = 0;
     let mut missing = query.missing_candles()?;
 
@@ -33,6 +34,7 @@ impl Api {
       pb(missing_pb_label, 0.);
 
       for range_index in 0..missing.len() {
+        token.check()?;
         let range = &missing[range_index];
         pb(missing_pb_label, range_index as f64 / missing.len() as f64);
 
@@ -53,7 +55,11 @@ impl Api {
         pb(pb_label, 0.);
         let (mut i, num_candles) = (0f64, candles.len() as f64);
 
+        // candles are inserted one row at a time, so stopping between
+        // them leaves nothing half-written. Anything left missing will
+        // be picked up by the next save_candles.
         for candle in candles {
+          token.check()?;
           pb(pb_label, i / num_candles);
           query.insert_candle(&candle)?;
           i += 1.;
@@ -67,6 +73,7 @@ impl Api {
     }
 
     query.linear_regression()?;
+    candle::update_domain(query)?;
     query.query_candles()
   }
 }
@@ -23,7 +23,7 @@ impl ApiTrait for Binance {
         end
       );
 
-      log!("url: {}", url);
+      log!(debug: "url: {}", url);
 
       let body = reqwest::blocking::get(&url)?.text()?;
       let raw_candles: Vec<RawCandle> = serde_json::from_str(&body)?;
@@ -35,20 +35,22 @@ impl ApiTrait for Binance {
       Ok(())
     };
 
+    let token = terminal::jobs::current();
     let pb_label = "Downloading candles...".to_owned();
-    let _ = terminal::PB.0.send((pb_label.clone(), 0.));
+    pb(&pb_label, 0.);
 
     let r = query.range().unwrap();
     let r = r.start..(r.end - 1);
     for start in (r.start..r.end).step_by(fetch_step) {
-      let _ = terminal::PB.0.send((
-        pb_label.clone(),
+      token.check()?;
+      pb(
+        &pb_label,
         (start - r.start) as f64 / (r.end - r.start) as f64,
-      ));
+      );
       fetch(start, (start + fetch_step as i64).min(r.end))?;
     }
 
-    let _ = terminal::PB.0.send((pb_label.clone(), -1.));
+    pb(&pb_label, -1.);
 
     Ok(result)
   }
@@ -0,0 +1,105 @@
+// clippy checks the bench as a test target, which would leave the
+// module's test helpers unused
+#[allow(dead_code)]
+#[path = "../core/domain.rs"]
+mod domain;
+
+use bencher::{benchmark_group, benchmark_main, black_box, Bencher};
+use domain::Domain;
+
+const CANDLES: usize = 100_000;
+// the naive walk is quadratic on trending data, so it gets fewer
+const NAIVE_CANDLES: usize = 20_000;
+
+// a trending random walk, so some candles stand over thousands of others
+fn walk(n: usize) -> (Vec<f32>, Vec<f32>, Vec<i64>) {
+  let mut seed = 7u64;
+  let mut price = 1000f32;
+  let mut next = || {
+    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+    (seed >> 33) as f32 / (1u64 << 31) as f32
+  };
+  let (highs, lows) = (0..n)
+    .map(|_| {
+      price += next() * 10. - 4.9;
+      (price + next() * 5., price - next() * 5.)
+    })
+    .unzip();
+  (highs, lows, (0..n as i64).collect())
+}
+
+// walking out from every candle, as build_domain used to
+fn naive(highs: &[f32], lows: &[f32]) -> Vec<Domain> {
+  let n = highs.len();
+  let walk = |v: &[f32], i: usize, beats: fn(f32, f32) -> bool| {
+    let left = (0..i).rev().find(|&j| beats(v[j], v[i]));
+    let right = (i + 1..n).find(|&j| !beats(v[i], v[j]));
+    let l = left.map_or(i + 1, |j| i - j);
+    let r = right.map_or(n - i, |j| j - i);
+    (l.min(r) as i32, right.is_none())
+  };
+  (0..n)
+    .map(|i| {
+      let (top, top_open) = walk(highs, i, |a, b| a > b);
+      let (bottom, bottom_open) = walk(lows, i, |a, b| a < b);
+      Domain {
+        top,
+        bottom,
+        fuzzy: top_open || bottom_open,
+      }
+    })
+    .collect()
+}
+
+fn sum(domains: &[Domain]) -> i64 {
+  domains
+    .iter()
+    .map(|d| (d.top + d.bottom) as i64 + d.fuzzy as i64)
+    .sum()
+}
+
+fn bench_naive_domains(b: &mut Bencher) {
+  let (highs, lows, _) = walk(NAIVE_CANDLES);
+  b.iter(|| black_box(sum(&naive(&highs, &lows))));
+}
+
+fn bench_stack_domains_small(b: &mut Bencher) {
+  let (highs, lows, pos) = walk(NAIVE_CANDLES);
+  assert_eq!(domain::domains(&highs, &lows, &pos), naive(&highs, &lows));
+  b.iter(|| black_box(sum(&domain::domains(&highs, &lows, &pos))));
+}
+
+fn bench_stack_domains(b: &mut Bencher) {
+  let (highs, lows, pos) = walk(CANDLES);
+  b.iter(|| black_box(sum(&domain::domains(&highs, &lows, &pos))));
+}
+
+// appending a day of minutes to the fuzzy candles of the rest
+fn bench_tail_update(b: &mut Bencher) {
+  let (highs, lows, pos) = walk(CANDLES);
+  let split = CANDLES - 1440;
+  let before = domain::domains(&highs[..split], &lows[..split], &pos[..split]);
+  let tail: Vec<usize> = (0..split)
+    .filter(|&i| before[i].fuzzy)
+    .chain(split..CANDLES)
+    .collect();
+  let stored: Vec<Option<Domain>> =
+    tail.iter().map(|&i| before.get(i).copied()).collect();
+  let pick = |v: &[f32]| tail.iter().map(|&i| v[i]).collect::<Vec<_>>();
+  let (tail_highs, tail_lows) = (pick(&highs), pick(&lows));
+  let tail_pos: Vec<i64> = tail.iter().map(|&i| pos[i]).collect();
+  b.iter(|| {
+    black_box(
+      domain::update_tail(&tail_highs, &tail_lows, &tail_pos, 0, &stored).len(),
+    )
+  });
+}
+
+benchmark_group!(
+  benches,
+  bench_naive_domains,
+  bench_stack_domains_small,
+  bench_stack_domains,
+  bench_tail_update
+);
+benchmark_main!(benches);
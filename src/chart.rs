@@ -0,0 +1,218 @@
+mod candles;
+mod line_cross;
+mod trend_line;
+
+pub use crate::core::{strong_point::CandlePos, Candle};
+pub use candles::Candles;
+pub use line_cross::{generate_crosses, CrossType, LineCross};
+pub use trend_line::{generate_bounding_lines, rank, Line, TrendLine};
+
+use crate::config::TrendLinesConfig;
+use crate::prelude::*;
+use std::sync::Arc;
+
+/// The candles stored before `candles`, newest first, as many again as
+/// there are in `candles`.
+pub fn earlier_candles(
+  query: &Query,
+  candles: &[Candle],
+) -> Result<Vec<Candle>> {
+  let first = match candles.first() {
+    Some(c) => c,
+    None => return Ok(vec![]),
+  };
+  let mut query = Query::new(query.symbol(), query.interval());
+  query.set_all(vec![
+    End(first.open_time - query.step()),
+    Limit(candles.len()),
+    Order(DESC),
+  ]);
+  query.query_candles()
+}
+
+/// Bounding trend lines over `candles` that also held before the first
+/// root. `earlier` continues `candles` backwards, newest first.
+pub fn trend_lines_in(
+  candles: &Arc<Candles>,
+  earlier: &[Candle],
+) -> Vec<TrendLine> {
+  generate_bounding_lines(candles)
+    .into_iter()
+    .filter(|l| l.check_backwards(candles, earlier))
+    .collect()
+}
+
+/// Trend lines over candles loaded with `query`, looking back into the
+/// database for lines that start near the first candle. Only the strongest
+/// are kept, as configured in `CONFIG.trend_lines`.
+pub fn trend_lines(
+  query: &Query,
+  candles: Vec<Candle>,
+) -> Result<Vec<TrendLine>> {
+  trend_lines_with(query, candles, &CONFIG.trend_lines)
+}
+
+pub fn trend_lines_with(
+  query: &Query,
+  candles: Vec<Candle>,
+  config: &TrendLinesConfig,
+) -> Result<Vec<TrendLine>> {
+  let earlier = earlier_candles(query, &candles)?;
+  let lines = trend_lines_in(&Arc::new(Candles::new(candles)), &earlier);
+  Ok(rank(lines, config))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STEP: i64 = 60_000;
+
+  fn candle(i: i64, low: f32, high: f32) -> Candle {
+    Candle {
+      open_time: 1_600_000_000_000 + i * STEP,
+      close_time: 1_600_000_000_000 + (i + 1) * STEP - 1,
+      open: low,
+      close: high,
+      high,
+      low,
+      ..Default::default()
+    }
+  }
+
+  // a rising, oscillating series
+  fn waves(n: i64) -> Vec<Candle> {
+    (0..n)
+      .map(|i| {
+        let mid = 100. + i as f32 * 0.5 + 20. * (i as f32 / 6.).sin();
+        candle(i, mid - 2., mid + 2.)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn lines_bound_the_candles_between_their_roots() {
+    let candles = Arc::new(Candles::new(waves(200)));
+    let lines = generate_bounding_lines(&candles);
+    assert!(lines
+      .iter()
+      .any(|l| l.line.roots[0].candle_position == CandlePos::LOW));
+    assert!(lines
+      .iter()
+      .any(|l| l.line.roots[0].candle_position == CandlePos::HIGH));
+
+    for l in &lines {
+      let (r1, r2) = (&l.line.roots[0], &l.line.roots[1]);
+      assert!(r1.candle_index < r2.candle_index);
+      for c in &candles.candles[r1.candle_index..=r2.candle_index] {
+        let y = l.line.y_at_x(c.open_time);
+        match r1.candle_position {
+          CandlePos::LOW => assert!(c.low >= y - 0.01, "{:?}", l.line),
+          CandlePos::HIGH => assert!(c.high <= y + 0.01, "{:?}", l.line),
+        }
+      }
+    }
+
+    let empty = Arc::new(Candles::new(vec![]));
+    assert!(generate_bounding_lines(&empty).is_empty());
+  }
+
+  #[test]
+  fn check_backwards_rejects_a_pierced_line() {
+    // flat at 10..20, support line along the lows from candle 10 to 20
+    let candles: Vec<Candle> = (0..30).map(|i| candle(i, 10., 20.)).collect();
+    let candles = Arc::new(Candles::new(candles));
+    let line = TrendLine::new_from_candle_indexes(
+      candles.clone(),
+      10,
+      20,
+      CandlePos::LOW,
+    )
+    .unwrap();
+    assert!(line.check_backwards(&candles, &[]));
+
+    // a candle straddling the line before the first root
+    let mut pierced = candles.candles.clone();
+    pierced[4] = candle(4, 5., 15.);
+    let pierced = Candles::new(pierced);
+    assert!(!line.check_backwards(&pierced, &[]));
+
+    // the line spans 10 candles, so only 10 before candle 10 are checked
+    let earlier: Vec<Candle> = (1..=20).map(|i| candle(-i, 5., 15.)).collect();
+    assert!(line.check_backwards(&candles, &earlier));
+    let line = TrendLine::new_from_candle_indexes(
+      candles.clone(),
+      2,
+      12,
+      CandlePos::LOW,
+    )
+    .unwrap();
+    assert!(!line.check_backwards(&candles, &earlier));
+  }
+
+  #[test]
+  fn lines_are_scored_by_how_price_reacted() {
+    // flat, with support along the lows from candle 10 to 20
+    let flat = |i| Candle {
+      open: 12.,
+      close: 18.,
+      ..candle(i, 10., 20.)
+    };
+    let support = |candles: Vec<Candle>| {
+      TrendLine::new_from_candle_indexes(
+        Arc::new(Candles::new(candles)),
+        10,
+        20,
+        CandlePos::LOW,
+      )
+      .unwrap()
+    };
+
+    let mut bounced: Vec<Candle> = (0..40).map(flat).collect();
+    bounced[30] = Candle {
+      low: 8.,
+      ..flat(30)
+    };
+    let bounced = support(bounced);
+
+    let mut broken: Vec<Candle> = (0..40).map(flat).collect();
+    broken[30] = Candle {
+      open: 12.,
+      close: 5.,
+      ..candle(30, 4., 14.)
+    };
+    for (i, c) in broken.iter_mut().enumerate().skip(31) {
+      *c = Candle {
+        open: 5.,
+        close: 5.,
+        ..candle(i as i64, 2., 8.)
+      };
+    }
+    let broken = support(broken);
+
+    assert_eq!(bounced.angle, 0.);
+    assert_eq!(bounced.crosses.len(), 1);
+    assert_eq!(bounced.crosses[0].t, CrossType::Bounce);
+    assert_eq!(broken.crosses.len(), 1);
+    assert_eq!(broken.crosses[0].t, CrossType::Down);
+    assert!(bounced.strength > broken.strength);
+
+    let config = |min_strength, max_lines| TrendLinesConfig {
+      min_strength,
+      max_lines,
+    };
+    let lines = vec![broken.clone(), bounced.clone()];
+    let ranked = rank(lines.clone(), &config(0., 10));
+    assert_eq!(ranked, vec![bounced.clone(), broken.clone()]);
+    assert_eq!(rank(lines.clone(), &config(0., 1)), vec![bounced.clone()]);
+    let between = (bounced.strength + broken.strength) / 2.;
+    assert_eq!(rank(lines, &config(between, 10)), vec![bounced]);
+
+    // a rising line leans somewhere between flat and upright
+    let rising: Vec<Candle> = (0..40)
+      .map(|i| candle(i, i as f32, i as f32 + 1.))
+      .collect();
+    let rising = support(rising);
+    assert!(rising.angle > 0. && rising.angle < 90.);
+  }
+}
@@ -1,4 +1,4 @@
-use super::Candle;
+use crate::core::Candle;
 // use serde::ser::{Serialize, SerializeStruct, Serializer};
 use serde::Serialize;
 use std::ops::{Index, IndexMut};
@@ -14,6 +14,9 @@ pub struct Candles {
 
 impl Candles {
   pub fn new(candles: Vec<Candle>) -> Self {
+    if candles.is_empty() {
+      return Self::default();
+    }
     let ms_width = candles.last().unwrap().open_time - candles[0].open_time;
     let (mut high, mut low) = (candles[0].high, candles[0].low);
     for candle in candles.iter() {
@@ -35,7 +38,8 @@ impl Candles {
   }
   pub fn get(&self, index: usize) -> Option<&Candle> { self.candles.get(index) }
   pub fn len(&self) -> usize { self.candles.len() }
-  pub fn first(&self) -> &Candle { &self.candles[0] }
+  // time from the first candle's open to the last's
+  pub fn ms_width(&self) -> i64 { self.ms_width }
   pub fn last(&self) -> &Candle { &self.candles[&self.candles.len() - 1] }
 }
 
@@ -74,7 +78,7 @@ impl Iterator for CandleIterator {
       return None;
     }
     self.index += 1;
-    Some(self.candles[self.index - 1])
+    Some(self.candles[self.index - 1].clone())
   }
 }
 impl Index<usize> for Candles {
@@ -1,3 +0,0 @@
-use crate::chart::Candle;
-
-pub fn calculate_ema_200_4h(candles: &mut Vec<Candle>, i: usize) {}
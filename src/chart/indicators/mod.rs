@@ -1,5 +0,0 @@
-use crate::chart::Candle;
-
-pub mod ema;
-
-pub fn calculate_indicators(candles: &Vec<Candle>) {}
@@ -1,14 +1,15 @@
-use crate::chart::{Candles, Line, TrendLine};
+use super::{Candles, Line, TrendLine};
 use serde::Serialize;
 use std::sync::Arc;
 
 #[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum CrossType {
-  REJECT,
-  UP,
-  DOWN,
-  BOUNCE,
-  VOID,
+  Reject,
+  Up,
+  Down,
+  Bounce,
+  Void,
 }
 
 #[derive(Serialize, Debug, PartialEq, Clone)]
@@ -31,40 +32,24 @@ pub fn generate_crosses(
     Some(limit) => limit,
     None => candles.len() - 1,
   };
-  crawl(
-    candles,
-    trend_line,
-    trend_line.line.roots[1].candle_index + 1,
-    vec![],
-    limit,
-  )
-}
-
-fn crawl(
-  candles: &Arc<Candles>,
-  trend_line: &TrendLine,
-  index: usize,
-  mut crosses: Vec<LineCross>,
-  limit: usize,
-) -> Vec<LineCross> {
-  if index > limit {
-    return crosses;
-  }
-  match candles.get(index) {
-    Some(candle) => {
-      match trend_line.line.intersects(candle) {
-        true => {
-          // cross found, collect the cross
-          let cross = collect(candles, &trend_line.line, index, index + 1, 0);
-          let close_index = cross.close_index;
-          crosses.push(cross);
-          crawl(candles, trend_line, close_index + 1, crosses, limit)
-        }
-        false => crawl(candles, trend_line, index + 1, crosses, limit), // keep crawling
+  let mut crosses = vec![];
+  let mut index = trend_line.line.roots[1].candle_index + 1;
+  while index <= limit {
+    let candle = match candles.get(index) {
+      Some(candle) => candle,
+      None => break,
+    };
+    match trend_line.line.intersects(candle) {
+      true => {
+        // cross found, collect the cross
+        let cross = collect(candles, &trend_line.line, index, index + 1, 0);
+        index = cross.close_index + 1;
+        crosses.push(cross);
       }
+      false => index += 1, // keep crawling
     }
-    None => crosses, // done
   }
+  crosses
 }
 
 const GAP: usize = 1;
@@ -76,10 +61,10 @@ pub fn collect(
   gap: usize,
 ) -> LineCross {
   let create = || -> LineCross {
-    let start_candle = candles[open_index];
-    let end_candle = candles[close_index - 1];
-    let open = start_candle.open - line.y_at_x(start_candle.open_time as f32);
-    let close = end_candle.close - line.y_at_x(end_candle.close_time as f32);
+    let start_candle = &candles[open_index];
+    let end_candle = &candles[close_index - 1];
+    let open = start_candle.open - line.y_at_x(start_candle.open_time);
+    let close = end_candle.close - line.y_at_x(end_candle.close_time);
     LineCross {
       width: (close_index - open_index) as i32,
       height: 0f32,
@@ -87,18 +72,18 @@ pub fn collect(
       close_index,
       p1: (
         start_candle.open_time as f32,
-        line.y_at_x(start_candle.open_time as f32),
+        line.y_at_x(start_candle.open_time),
       ),
       p2: (
         end_candle.open_time as f32,
-        line.y_at_x(end_candle.open_time as f32),
+        line.y_at_x(end_candle.open_time),
       ),
       t: match (open, close) {
-        (o, c) if o <= 0f32 && c <= 0f32 => CrossType::REJECT,
-        (o, c) if o <= 0f32 && c > 0f32 => CrossType::UP,
-        (o, c) if o > 0f32 && c <= 0f32 => CrossType::DOWN,
-        (o, c) if o > 0f32 && c > 0f32 => CrossType::BOUNCE,
-        (_, _) => CrossType::VOID,
+        (o, c) if o <= 0f32 && c <= 0f32 => CrossType::Reject,
+        (o, c) if o <= 0f32 && c > 0f32 => CrossType::Up,
+        (o, c) if o > 0f32 && c <= 0f32 => CrossType::Down,
+        (o, c) if o > 0f32 && c > 0f32 => CrossType::Bounce,
+        (_, _) => CrossType::Void,
       },
     }
   };
@@ -1,18 +1,14 @@
-use crate::chart::{Candle, Candles, Chart, LineCross};
-
+use super::{
+  generate_crosses, Candle, CandlePos, Candles, CrossType, LineCross,
+};
+use crate::config::TrendLinesConfig;
 use rayon::prelude::*;
 use serde::Serialize;
 use std::sync::Arc;
 use CandlePos::*;
 
-use super::CandlePos;
-
-#[derive(Default, Clone, Debug, PartialEq, Serialize)]
-pub struct TrendLineMeta {
-  pub before_check: u64,
-  pub after_check: u64,
-  pub min_width: u64,
-}
+// a candle within this share of the chart's height of the line touches it
+const TOUCH: f32 = 0.01;
 
 #[derive(Serialize, Debug, PartialEq, Clone)]
 pub struct TrendLine {
@@ -46,67 +42,97 @@ impl TrendLine {
     i2: usize,
     pos: CandlePos,
   ) -> Option<TrendLine> {
-    let (c1, c2) = match (candles.get(i1), candles.get(i2)) {
-      (Some(c1), Some(c2)) => (c1, c2),
+    let c1 = match (candles.get(i1), candles.get(i2)) {
+      (Some(c1), Some(_)) => c1,
       _ => return None,
     };
 
-    let ((x1, y1), (x2, y2)) = match pos {
-      HIGH => ((c1.open_time, c1.high), (c2.open_time, c2.high)),
-      LOW => ((c1.open_time, c1.low), (c2.open_time, c2.low)),
+    let (x1, y1) = match pos {
+      HIGH => (c1.open_time, c1.high),
+      LOW => (c1.open_time, c1.low),
     };
     let line = Line::new(candles.clone(), i1, pos, i2, pos);
 
     let max_x = candles.last().open_time;
-    let max_y = line.y_at_x(max_x as f32);
+    let max_y = line.y_at_x(max_x);
 
-    let trend_line = TrendLine {
+    let mut trend_line = TrendLine {
+      angle: line.angle(&candles),
       line,
       crosses: vec![],
       strength: 0f32,
-      angle: 0f32,
       p1: (x1 as f32, y1),
       p2: (max_x as f32, max_y),
     };
-
-    // TODO: add crosses
+    trend_line.crosses = generate_crosses(&candles, &trend_line, None);
+    trend_line.strength = trend_line.score(&candles);
 
     Some(trend_line)
   }
 
-  pub fn check_backwards(
-    &self,
-    chart: Arc<Chart>,
-    extra_candles_reverse: Arc<Vec<Candle>>,
-  ) -> bool {
-    let mut index = self.line.roots[0].candle_index;
-    let (c1, c2) = (
-      chart.candles[self.line.roots[0].candle_index],
-      chart.candles[self.line.roots[1].candle_index],
+  /// Candles between the roots that come within `TOUCH` of the line,
+  /// roots included.
+  pub fn touches(&self, candles: &Candles) -> usize {
+    let (i1, i2) = (
+      self.line.roots[0].candle_index,
+      self.line.roots[1].candle_index,
     );
-    let min_open_time = c1.open_time - (c2.open_time - c1.open_time);
+    let tolerance = candles.height * TOUCH;
+    candles.candles[i1..=i2]
+      .iter()
+      .filter(|c| self.line.vertical_distance(c, false).abs() <= tolerance)
+      .count()
+  }
 
-    while let Some(c) = chart.candles.get(index) {
-      index -= 1;
-      if self.line.intersects(c) {
-        return false;
-      }
-      if index == 0 || c.open_time <= min_open_time {
-        break;
-      }
-    }
-    index = 0;
-    while let Some(c) = extra_candles_reverse.get(index) {
-      if c.open_time <= min_open_time {
-        break;
-      }
-      if self.line.intersects(c) {
-        return false;
-      }
-      index += 1;
+  /// Whether a cross took price through the line: down through support or
+  /// up through resistance.
+  pub fn breaks(&self, cross: &LineCross) -> bool {
+    matches!(
+      (self.line.roots[0].candle_position, &cross.t),
+      (LOW, CrossType::Down) | (HIGH, CrossType::Up)
+    )
+  }
+
+  /// How far a line can be trusted. Each touch between the roots scores 1
+  /// and each cross scores by how price reacted to the line. The span
+  /// between the roots and how long the line held after them score up to
+  /// 2 each, as shares of the chart.
+  pub fn score(&self, candles: &Candles) -> f32 {
+    let len = candles.len() as f32;
+    let pos = self.line.roots[0].candle_position;
+    let held = match self.crosses.iter().find(|c| self.breaks(c)) {
+      Some(c) => c.open_index,
+      None => candles.len() - 1,
+    } - self.line.roots[1].candle_index;
+    let span = self.line.width() as f32 / len;
+
+    let mut broken = false;
+    let mut reactions = 0.;
+    for cross in &self.crosses {
+      reactions += reaction(pos, &cross.t, broken);
+      broken |= self.breaks(cross);
     }
 
-    true
+    self.touches(candles) as f32 + reactions + 2. * (span + held as f32 / len)
+  }
+
+  /// Whether the line held before its first root, for as long again as it
+  /// spans. `extra_candles_reverse` continues `candles` backwards, newest
+  /// first, for lines that start near the beginning of `candles`.
+  pub fn check_backwards(
+    &self,
+    candles: &Candles,
+    extra_candles_reverse: &[Candle],
+  ) -> bool {
+    let (r1, r2) = (&self.line.roots[0], &self.line.roots[1]);
+    let min_open_time = r1.x - (r2.x - r1.x);
+
+    candles.candles[..r1.candle_index]
+      .iter()
+      .rev()
+      .chain(extra_candles_reverse)
+      .take_while(|c| c.open_time >= min_open_time)
+      .all(|c| !self.line.intersects(c))
   }
 }
 
@@ -179,7 +205,19 @@ impl Line {
   pub fn recalculate(&mut self) {
     self.slope = (self.roots[1].y - self.roots[0].y)
       / (self.roots[1].x - self.roots[0].x) as f32;
-    self.b = self.roots[0].y - self.slope * self.roots[0].x as f32;
+    self.b = (self.roots[0].y as f64
+      - self.slope as f64 * self.roots[0].x as f64) as f32;
+  }
+
+  /// Angle in degrees as the line appears on a chart of `candles` drawn
+  /// as tall as it is wide.
+  pub fn angle(&self, candles: &Candles) -> f32 {
+    match candles.height > 0. {
+      true => (self.slope * candles.ms_width() as f32 / candles.height)
+        .atan()
+        .to_degrees(),
+      false => 0.,
+    }
   }
 
   pub fn width(&self) -> usize {
@@ -189,7 +227,7 @@ impl Line {
   // distance from line to candle
   // if line is above, result is negative. if line is below, result is positive.
   pub fn vertical_distance(&self, candle: &Candle, body_only: bool) -> f32 {
-    let y = self.y_at_x(candle.open_time as f32);
+    let y = self.y_at_x(candle.open_time);
     let (high, low) = match body_only {
       true => (candle.open.max(candle.close), candle.open.min(candle.close)),
       false => (candle.high, candle.low),
@@ -202,18 +240,25 @@ impl Line {
   }
 
   pub fn intersects(&self, candle: &Candle) -> bool {
-    let y = self.y_at_x(candle.open_time as f32);
+    let y = self.y_at_x(candle.open_time);
     y > candle.low && y < candle.high
   }
 
-  pub fn y_at_x(&self, x: f32) -> f32 { (self.slope * x) + self.b }
+  // measured from the first root, since a timestamp is too big for an f32
+  // to keep the precision of `b`
+  pub fn y_at_x(&self, x: i64) -> f32 {
+    self.roots[0].y + self.slope * (x - self.roots[0].x) as f32
+  }
 }
 
 pub fn generate_bounding_lines(candles: &Arc<Candles>) -> Vec<TrendLine> {
+  if candles.len() < 2 {
+    return vec![];
+  }
   let mut all_lines = Vec::<Line>::new();
   let mut index = 0;
   while index < candles.len() {
-    let mut lines = crawl(&candles, index, LOW);
+    let mut lines = crawl(candles, index, LOW);
     index = match lines.len() {
       0 => index + 1,
       _ => lines.last().unwrap().roots[1].candle_index,
@@ -223,7 +268,7 @@ pub fn generate_bounding_lines(candles: &Arc<Candles>) -> Vec<TrendLine> {
 
   index = 0;
   while index < candles.len() {
-    let mut lines = crawl(&candles, index, HIGH);
+    let mut lines = crawl(candles, index, HIGH);
     index = match lines.len() {
       0 => index + 1,
       _ => lines[0].roots[1].candle_index,
@@ -241,26 +286,44 @@ pub fn generate_bounding_lines(candles: &Arc<Candles>) -> Vec<TrendLine> {
         l.roots[0].candle_position,
       )
     })
-    .filter_map(|l| l)
+    .flatten()
     .collect();
 
   res
 }
 
-enum Dir {
-  FRWD,
-  BACK,
+// what a cross says about a line. Holding scores, breaking costs, and once
+// broken a line that flips to the other side still counts for a little.
+fn reaction(pos: CandlePos, t: &CrossType, broken: bool) -> f32 {
+  use CrossType::*;
+  match (pos, t, broken) {
+    (_, Void, _) => 0.,
+    (LOW, Bounce, false) | (HIGH, Reject, false) => 1.,
+    // opened on the far side but closed back where the line holds
+    (LOW, Up, false) | (HIGH, Down, false) => 0.5,
+    (LOW, Down, _) | (HIGH, Up, _) => -1.,
+    // the line flipped from support to resistance or back
+    (LOW, Reject, true) | (HIGH, Bounce, true) => 0.5,
+    _ => 0.,
+  }
+}
+
+/// The strongest `config.max_lines` lines scoring at least
+/// `config.min_strength`, strongest first.
+pub fn rank(
+  mut lines: Vec<TrendLine>,
+  config: &TrendLinesConfig,
+) -> Vec<TrendLine> {
+  lines.retain(|l| l.strength >= config.min_strength);
+  lines.sort_by(|a, b| b.strength.total_cmp(&a.strength));
+  lines.truncate(config.max_lines);
+  lines
 }
 
 const MIN_LIFT: f32 = 0.03;
 
-fn crawl(
-  candles: &Arc<Candles>,
-  mut index: usize,
-  pos: CandlePos,
-) -> Vec<Line> {
+fn crawl(candles: &Arc<Candles>, index: usize, pos: CandlePos) -> Vec<Line> {
   let min_width = 4usize;
-  let before_check = 4usize;
   let after_check = 4usize;
 
   let mut lines = vec![];
@@ -271,13 +334,12 @@ fn crawl(
   let min_lift = candles.height * MIN_LIFT;
 
   let mut lift_sustain = 0;
-  let (c1, c2) = (&candles[index], &candles[index + 1]);
   let mut line = Line::new(candles.clone(), index, pos, index + 1, pos);
   let mut current_line_added = false;
 
   for i in (index + 1)..(candles.len() - 1) {
     let candle = &candles[i];
-    let y = line.y_at_x(candle.open_time as f32);
+    let y = line.y_at_x(candle.open_time);
     let pos = line.roots[1].candle_position;
     match (y, candle.high, candle.low) {
       // is the line on the wrong side of, or in the candle?
@@ -306,7 +368,6 @@ fn crawl(
         lift_sustain = 0;
       }
     }
-    index += 1;
   }
   // if line.width() >= 10 && line.roots[1].candle_index < chart.candles.len() - 5 {
   // lines.push(line);
@@ -318,7 +379,6 @@ fn add(candles: &Arc<Candles>, lines: &mut Vec<Line>, line: &mut Line) -> bool {
   use std::cmp::max;
 
   let before_check = 4usize;
-  let after_check = 4usize;
 
   let i1 = line.roots[0].candle_index;
   let pos = line.roots[0].candle_position;
@@ -333,7 +393,7 @@ fn add(candles: &Arc<Candles>, lines: &mut Vec<Line>, line: &mut Line) -> bool {
   // check behind
   for i in (max(i1, before_check) - before_check)..i1 {
     let c = &candles[i];
-    let y = line.y_at_x(c.open_time as f32);
+    let y = line.y_at_x(c.open_time);
     let pos = line.roots[0].candle_position;
 
     match (pos, y, c.high, c.low) {
@@ -1,4 +1,9 @@
+use crate::core::strong_point::{FuzzyPolicy, LimitBy};
+use crate::core::zone::Tolerance;
+use crate::normalized::scale::{Method, Scope};
+use crate::normalized::{Labeller, StratSpec};
 use crate::prelude::*;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
@@ -6,7 +11,9 @@ const CONF_FILE: &str = "config.json";
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+  // share of the price paid per fill
   pub exchange_fee: f32,
+  // percent of the price lost per fill
   pub transaction_slippage: f32,
   pub query_limit: usize,
   // Percent profit expected to vote to take the trade
@@ -18,10 +25,66 @@ pub struct Config {
   pub export: ExportConfig,
   pub history_start: usize,
   pub history_end: usize,
+  #[serde(default)]
+  pub log: LogConfig,
+  #[serde(default)]
+  pub web: WebConfig,
+  #[serde(default)]
+  pub trend_lines: TrendLinesConfig,
+  #[serde(default)]
+  pub zones: ZonesConfig,
+  #[serde(default)]
+  pub confluence: ConfluenceConfig,
+  // export layouts by name, see `normalized::spec`
+  #[serde(default = "default_strats")]
+  pub strats: BTreeMap<String, StratSpec>,
+  // the strat commands use unless given one
+  #[serde(default = "default_strat")]
+  pub default_strat: String,
+  // what exports are labelled with unless told otherwise
+  #[serde(default = "default_label")]
+  pub label: Labeller,
+  #[serde(default)]
+  pub walk_forward: WalkForwardConfig,
+  #[serde(default)]
+  pub normalization: NormalizationConfig,
+  #[serde(default)]
+  pub train: TrainConfig,
 }
-#[derive(Serialize, Deserialize)]
+
+fn default_label() -> Labeller { Labeller::Horizon("8h".into()) }
+
+fn default_strat() -> String { "strat1".into() }
+
+fn default_strats() -> BTreeMap<String, StratSpec> {
+  BTreeMap::from([(
+    default_strat(),
+    "52w:1w,6w:1d,1w:4h,4d:1h,2d:15m;4h:200:true,1d:50:false"
+      .parse()
+      .unwrap(),
+  )])
+}
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
 pub struct StrongPointsConfig {
+  pub limit_by: LimitBy,
   pub min_domain: i32,
+  pub count: usize,
+  // share of the candles, 0.01 picks one point per hundred candles
+  pub percent: f32,
+  pub fuzzy: FuzzyPolicy,
+}
+
+impl Default for StrongPointsConfig {
+  fn default() -> Self {
+    Self {
+      limit_by: LimitBy::MinDomain,
+      min_domain: 4,
+      count: 200,
+      percent: 0.01,
+      fuzzy: FuzzyPolicy::Keep,
+    }
+  }
 }
 #[derive(Serialize, Deserialize)]
 pub struct ExportConfig {
@@ -30,6 +93,173 @@ pub struct ExportConfig {
   pub predict_candles_forward: usize,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct LogConfig {
+  pub dir: String,
+  // the log file is rotated once it grows past this
+  pub max_bytes: u64,
+  // number of rotated files to keep
+  pub keep: usize,
+}
+
+impl Default for LogConfig {
+  fn default() -> Self {
+    Self {
+      dir: "logs".into(),
+      max_bytes: 10_000_000,
+      keep: 5,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebConfig {
+  pub bind: String,
+  // output of `yarn build` in web/
+  pub static_dir: String,
+  // bearer token for running commands over http; unset disables it
+  pub token: Option<String>,
+}
+
+impl Default for WebConfig {
+  fn default() -> Self {
+    Self {
+      bind: "127.0.0.1:8080".into(),
+      static_dir: "web/build".into(),
+      token: None,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TrendLinesConfig {
+  // lines scoring below this are dropped
+  pub min_strength: f32,
+  // strongest lines kept per chart
+  pub max_lines: usize,
+}
+
+impl Default for TrendLinesConfig {
+  fn default() -> Self {
+    Self {
+      min_strength: 4.,
+      max_lines: 12,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ZonesConfig {
+  // how close strong points must be to share a zone
+  pub tolerance: Tolerance,
+  // zones with fewer strong points are dropped
+  pub min_touches: usize,
+}
+
+impl Default for ZonesConfig {
+  fn default() -> Self {
+    Self {
+      tolerance: Tolerance::Atr(0.5),
+      min_touches: 2,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WalkForwardConfig {
+  pub folds: usize,
+  // time between exported cursors
+  pub stride: String,
+  // extra gap after the label horizon between training and testing
+  pub embargo: String,
+}
+
+impl Default for WalkForwardConfig {
+  fn default() -> Self {
+    Self {
+      folds: 4,
+      stride: "1d".into(),
+      embargo: "1d".into(),
+    }
+  }
+}
+
+/// How exported features are scaled, see `normalized::scale`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct NormalizationConfig {
+  pub method: Method,
+  pub scope: Scope,
+}
+
+impl Default for NormalizationConfig {
+  fn default() -> Self {
+    Self {
+      method: Method::MinMax,
+      scope: Scope::Window,
+    }
+  }
+}
+
+/// How `model::train` fits models.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainConfig {
+  // passes over the training samples by linear and logistic models
+  pub epochs: usize,
+  // step size, relative to the samples' mean squared length
+  pub learning_rate: f32,
+  // weight decay
+  pub l2: f32,
+  // boosting rounds
+  pub trees: usize,
+  pub depth: usize,
+  // share of each tree added to the model
+  pub shrinkage: f32,
+  // samples each leaf needs
+  pub min_leaf: usize,
+  // values each input is split into when looking for thresholds
+  pub bins: usize,
+}
+
+impl Default for TrainConfig {
+  fn default() -> Self {
+    Self {
+      epochs: 300,
+      learning_rate: 0.5,
+      l2: 1e-4,
+      trees: 100,
+      depth: 3,
+      shrinkage: 0.1,
+      min_leaf: 20,
+      bins: 32,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConfluenceConfig {
+  // higher timeframes projected onto the chart
+  pub intervals: Vec<String>,
+  // how close points must be to share a level
+  pub tolerance: Tolerance,
+  // candles of each interval searched for strong points
+  pub lookback: usize,
+  // levels seen by fewer intervals are dropped
+  pub min_intervals: usize,
+}
+
+impl Default for ConfluenceConfig {
+  fn default() -> Self {
+    Self {
+      intervals: vec!["1w".into(), "1d".into(), "4h".into()],
+      tolerance: Tolerance::Fraction(0.005),
+      lookback: 500,
+      min_intervals: 2,
+    }
+  }
+}
+
 impl ::std::default::Default for Config {
   fn default() -> Self {
     Self {
@@ -44,9 +274,20 @@ impl ::std::default::Default for Config {
         strong_point_length: 100,
         predict_candles_forward: 32,
       },
-      strong_points: StrongPointsConfig { min_domain: 4 },
+      strong_points: StrongPointsConfig::default(),
       history_start: 365 * 4, // 365 * 4
       history_end: 0,
+      log: LogConfig::default(),
+      web: WebConfig::default(),
+      trend_lines: TrendLinesConfig::default(),
+      zones: ZonesConfig::default(),
+      confluence: ConfluenceConfig::default(),
+      strats: default_strats(),
+      default_strat: default_strat(),
+      label: default_label(),
+      walk_forward: WalkForwardConfig::default(),
+      normalization: NormalizationConfig::default(),
+      train: TrainConfig::default(),
     }
   }
 }
@@ -61,6 +302,16 @@ impl Config {
     .expect("Could not parse config file.");
     config
   }
+  pub fn strat(&self, name: &str) -> Result<&StratSpec> {
+    match self.strats.get(name) {
+      Some(strat) => Ok(strat),
+      None => bail!(
+        "No strat named '{}', expected one of {}",
+        name,
+        self.strats.keys().cloned().collect::<Vec<_>>().join(", ")
+      ),
+    }
+  }
   pub fn export_detail_len(&self) -> usize {
     self.export.detail_view_len
   }
@@ -1,8 +1,12 @@
 pub mod candle;
 pub use candle::*;
+pub mod confluence;
+pub mod domain;
 pub mod strong_point;
 pub use strong_point::StrongPoint;
 pub mod moving_average;
 pub use moving_average::*;
 pub mod meta;
 pub use meta::*;
+pub mod zone;
+pub use zone::Zone;
@@ -1,3 +1,4 @@
+use super::domain::{self, Domain};
 use crate::prelude::*;
 use anyhow::Result;
 use postgres::Row;
@@ -121,95 +122,145 @@ impl From<(usize, &Row)> for Candle {
   }
 }
 
-pub fn build_domain(query: &mut Query) -> Result<()> {
+/// Recalculate the top and bottom domains of every stored candle.
+pub fn build_domain(query: &Query) -> Result<()> {
   log!(
     "Calculating domain for {}, {}...",
     query.symbol(),
     query.interval()
   );
-  let mut candles = query.query_candles()?;
-  // let pb = data::progress_bar(candles.len() as i64);
+  let candles = Query::new(query.symbol(), query.interval()).query_candles()?;
+  let step = query.step();
+  let (highs, lows): (Vec<f32>, Vec<f32>) =
+    candles.iter().map(|c| (c.high, c.low)).unzip();
+  let pos: Vec<i64> = candles.iter().map(|c| c.open_time / step).collect();
 
-  // pb.finish_and_clear();
-  log!("Saving..");
+  let domains = domain::domains(&highs, &lows, &pos);
+  save_domains(
+    query,
+    candles.iter().map(|c| c.open_time).zip(domains).collect(),
+  )
+}
+
+/// Bring domains up to date after candles were appended, loading only the
+/// fuzzy candles and the new ones. Candles added before ones that already
+/// have domains rebuild them all.
+pub fn update_domain(query: &Query) -> Result<()> {
+  let (symbol, interval, step) =
+    (query.symbol(), query.interval(), query.step());
+  let rows = con().query(
+    "SELECT DISTINCT ON (open_time) open_time, high, low, top_domain, bottom_domain, fuzzy_domain FROM candles WHERE symbol = $1 AND interval = $2 AND (fuzzy_domain OR top_domain = 0) ORDER BY open_time",
+    &[&symbol, &interval],
+  )?;
 
-  for i in 0..candles.len() {
-    set_domain(&mut candles, i, query.symbol(), query.interval());
+  let mut open_times = vec![];
+  let (mut highs, mut lows, mut pos, mut stored) =
+    (vec![], vec![], vec![], vec![]);
+  for row in &rows {
+    let open_time: i64 = row.get(0);
+    open_times.push(open_time);
+    highs.push(row.get(1));
+    lows.push(row.get(2));
+    pos.push(open_time / step);
+    stored.push(match row.get::<_, i32>(3) {
+      0 => None,
+      top => Some(Domain {
+        top,
+        bottom: row.get(4),
+        fuzzy: row.get(5),
+      }),
+    });
   }
-  let candle_rows: Vec<String> = candles
-    .iter()
-    .map(|c| c.to_string(query.symbol(), query.interval()))
-    .collect();
-  query.copy_in_candles(candle_rows.join("")).unwrap();
 
-  Ok(())
+  // the last calculated candle is always fuzzy, so it was loaded
+  let first_new = stored.iter().position(|d| d.is_none());
+  let last_old = stored.iter().rposition(|d| d.is_some());
+  match (first_new, last_old) {
+    (None, _) => return Ok(()),
+    (Some(new), Some(old)) if new < old => return build_domain(query),
+    _ => {}
+  }
+
+  let first: i64 = con()
+    .query_one(
+      "SELECT MIN(open_time) FROM candles WHERE symbol = $1 AND interval = $2",
+      &[&symbol, &interval],
+    )?
+    .get(0);
+  let changed = domain::update_tail(&highs, &lows, &pos, first / step, &stored);
+  save_domains(
+    query,
+    changed
+      .into_iter()
+      .map(|(i, d)| (open_times[i], d))
+      .collect(),
+  )
 }
 
-fn set_domain(
-  candles: &mut [Candle],
-  i: usize,
-  symbol: &str,
-  interval: &str,
-) -> String {
-  use std::cmp::{max, min};
+fn save_domains(query: &Query, domains: Vec<(i64, Domain)>) -> Result<()> {
+  let open_times: Vec<i64> = domains.iter().map(|(t, _)| *t).collect();
+  let tops: Vec<i32> = domains.iter().map(|(_, d)| d.top).collect();
+  let bottoms: Vec<i32> = domains.iter().map(|(_, d)| d.bottom).collect();
+  let fuzzy: Vec<bool> = domains.iter().map(|(_, d)| d.fuzzy).collect();
 
-  let candle = &candles[i];
-  // 0: top-left, 1: top-right, 2: bottom-right, 3: bottom-left
-  let mut domains = vec![0usize; 4];
-  let mut fuzzy_domain = false;
+  con().execute(
+    "UPDATE candles AS c SET top_domain = d.top, bottom_domain = d.bottom, fuzzy_domain = d.fuzzy FROM UNNEST($3::BIGINT[], $4::INT[], $5::INT[], $6::BOOLEAN[]) AS d(open_time, top, bottom, fuzzy) WHERE c.symbol = $1 AND c.interval = $2 AND c.open_time = d.open_time",
+    &[&query.symbol(), &query.interval(), &open_times, &tops, &bottoms, &fuzzy],
+  )?;
 
-  let mut index = i;
+  if !domains.is_empty() {
+    web_server::push::publish(web_server::push::Event::StrongPoints {
+      symbol: query.symbol().to_owned(),
+      interval: query.interval().to_owned(),
+    });
+  }
+  Ok(())
+}
 
-  // look left
-  while index > 0 && (domains[0] == 0 || domains[3] == 0) {
-    index -= 1;
-    let c = &candles[index];
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-    if domains[0] == 0 && c.high > candle.high {
-      domains[0] = i - index;
-    }
-    if domains[3] == 0 && c.low < candle.low {
-      domains[3] = i - index;
+  #[test]
+  fn domains_are_stored_and_updated() -> Result<()> {
+    let mut query = Query::new("DOMAINTEST", "1h");
+    let step = query.step();
+    let start = "10d".ago().round(step);
+    let insert = |query: &mut Query, i: i64, high: f32| {
+      query.insert_candle(&Candle {
+        open_time: start + step * i,
+        close_time: start + step * (i + 1) - 1,
+        high,
+        low: high - 1.,
+        ..Default::default()
+      })
+    };
+    for (i, high) in [5., 3., 4., 2., 6.].into_iter().enumerate() {
+      insert(&mut query, i as i64, high)?;
     }
-  }
 
-  index = i;
-  let candles_len = candles.len() - 1;
+    let domains = |query: &Query| -> Result<Vec<(i32, i32, bool)>> {
+      Ok(
+        query
+          .query_candles()?
+          .iter()
+          .map(|c| (c.top_domain, c.bottom_domain, c.fuzzy_domain))
+          .collect(),
+      )
+    };
+    update_domain(&query)?;
+    assert_eq!(domains(&query)?[2], (2, 1, false));
+    assert_eq!(domains(&query)?[4], (1, 1, true));
 
-  // look right
-  while index < candles_len && (domains[1] == 0 || domains[2] == 0) {
-    index += 1;
-    let c = &candles[index];
+    // appending only touches the tail, and agrees with a rebuild
+    insert(&mut query, 5, 7.)?;
+    insert(&mut query, 6, 1.)?;
+    update_domain(&query)?;
+    let updated = domains(&query)?;
+    assert_eq!(updated[4], (1, 1, false));
+    build_domain(&query)?;
+    assert_eq!(domains(&query)?, updated);
 
-    if domains[1] == 0 && c.high >= candle.high {
-      domains[1] = index - i;
-    }
-    if domains[2] == 0 && c.low <= candle.low {
-      domains[2] = index - i;
-    }
+    Ok(())
   }
-
-  let mut maybe_fuzzy = |a, b| {
-    let mut domain = max(a, b);
-    if domain == 0 {
-      // all time high / low
-      domain = min(max(i, 2) - 1, candles_len - i);
-    }
-    let distance_from_edge = min(max(i, 2) - 1, candles_len - i);
-    if domain >= distance_from_edge {
-      fuzzy_domain = true;
-    }
-    domain
-  };
-
-  candles[i].top_domain = match (domains[2], domains[3]) {
-    (0, _) | (_, 0) => maybe_fuzzy(domains[2], domains[3]),
-    (a, b) => min(a, b),
-  } as i32;
-  candles[i].bottom_domain = match (domains[0], domains[1]) {
-    (0, _) | (_, 0) => maybe_fuzzy(domains[0], domains[1]),
-    (a, b) => min(a, b),
-  } as i32;
-  candles[i].fuzzy_domain = fuzzy_domain;
-  candles[i].to_string(symbol, interval)
 }
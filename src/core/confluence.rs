@@ -0,0 +1,298 @@
+use crate::config::ConfluenceConfig;
+use crate::prelude::*;
+use strong_point::{generate_points, CandlePos};
+use zone::Tolerance;
+
+// distance reported for a side with no level, as a share of the price
+const NO_LEVEL: f32 = 1.;
+
+/// A strong point from one interval, to be placed on another.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Projected {
+  pub interval: String,
+  pub position: CandlePos,
+  pub x: i64,
+  pub y: f32,
+  // in milliseconds, so domains compare across intervals
+  pub domain_ms: i64,
+}
+
+/// A price where strong points from several intervals agree.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Level {
+  pub low: f32,
+  pub high: f32,
+  pub intervals: Vec<String>,
+  pub points: usize,
+  pub score: f32,
+}
+
+/// `candles` as they would have been stored at `at`. A domain reaching past
+/// `at` was only as large as the candles closed since. A candle is fuzzy
+/// while nothing closed after it reaches its high or low, whichever side
+/// its domain ends on. Candles that hadn't closed are left out.
+pub fn as_of(candles: &[Candle], step: i64, at: i64) -> Vec<Candle> {
+  // the highest high and lowest low closed after each candle
+  let mut after = (f32::NEG_INFINITY, f32::INFINITY);
+  let mut known: Vec<Candle> = candles
+    .iter()
+    .filter(|c| c.open_time + step <= at)
+    .rev()
+    .map(|c| {
+      let open = after.0 < c.high || after.1 > c.low;
+      after = (after.0.max(c.high), after.1.min(c.low));
+      let known = ((at - c.open_time) / step) as i32;
+      Candle {
+        top_domain: c.top_domain.min(known),
+        bottom_domain: c.bottom_domain.min(known),
+        fuzzy_domain: c.fuzzy_domain
+          || open
+          || c.top_domain > known
+          || c.bottom_domain > known,
+        ..c.clone()
+      }
+    })
+    .collect();
+  known.reverse();
+  known
+}
+
+/// The last `limit` candles of an interval opened by a time, oldest first.
+pub trait Source: Fn(&str, i64, usize) -> Result<Vec<Candle>> {}
+impl<F: Fn(&str, i64, usize) -> Result<Vec<Candle>>> Source for F {}
+
+/// Reads `Source` candles from the database.
+pub fn stored(symbol: &str) -> impl Source + '_ {
+  move |interval, end, limit| {
+    let mut query = Query::new(symbol, interval);
+    query.set_all(vec![End(end), Limit(limit), Order(DESC)]);
+    let mut candles = query.query_candles()?;
+    candles.reverse();
+    Ok(candles)
+  }
+}
+
+/// Strong points of each interval as they stood at `at`, from the last
+/// `lookback` closed candles of each.
+pub fn project(
+  source: &impl Source,
+  intervals: &[String],
+  at: i64,
+  lookback: usize,
+) -> Result<Vec<Projected>> {
+  let mut projected = vec![];
+  for interval in intervals {
+    let step = interval.ms();
+    let candles = as_of(&source(interval, at - step, lookback)?, step, at);
+    projected.extend(generate_points(&candles).into_iter().map(|p| {
+      Projected {
+        interval: interval.to_owned(),
+        position: p.position,
+        x: p.x,
+        y: p.y,
+        domain_ms: p.domain as i64 * step,
+      }
+    }));
+  }
+  Ok(projected)
+}
+
+/// Cluster projected points into levels, the way zones cluster strong
+/// points, with `candles` of the chart's interval for the tolerance. Each
+/// interval adds the log of its strongest point's domain, counted in
+/// `candles`' steps, to the score, so a level only scores high
+/// when several timeframes agree on it. Levels seen by fewer than
+/// `config.min_intervals` intervals are dropped.
+pub fn levels(
+  candles: &[Candle],
+  step: i64,
+  projected: &[Projected],
+  config: &ConfluenceConfig,
+) -> Vec<Level> {
+  let atr = zone::atr(candles, zone::ATR_PERIOD);
+  let tolerance = |price: f32| match config.tolerance {
+    Tolerance::Fraction(f) => price * f,
+    Tolerance::Atr(m) => atr * m,
+  };
+
+  let mut points: Vec<&Projected> = projected.iter().collect();
+  points.sort_by(|a, b| a.y.total_cmp(&b.y));
+
+  let mut clusters: Vec<Vec<&Projected>> = vec![];
+  for p in points {
+    match clusters.last_mut() {
+      Some(c) if p.y - c[0].y <= tolerance(c[0].y) => c.push(p),
+      _ => clusters.push(vec![p]),
+    }
+  }
+
+  clusters
+    .into_iter()
+    .filter_map(|c| {
+      let mut strongest: Vec<(&str, i64)> = vec![];
+      for p in &c {
+        match strongest.iter_mut().find(|(i, _)| *i == p.interval) {
+          Some((_, domain)) => *domain = (*domain).max(p.domain_ms),
+          None => strongest.push((&p.interval, p.domain_ms)),
+        }
+      }
+      if strongest.len() < config.min_intervals {
+        return None;
+      }
+      Some(Level {
+        low: c[0].y,
+        high: c[c.len() - 1].y,
+        intervals: strongest.iter().map(|(i, _)| i.to_string()).collect(),
+        points: c.len(),
+        score: strongest
+          .iter()
+          .map(|(_, domain)| (1. + *domain as f32 / step as f32).log2())
+          .sum(),
+      })
+    })
+    .collect()
+}
+
+/// Confluence levels for a chart of `interval`, from the configured
+/// intervals above it, as they stood at `at`.
+pub fn levels_at(symbol: &str, interval: &str, at: i64) -> Result<Vec<Level>> {
+  levels_from(&stored(symbol), interval, at)
+}
+
+/// `levels_at`, reading candles from `source`.
+pub fn levels_from(
+  source: &impl Source,
+  interval: &str,
+  at: i64,
+) -> Result<Vec<Level>> {
+  let config = &CONFIG.confluence;
+  let step = interval.ms();
+  let higher: Vec<String> = config
+    .intervals
+    .iter()
+    .filter(|i| i.ms() > step)
+    .cloned()
+    .collect();
+
+  let candles = source(interval, at - step, config.lookback)?;
+  let projected = project(source, &higher, at, config.lookback)?;
+  Ok(levels(&candles, step, &projected, config))
+}
+
+/// The nearest level above and below `price`, as
+/// `[distance above, score above, distance below, score below]` with
+/// distances a share of the price. A level around the price counts as
+/// both, at 0.
+pub fn features(levels: &[Level], price: f32) -> [f32; 4] {
+  let nearest = |distance: fn(&Level, f32) -> Option<f32>| {
+    levels
+      .iter()
+      .filter_map(|l| Some((distance(l, price)?.max(0.) / price, l.score)))
+      .min_by(|a, b| a.0.total_cmp(&b.0))
+      .unwrap_or((NO_LEVEL, 0.))
+  };
+  let above = nearest(|l, price| (l.high >= price).then_some(l.low - price));
+  let below = nearest(|l, price| (l.low <= price).then_some(price - l.high));
+  [above.0, above.1, below.0, below.1]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HOUR: i64 = 3_600_000;
+
+  fn point(interval: &str, y: f32, domain_ms: i64) -> Projected {
+    Projected {
+      interval: interval.into(),
+      position: CandlePos::HIGH,
+      x: 0,
+      y,
+      domain_ms,
+    }
+  }
+
+  #[test]
+  fn domains_are_clipped_to_what_was_known() {
+    let candles: Vec<Candle> = (0..4)
+      .map(|i| Candle {
+        open_time: i * HOUR,
+        top_domain: 10,
+        bottom_domain: 1,
+        fuzzy_domain: false,
+        ..Default::default()
+      })
+      .collect();
+
+    // at 3h, the candle at 2h has just closed and the one at 3h hasn't
+    let known = as_of(&candles, HOUR, 3 * HOUR);
+    assert_eq!(known.len(), 3);
+    let domains: Vec<(i32, i32, bool)> = known
+      .iter()
+      .map(|c| (c.top_domain, c.bottom_domain, c.fuzzy_domain))
+      .collect();
+    assert_eq!(domains, vec![(3, 1, true), (2, 1, true), (1, 1, true)]);
+    assert_eq!(as_of(&candles, HOUR, 20 * HOUR)[0].top_domain, 10);
+    assert!(!as_of(&candles, HOUR, 20 * HOUR)[0].fuzzy_domain);
+
+    // the high at 1h is beaten on its left a candle away, and on its right
+    // only by the candle at 3h
+    let candles: Vec<Candle> = [12., 10., 9., 11.]
+      .into_iter()
+      .enumerate()
+      .map(|(i, high)| Candle {
+        open_time: i as i64 * HOUR,
+        high,
+        low: 1.,
+        top_domain: 1,
+        bottom_domain: 1,
+        fuzzy_domain: false,
+        ..Default::default()
+      })
+      .collect();
+    let fuzzy = |at: i64| as_of(&candles, HOUR, at)[1].fuzzy_domain;
+    assert!(fuzzy(3 * HOUR));
+    assert!(!fuzzy(4 * HOUR));
+  }
+
+  #[test]
+  fn levels_score_agreeing_timeframes() {
+    let config = ConfluenceConfig {
+      intervals: vec![],
+      tolerance: Tolerance::Fraction(0.01),
+      lookback: 0,
+      min_intervals: 2,
+    };
+    let projected = vec![
+      point("1d", 100., 3 * HOUR),
+      point("1d", 100.5, 7 * HOUR),
+      point("4h", 100.2, HOUR),
+      // a single timeframe doesn't make a level
+      point("1d", 120., 100 * HOUR),
+      point("1d", 120.1, 100 * HOUR),
+    ];
+    let found = levels(&[], HOUR, &projected, &config);
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].low, found[0].high), (100., 100.5));
+    assert_eq!(found[0].intervals, vec!["1d", "4h"]);
+    assert_eq!(found[0].points, 3);
+    // log2(1 + 7) from the strongest daily point, log2(1 + 1) from 4h
+    assert_eq!(found[0].score, 4.);
+  }
+
+  #[test]
+  fn features_find_the_nearest_levels() {
+    let level = |low, high, score| Level {
+      low,
+      high,
+      intervals: vec![],
+      points: 2,
+      score,
+    };
+    let levels = vec![level(90., 91., 2.), level(110., 111., 3.)];
+    assert_eq!(features(&levels, 100.), [0.1, 3., 0.09, 2.]);
+    assert_eq!(features(&levels, 90.5), [0., 2., 0., 2.]);
+    assert_eq!(features(&levels, 200.), [NO_LEVEL, 0., 0.445, 3.]);
+    assert_eq!(features(&[], 1.), [NO_LEVEL, 0., NO_LEVEL, 0.]);
+  }
+}
@@ -0,0 +1,230 @@
+// A candle's top domain is how many candles its high stands over: the
+// distance to the nearest candle that reaches it, strictly higher before
+// it or at least as high after it, whichever is closer. Bottom domains are
+// the same for lows. With no such candle on a side, the distance past the
+// first or last stored candle stands in.
+//
+// Everything here works on positions in candles (open time / step), so
+// gaps in the data don't shrink domains.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Domain {
+  pub top: i32,
+  pub bottom: i32,
+  // nothing after the candle reaches its high or low yet, so the domain
+  // can still grow as candles arrive
+  pub fuzzy: bool,
+}
+
+// indexes of the nearest beating value before and after each value
+type Nearest = Vec<(Option<usize>, Option<usize>)>;
+
+/// For each value, the index of the nearest earlier value that `beats` it
+/// and of the nearest later value it doesn't beat, found with a monotonic
+/// stack in linear time.
+fn nearest(values: &[f32], beats: fn(f32, f32) -> bool) -> Nearest {
+  let mut result = vec![(None, None); values.len()];
+  let mut stack: Vec<usize> = vec![];
+
+  for i in 0..values.len() {
+    while let Some(&j) = stack.last() {
+      if beats(values[j], values[i]) {
+        break;
+      }
+      stack.pop();
+    }
+    result[i].0 = stack.last().copied();
+    stack.push(i);
+  }
+
+  stack.clear();
+  for i in (0..values.len()).rev() {
+    while let Some(&j) = stack.last() {
+      if !beats(values[i], values[j]) {
+        break;
+      }
+      stack.pop();
+    }
+    result[i].1 = stack.last().copied();
+    stack.push(i);
+  }
+
+  result
+}
+
+fn higher(a: f32, b: f32) -> bool { a > b }
+fn lower(a: f32, b: f32) -> bool { a < b }
+
+struct Span<'a> {
+  pos: &'a [i64],
+  // positions of the first and last stored candles
+  first: i64,
+  last: i64,
+}
+
+impl Span<'_> {
+  fn domain(
+    &self,
+    i: usize,
+    (left, right): (Option<usize>, Option<usize>),
+  ) -> i32 {
+    let left = match left {
+      Some(j) => self.pos[i] - self.pos[j],
+      None => self.pos[i] - self.first + 1,
+    };
+    let right = match right {
+      Some(j) => self.pos[j] - self.pos[i],
+      None => self.last - self.pos[i] + 1,
+    };
+    left.min(right) as i32
+  }
+}
+
+/// Domains of every candle, given their highs, lows and positions.
+pub fn domains(highs: &[f32], lows: &[f32], pos: &[i64]) -> Vec<Domain> {
+  let stored = vec![None; pos.len()];
+  match (pos.first(), pos.last()) {
+    (Some(&first), Some(_)) => update_tail(highs, lows, pos, first, &stored)
+      .into_iter()
+      .map(|(_, d)| d)
+      .collect(),
+    _ => vec![],
+  }
+}
+
+/// Domains after candles were appended, without loading the whole series.
+/// Pass the candles that were fuzzy along with the new ones, in order, with
+/// `stored` holding the fuzzy candles' domains and None for new candles.
+/// `first` is the position of the first stored candle.
+///
+/// This works because anything a new candle can meet looking back is
+/// fuzzy: the nearest earlier candle beating it beats everything after
+/// itself too. Returns the domains that changed, by index.
+pub fn update_tail(
+  highs: &[f32],
+  lows: &[f32],
+  pos: &[i64],
+  first: i64,
+  stored: &[Option<Domain>],
+) -> Vec<(usize, Domain)> {
+  let last = match pos.last() {
+    Some(&last) => last,
+    None => return vec![],
+  };
+  let span = Span { pos, first, last };
+  let tops = nearest(highs, higher);
+  let bottoms = nearest(lows, lower);
+  // a fuzzy candle only changes if it's still unbeaten among the old ones
+  let reopened =
+    |right: Option<usize>| right.is_none_or(|j| stored[j].is_none());
+
+  let mut changed = vec![];
+  for i in 0..pos.len() {
+    let domain = Domain {
+      top: match stored[i] {
+        Some(d) if !reopened(tops[i].1) => d.top,
+        _ => span.domain(i, tops[i]),
+      },
+      bottom: match stored[i] {
+        Some(d) if !reopened(bottoms[i].1) => d.bottom,
+        _ => span.domain(i, bottoms[i]),
+      },
+      fuzzy: tops[i].1.is_none() || bottoms[i].1.is_none(),
+    };
+    if stored[i] != Some(domain) {
+      changed.push((i, domain));
+    }
+  }
+  changed
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the definition, walking out from every candle
+  fn naive(highs: &[f32], lows: &[f32]) -> Vec<Domain> {
+    let n = highs.len() as i64;
+    let walk = |v: &[f32], i: usize, beats: fn(f32, f32) -> bool| {
+      let left = (0..i).rev().find(|&j| beats(v[j], v[i]));
+      let right = (i + 1..v.len()).find(|&j| !beats(v[i], v[j]));
+      let l = left.map_or(i as i64 + 1, |j| (i - j) as i64);
+      let r = right.map_or(n - i as i64, |j| (j - i) as i64);
+      (l.min(r) as i32, right.is_none())
+    };
+    (0..highs.len())
+      .map(|i| {
+        let (top, top_open) = walk(highs, i, higher);
+        let (bottom, bottom_open) = walk(lows, i, lower);
+        Domain {
+          top,
+          bottom,
+          fuzzy: top_open || bottom_open,
+        }
+      })
+      .collect()
+  }
+
+  // a random walk with plenty of equal prices
+  fn walk(n: usize, mut seed: u64) -> (Vec<f32>, Vec<f32>) {
+    let mut price = 100f32;
+    let mut next = || {
+      seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+      (seed >> 33) % 7
+    };
+    (0..n)
+      .map(|_| {
+        price += next() as f32 - 3.;
+        (price + next() as f32, price - next() as f32)
+      })
+      .unzip()
+  }
+
+  #[test]
+  fn stack_matches_the_definition() {
+    for seed in 0..20 {
+      let (highs, lows) = walk(300, seed);
+      let pos: Vec<i64> = (0..300).collect();
+      assert_eq!(domains(&highs, &lows, &pos), naive(&highs, &lows));
+    }
+    assert!(domains(&[], &[], &[]).is_empty());
+  }
+
+  #[test]
+  fn gaps_count_towards_domains() {
+    // neighbours by index, but 4 and 5 candles away in time
+    let d = domains(&[5., 3., 5.], &[1., 2., 1.], &[0, 4, 9]);
+    assert_eq!(d[1].top, 4);
+    assert_eq!(d[1].bottom, 4);
+    assert!(!d[1].fuzzy && d[2].fuzzy);
+  }
+
+  #[test]
+  fn tail_updates_match_a_full_rebuild() {
+    for seed in 0..20 {
+      let (highs, lows) = walk(400, seed);
+      let pos: Vec<i64> = (0..400).collect();
+      let split = 250 + seed as usize;
+      let before = domains(&highs[..split], &lows[..split], &pos[..split]);
+
+      // what would be loaded: the fuzzy candles, then the new ones
+      let tail: Vec<usize> = (0..split)
+        .filter(|&i| before[i].fuzzy)
+        .chain(split..400)
+        .collect();
+      let stored: Vec<Option<Domain>> =
+        tail.iter().map(|&i| before.get(i).copied()).collect();
+      let pick = |v: &[f32]| tail.iter().map(|&i| v[i]).collect::<Vec<_>>();
+      let tail_pos: Vec<i64> = tail.iter().map(|&i| pos[i]).collect();
+      let changed =
+        update_tail(&pick(&highs), &pick(&lows), &tail_pos, 0, &stored);
+
+      let mut updated = before.clone();
+      updated.resize(400, Domain::default());
+      for (i, d) in changed {
+        updated[tail[i]] = d;
+      }
+      assert_eq!(updated, domains(&highs, &lows, &pos), "seed {}", seed);
+    }
+  }
+}
@@ -1,36 +1,44 @@
-use postgres::error::SqlState;
+use postgres::GenericClient;
 
 use crate::prelude::*;
 
 pub static UNIQUE_VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
+#[derive(Serialize)]
 pub struct MovingAverage {
   symbol: String,
   interval: String,
-  ms: i64,
-  len: i32, // 240
-  val: f32,
-  exp: bool,
+  pub ms: i64,
+  pub len: i32, // 240
+  pub val: f32,
+  pub exp: bool,
 }
 
 impl MovingAverage {
   pub const DB_COLUMNS: &'static str = "symbol, interval, ms, len, val, exp";
 
   pub fn calculate_ma(symbol: &str, interval: &str, len: usize) -> Result<()> {
+    let token = terminal::jobs::current();
     let q = Query::new(symbol, interval);
     let candles = q.query_candles()?;
-    MovingAverage::clear(symbol, interval, len, false)?;
 
     assert!(len < candles.len());
     candles.ensure_congruent();
 
+    // replace the old series in one transaction so a cancelled run leaves it
+    // untouched
+    let mut con = con();
+    let mut tx = con.transaction()?;
+    MovingAverage::clear(&mut tx, symbol, interval, len, false)?;
+
     let mut sum = candles[..len].iter().fold(0., |acc, c| acc + c.close) as f32;
     let len_i32 = len as i32;
     let len_f32 = len as f32;
 
     let pb_label = format!("Moving Average {}, {} - {}", symbol, interval, len);
-    terminal::PB.0.send((pb_label.clone(), 0.))?;
+    pb(&pb_label, 0.);
 
     for i in len..candles.len() {
+      token.check()?;
       MovingAverage {
         symbol: symbol.to_owned(),
         interval: interval.to_owned(),
@@ -39,38 +47,53 @@ impl MovingAverage {
         val: sum / len_f32,
         exp: false,
       }
-      .save()?;
+      .save(&mut tx)?;
 
       sum -= candles[i - len].close;
       sum += candles[i].close;
 
-      terminal::PB
-        .0
-        .send((pb_label.clone(), i as f64 / (candles.len() - len) as f64))?;
+      pb(&pb_label, i as f64 / (candles.len() - len) as f64);
     }
 
-    let _ = terminal::PB.0.send((pb_label.clone(), -1.));
+    tx.commit()?;
+    web_server::push::publish(web_server::push::Event::MovingAverage {
+      symbol: symbol.to_owned(),
+      interval: interval.to_owned(),
+      len: len as i32,
+      exp: false,
+    });
+    pb(&pb_label, -1.);
 
     Ok(())
   }
 
-  fn clear(symbol: &str, interval: &str, len: usize, exp: bool) -> Result<()> {
+  fn clear(
+    con: &mut impl GenericClient,
+    symbol: &str,
+    interval: &str,
+    len: usize,
+    exp: bool,
+  ) -> Result<()> {
     let query = format!(
       "DELETE FROM moving_averages WHERE symbol='{}' AND interval='{}' AND len={} AND exp={}",
       symbol, interval, len, exp
     );
-    con().batch_execute(&query)?;
+    con.batch_execute(&query)?;
     Ok(())
   }
 
   pub fn calculate_ema(symbol: &str, interval: &str, len: usize) -> Result<()> {
+    let token = terminal::jobs::current();
     let q = Query::new(symbol, interval);
     let candles = q.query_candles()?;
-    MovingAverage::clear(symbol, interval, len, true)?;
 
     assert!(len < candles.len());
     candles.ensure_congruent();
 
+    let mut con = con();
+    let mut tx = con.transaction()?;
+    MovingAverage::clear(&mut tx, symbol, interval, len, true)?;
+
     let mut ma = candles[..len].iter().fold(0., |acc, c| acc + c.close) as f32
       / len as f32;
     let k = 2. / (len as f32 + 1.);
@@ -80,9 +103,10 @@ impl MovingAverage {
       "Exponential moving Average {}, {} - {}",
       symbol, interval, len
     );
-    terminal::PB.0.send((pb_label.clone(), 0.))?;
+    pb(&pb_label, 0.);
 
     for i in len..candles.len() {
+      token.check()?;
       ma = candles[i].close * k + ma * (1. - k);
 
       MovingAverage {
@@ -93,15 +117,19 @@ impl MovingAverage {
         val: ma,
         exp: true,
       }
-      .save()?;
+      .save(&mut tx)?;
 
-      terminal::PB.0.send((
-        pb_label.clone(),
-        i as f64 / (candles.len() as f64 - len as f64),
-      ))?;
+      pb(&pb_label, i as f64 / (candles.len() as f64 - len as f64));
     }
 
-    let _ = terminal::PB.0.send((pb_label.clone(), -1.));
+    tx.commit()?;
+    web_server::push::publish(web_server::push::Event::MovingAverage {
+      symbol: symbol.to_owned(),
+      interval: interval.to_owned(),
+      len: len as i32,
+      exp: true,
+    });
+    pb(&pb_label, -1.);
     log!("Done");
 
     Ok(())
@@ -113,6 +141,19 @@ impl MovingAverage {
     len: i32,
     exp: bool,
     range: Option<Range<i64>>,
+  ) -> Result<Vec<MovingAverage>> {
+    Self::query_page(symbol, interval, len, exp, range, ASC, None)
+  }
+
+  /// Like `query`, in `order` of time and at most `limit` long.
+  pub fn query_page(
+    symbol: &str,
+    interval: &str,
+    len: i32,
+    exp: bool,
+    range: Option<Range<i64>>,
+    order: database::Order,
+    limit: Option<usize>,
   ) -> Result<Vec<MovingAverage>> {
     let mut query = format!(
       r#"SELECT {} FROM moving_averages WHERE symbol = '{}' AND interval = '{}' AND len = {} AND exp = {}"#,
@@ -129,12 +170,28 @@ impl MovingAverage {
         range.start, range.end
       ));
     }
+    query.push_str(match order {
+      ASC => " ORDER BY ms ASC",
+      DESC => " ORDER BY ms DESC",
+    });
+    if let Some(limit) = limit {
+      query.push_str(&format!(" LIMIT {}", limit));
+    }
 
     let rows = con().query(query.as_str(), &[])?;
     Ok(rows.iter().map(|r| r.into()).collect())
   }
 
-  fn save(&self) -> Result<()> {
+  /// (len, exp) of every moving average stored for a symbol and interval.
+  pub fn available(symbol: &str, interval: &str) -> Result<Vec<(i32, bool)>> {
+    let rows = con().query(
+      "SELECT DISTINCT len, exp FROM moving_averages WHERE symbol = $1 AND interval = $2 ORDER BY len",
+      &[&symbol, &interval],
+    )?;
+    Ok(rows.iter().map(|r| (r.get(0), r.get(1))).collect())
+  }
+
+  fn save(&self, con: &mut impl GenericClient) -> Result<()> {
     // log!(
     // "Saving {} EMA {} for {}, val: {}",
     // self.interval,
@@ -143,18 +200,15 @@ impl MovingAverage {
     // self.val
     // );
 
-    let result = con().execute(
-      "INSERT INTO moving_averages (symbol, interval, ms, len, val, exp) values ($1, $2, $3, $4, $5, $6)",
+    let inserted = con.execute(
+      "INSERT INTO moving_averages (symbol, interval, ms, len, val, exp) values ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
     &[&self.symbol, &self.interval, &self.ms, &self.len, &self.val, &self.exp]
-    );
+    )?;
 
-    if let Err(e) = result {
-      match e.code() {
-        Some(&SqlState::UNIQUE_VIOLATION) => {
-          UNIQUE_VIOLATIONS.fetch_add(1, Relaxed);
-        }
-        _ => Err(e)?,
-      }
+    // a failed insert would abort the surrounding transaction, so conflicts
+    // are skipped by postgres and counted here instead
+    if inserted == 0 {
+      UNIQUE_VIOLATIONS.fetch_add(1, Relaxed);
     }
 
     Ok(())
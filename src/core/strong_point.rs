@@ -1,5 +1,5 @@
+use crate::config::StrongPointsConfig;
 use crate::prelude::*;
-use serde::Serialize;
 
 use CandlePos::*;
 
@@ -29,10 +29,29 @@ pub struct StrongPoint {
   pub domain: i32,
 }
 
-#[derive(Debug, Serialize, Clone)]
-pub struct StrongPointConfig {
-  pub min_domain: u16,
-  pub count: u16,
+/// How strong points are picked from the candles.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LimitBy {
+  // the `count` points with the largest domains
+  Fixed,
+  // every point with at least `min_domain`
+  MinDomain,
+  // the largest domains, `percent` of the number of candles
+  Percent,
+}
+
+/// What to do with points whose domain is still open to the right, and so
+/// only a lower bound of what it will be.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FuzzyPolicy {
+  // rank them by the domain they have so far
+  Keep,
+  // leave them out until they settle
+  Drop,
+  // always include them, on top of the selection
+  Always,
 }
 
 impl StrongPoint {
@@ -62,21 +81,159 @@ impl StrongPoint {
 }
 
 pub fn generate_points(candles: &[Candle]) -> Vec<StrongPoint> {
-  let min_domain = CONFIG.strong_points.min_domain;
-  let mut strong_points = vec![];
-  let mut index = 0;
-
-  for i in 0..candles.len() {
-    let candle = &candles[i];
-    if candle.top_domain >= min_domain {
-      strong_points.push(StrongPoint::new(candles, i, HIGH, index));
-      index += 1;
+  generate_points_with(candles, &CONFIG.strong_points)
+}
+
+pub fn generate_points_with(
+  candles: &[Candle],
+  config: &StrongPointsConfig,
+) -> Vec<StrongPoint> {
+  // (candle index, position, whether the domain is still open)
+  let mut candidates = vec![];
+  // a fuzzy candle may be open on one side only, which shows as no later
+  // candle reaching it
+  let (mut later_high, mut later_low) = (f32::MIN, f32::MAX);
+  for (i, candle) in candles.iter().enumerate().rev() {
+    let open_top = candle.fuzzy_domain && candle.high > later_high;
+    let open_bottom = candle.fuzzy_domain && candle.low < later_low;
+    later_high = later_high.max(candle.high);
+    later_low = later_low.min(candle.low);
+
+    // domains of 0 haven't been calculated yet
+    if candle.top_domain > 0 {
+      candidates.push((i, HIGH, open_top));
     }
-    if candle.bottom_domain >= min_domain {
-      strong_points.push(StrongPoint::new(candles, i, LOW, index));
-      index += 1;
+    if candle.bottom_domain > 0 {
+      candidates.push((i, LOW, open_bottom));
     }
   }
 
-  strong_points
+  let domain = |&(i, position, _): &(usize, CandlePos, bool)| match position {
+    HIGH => candles[i].top_domain,
+    LOW => candles[i].bottom_domain,
+  };
+  let (fuzzy, mut settled): (Vec<_>, Vec<_>) =
+    candidates.into_iter().partition(|&(_, _, open)| open);
+  match config.fuzzy {
+    FuzzyPolicy::Keep => settled.extend(fuzzy.iter().copied()),
+    FuzzyPolicy::Drop | FuzzyPolicy::Always => (),
+  }
+
+  let limit = match config.limit_by {
+    LimitBy::MinDomain => {
+      settled.retain(|p| domain(p) >= config.min_domain);
+      settled.len()
+    }
+    LimitBy::Fixed => config.count,
+    LimitBy::Percent => {
+      (candles.len() as f32 * config.percent).round().max(0.) as usize
+    }
+  };
+  // candidates are newest first, so ties go to the newer point
+  settled.sort_by_key(|p| -domain(p));
+  settled.truncate(limit);
+  if config.fuzzy == FuzzyPolicy::Always {
+    settled.extend(fuzzy);
+  }
+
+  settled.sort_by_key(|&(i, position, _)| (i, position == LOW));
+  settled
+    .into_iter()
+    .enumerate()
+    .map(|(index, (i, position, _))| {
+      StrongPoint::new(candles, i, position, index)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // highs fall away from candle 2, so 2..6 are still open at the top
+  fn candles() -> Vec<Candle> {
+    [(1, 1), (3, 3), (9, 2), (2, 1), (6, 2), (4, 3), (2, 1)]
+      .iter()
+      .enumerate()
+      .map(|(i, &(top_domain, bottom_domain))| Candle {
+        open_time: i as i64,
+        high: 20. - (i as f32 - 2.).abs(),
+        low: 5.,
+        top_domain,
+        bottom_domain,
+        fuzzy_domain: i >= 2,
+        ..Default::default()
+      })
+      .collect()
+  }
+
+  fn config(limit_by: LimitBy, fuzzy: FuzzyPolicy) -> StrongPointsConfig {
+    StrongPointsConfig {
+      limit_by,
+      min_domain: 3,
+      count: 2,
+      percent: 0.5,
+      fuzzy,
+    }
+  }
+
+  fn picked(config: &StrongPointsConfig) -> Vec<(i64, CandlePos)> {
+    generate_points_with(&candles(), config)
+      .iter()
+      .map(|p| (p.x, p.position))
+      .collect()
+  }
+
+  #[test]
+  fn points_are_limited_by_domain_count_or_percent() {
+    use FuzzyPolicy::Keep;
+    assert_eq!(
+      picked(&config(LimitBy::MinDomain, Keep)),
+      vec![
+        (1, HIGH),
+        (1, LOW),
+        (2, HIGH),
+        (4, HIGH),
+        (5, HIGH),
+        (5, LOW)
+      ]
+    );
+    assert_eq!(
+      picked(&config(LimitBy::Fixed, Keep)),
+      vec![(2, HIGH), (4, HIGH)]
+    );
+    // half of 7 candles rounds to 4, and the newer of equal domains wins
+    assert_eq!(
+      picked(&config(LimitBy::Percent, Keep)),
+      vec![(2, HIGH), (4, HIGH), (5, HIGH), (5, LOW)]
+    );
+
+    let points =
+      generate_points_with(&candles(), &config(LimitBy::Fixed, Keep));
+    assert_eq!((points[0].index, points[1].index), (0, 1));
+    assert_eq!((points[0].domain, points[1].domain), (9, 6));
+  }
+
+  #[test]
+  fn fuzzy_points_follow_the_policy() {
+    use LimitBy::Fixed;
+    // the lows of fuzzy candles are reached later, bar the last one
+    assert_eq!(
+      picked(&config(Fixed, FuzzyPolicy::Drop)),
+      vec![(1, HIGH), (5, LOW)]
+    );
+    assert_eq!(
+      picked(&config(Fixed, FuzzyPolicy::Always)),
+      vec![
+        (1, HIGH),
+        (2, HIGH),
+        (3, HIGH),
+        (4, HIGH),
+        (5, HIGH),
+        (5, LOW),
+        (6, HIGH),
+        (6, LOW)
+      ]
+    );
+  }
 }
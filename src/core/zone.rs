@@ -0,0 +1,338 @@
+use crate::config::ZonesConfig;
+use crate::prelude::*;
+use postgres::GenericClient;
+use strong_point::{generate_points, CandlePos};
+
+// candles averaged for the true range
+pub const ATR_PERIOD: usize = 14;
+
+/// How close in price strong points must be to share a zone.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Tolerance {
+  // share of the point's price
+  Fraction(f32),
+  // multiple of the average true range
+  Atr(f32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "status", content = "at", rename_all = "snake_case")]
+pub enum Breakout {
+  Held,
+  // closed through the zone at this time, after the last touch
+  Up(i64),
+  Down(i64),
+}
+
+/// A band of prices where strong points cluster.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Zone {
+  // more than one when merged across intervals
+  pub intervals: Vec<String>,
+  pub low: f32,
+  pub high: f32,
+  pub touches: i32,
+  pub first_touch: i64,
+  pub last_touch: i64,
+  pub breakout: Breakout,
+}
+
+/// Average true range over the last `period` candles.
+pub fn atr(candles: &[Candle], period: usize) -> f32 {
+  let start = candles.len().saturating_sub(period).max(1);
+  let ranges: Vec<f32> = (start..candles.len())
+    .map(|i| {
+      let (c, prev) = (&candles[i], candles[i - 1].close);
+      (c.high - c.low)
+        .max((c.high - prev).abs())
+        .max((c.low - prev).abs())
+    })
+    .collect();
+  match ranges.len() {
+    0 => candles.first().map_or(0., |c| c.high - c.low),
+    n => ranges.iter().sum::<f32>() / n as f32,
+  }
+}
+
+/// Cluster `points` into zones. A zone grows upwards from its lowest point
+/// until the next point is further than the tolerance from it. Zones with
+/// fewer than `config.min_touches` points are dropped.
+pub fn zones(
+  interval: &str,
+  candles: &[Candle],
+  points: &[StrongPoint],
+  config: &ZonesConfig,
+) -> Vec<Zone> {
+  let atr = atr(candles, ATR_PERIOD);
+  let tolerance = |price: f32| match config.tolerance {
+    Tolerance::Fraction(f) => price * f,
+    Tolerance::Atr(m) => atr * m,
+  };
+
+  let mut points: Vec<&StrongPoint> = points.iter().collect();
+  points.sort_by(|a, b| a.y.total_cmp(&b.y));
+
+  let mut clusters: Vec<Vec<&StrongPoint>> = vec![];
+  for p in points {
+    match clusters.last_mut() {
+      Some(c) if p.y - c[0].y <= tolerance(c[0].y) => c.push(p),
+      _ => clusters.push(vec![p]),
+    }
+  }
+
+  clusters
+    .into_iter()
+    .filter(|c| c.len() >= config.min_touches)
+    .map(|c| {
+      let last = c.iter().max_by_key(|p| p.x).unwrap();
+      let mut zone = Zone {
+        intervals: vec![interval.to_owned()],
+        low: c[0].y,
+        high: c[c.len() - 1].y,
+        touches: c.len() as i32,
+        first_touch: c.iter().map(|p| p.x).min().unwrap(),
+        last_touch: last.x,
+        breakout: Breakout::Held,
+      };
+      zone.breakout = zone.breakout(candles, last.position);
+      zone
+    })
+    .collect()
+}
+
+/// Combine zones from several intervals, joining any that overlap.
+pub fn merge(mut zones: Vec<Zone>) -> Vec<Zone> {
+  zones.sort_by(|a, b| a.low.total_cmp(&b.low));
+  let mut merged: Vec<Zone> = vec![];
+  for zone in zones {
+    match merged.last_mut() {
+      Some(m) if zone.low <= m.high => m.join(zone),
+      _ => merged.push(zone),
+    }
+  }
+  merged
+}
+
+impl Zone {
+  pub fn contains(&self, price: f32) -> bool {
+    price >= self.low && price <= self.high
+  }
+
+  // the first close through the zone after it was last touched. Touched
+  // by a low it is support, and breaks downwards; by a high, upwards.
+  fn breakout(&self, candles: &[Candle], touched_by: CandlePos) -> Breakout {
+    candles
+      .iter()
+      .filter(|c| c.open_time > self.last_touch)
+      .find_map(|c| match touched_by {
+        CandlePos::HIGH if c.close > self.high => {
+          Some(Breakout::Up(c.open_time))
+        }
+        CandlePos::LOW if c.close < self.low => {
+          Some(Breakout::Down(c.open_time))
+        }
+        _ => None,
+      })
+      .unwrap_or(Breakout::Held)
+  }
+
+  fn join(&mut self, other: Zone) {
+    // the most recently touched zone knows whether price has left since
+    if other.last_touch > self.last_touch {
+      self.breakout = other.breakout;
+    }
+    self.high = self.high.max(other.high);
+    self.touches += other.touches;
+    self.first_touch = self.first_touch.min(other.first_touch);
+    self.last_touch = self.last_touch.max(other.last_touch);
+    for interval in other.intervals {
+      if !self.intervals.contains(&interval) {
+        self.intervals.push(interval);
+      }
+    }
+  }
+
+  /// Recalculate and store the zones for a symbol and interval from the
+  /// strong points of its stored candles.
+  pub fn calculate(symbol: &str, interval: &str) -> Result<Vec<Zone>> {
+    let candles = Query::new(symbol, interval).query_candles()?;
+    let zones = zones(
+      interval,
+      &candles,
+      &generate_points(&candles),
+      &CONFIG.zones,
+    );
+
+    let mut con = con();
+    let mut tx = con.transaction()?;
+    tx.execute(
+      "DELETE FROM zones WHERE symbol = $1 AND interval = $2",
+      &[&symbol, &interval],
+    )?;
+    for zone in &zones {
+      zone.save(&mut tx, symbol, interval)?;
+    }
+    tx.commit()?;
+
+    Ok(zones)
+  }
+
+  fn save(
+    &self,
+    con: &mut impl GenericClient,
+    symbol: &str,
+    interval: &str,
+  ) -> Result<()> {
+    let (breakout, broken_at) = match self.breakout {
+      Breakout::Held => ("held", None),
+      Breakout::Up(at) => ("up", Some(at)),
+      Breakout::Down(at) => ("down", Some(at)),
+    };
+    con.execute(
+      "INSERT INTO zones (symbol, interval, low, high, touches, first_touch, last_touch, breakout, broken_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+      &[&symbol, &interval, &self.low, &self.high, &self.touches, &self.first_touch, &self.last_touch, &breakout, &broken_at],
+    )?;
+    Ok(())
+  }
+
+  /// Stored zones for a symbol and interval, lowest first.
+  pub fn query(symbol: &str, interval: &str) -> Result<Vec<Zone>> {
+    let rows = con().query(
+      "SELECT interval, low, high, touches, first_touch, last_touch, breakout, broken_at FROM zones WHERE symbol = $1 AND interval = $2 ORDER BY low",
+      &[&symbol, &interval],
+    )?;
+    Ok(rows.iter().map(|r| r.into()).collect())
+  }
+
+  /// Stored zones for several intervals, merged where they overlap.
+  pub fn query_across(symbol: &str, intervals: &[&str]) -> Result<Vec<Zone>> {
+    let mut zones = vec![];
+    for interval in intervals {
+      zones.append(&mut Zone::query(symbol, interval)?);
+    }
+    Ok(merge(zones))
+  }
+}
+
+impl From<&postgres::Row> for Zone {
+  fn from(row: &postgres::Row) -> Self {
+    let broken_at: Option<i64> = row.get(7);
+    Self {
+      intervals: vec![row.get(0)],
+      low: row.get(1),
+      high: row.get(2),
+      touches: row.get(3),
+      first_touch: row.get(4),
+      last_touch: row.get(5),
+      breakout: match (row.get::<_, &str>(6), broken_at) {
+        ("up", Some(at)) => Breakout::Up(at),
+        ("down", Some(at)) => Breakout::Down(at),
+        _ => Breakout::Held,
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candle(i: i64, low: f32, high: f32, close: f32) -> Candle {
+    Candle {
+      open_time: i * 1000,
+      close_time: (i + 1) * 1000 - 1,
+      open: close,
+      close,
+      high,
+      low,
+      ..Default::default()
+    }
+  }
+
+  fn point(candles: &[Candle], i: usize, position: CandlePos) -> StrongPoint {
+    StrongPoint::new(candles, i, position, 0)
+  }
+
+  #[test]
+  fn strong_points_cluster_into_zones() {
+    use CandlePos::*;
+    let mut candles: Vec<Candle> =
+      (0..20).map(|i| candle(i, 99., 101., 100.)).collect();
+    // lows near 90, highs near 110 and one stray high
+    for (i, low) in [(2, 90.), (6, 90.4), (9, 89.8)] {
+      candles[i] = candle(i as i64, low, 101., 100.);
+    }
+    for (i, high) in [(4, 110.), (8, 110.5), (12, 130.)] {
+      candles[i] = candle(i as i64, 99., high, 100.);
+    }
+    // closes above the highs after the last touch
+    candles[15] = candle(15, 99., 112., 111.);
+    let points: Vec<StrongPoint> = [(2, LOW), (6, LOW), (9, LOW)]
+      .into_iter()
+      .chain([(4, HIGH), (8, HIGH), (12, HIGH)])
+      .map(|(i, pos)| point(&candles, i, pos))
+      .collect();
+
+    let config = ZonesConfig {
+      tolerance: Tolerance::Fraction(0.01),
+      min_touches: 2,
+    };
+    let found = zones("1h", &candles, &points, &config);
+    assert_eq!(found.len(), 2);
+    assert_eq!((found[0].low, found[0].high), (89.8, 90.4));
+    assert_eq!(found[0].touches, 3);
+    assert_eq!((found[0].first_touch, found[0].last_touch), (2000, 9000));
+    assert_eq!(found[0].breakout, Breakout::Held);
+    assert_eq!((found[1].low, found[1].high), (110., 110.5));
+    assert_eq!(found[1].breakout, Breakout::Up(15000));
+
+    // a small share of the average range keeps every point apart
+    let config = ZonesConfig {
+      tolerance: Tolerance::Atr(0.01),
+      ..config
+    };
+    assert!(zones("1h", &candles, &points, &config).is_empty());
+  }
+
+  #[test]
+  fn zones_merge_across_intervals() {
+    let zone = |interval: &str, low, high, last_touch, breakout| Zone {
+      intervals: vec![interval.to_owned()],
+      low,
+      high,
+      touches: 2,
+      first_touch: 0,
+      last_touch,
+      breakout,
+    };
+    let merged = merge(vec![
+      zone("1d", 100., 102., 5, Breakout::Held),
+      zone("4h", 101., 103., 9, Breakout::Down(10)),
+      zone("4h", 120., 121., 3, Breakout::Held),
+    ]);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].intervals, vec!["1d", "4h"]);
+    assert_eq!((merged[0].low, merged[0].high), (100., 103.));
+    assert_eq!(merged[0].touches, 4);
+    assert_eq!(merged[0].breakout, Breakout::Down(10));
+    assert!(merged[1].contains(120.5));
+  }
+
+  #[test]
+  fn zones_to_and_from_db() -> Result<()> {
+    let zone = Zone {
+      intervals: vec!["1h".into()],
+      low: 10.,
+      high: 11.,
+      touches: 3,
+      first_touch: 1,
+      last_touch: 2,
+      breakout: Breakout::Down(3),
+    };
+    zone.save(&mut *con(), "ZONETEST", "1h")?;
+    assert_eq!(Zone::query("ZONETEST", "1h")?, vec![zone]);
+    assert!(Zone::query("ZONETEST", "4h")?.is_empty());
+    Ok(())
+  }
+}
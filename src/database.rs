@@ -7,7 +7,10 @@ use std::{
   mem::discriminant,
   ops::Range,
   process::Command,
-  sync::{atomic::AtomicUsize, RwLock},
+  sync::{
+    atomic::{AtomicI64, AtomicUsize},
+    RwLock,
+  },
 };
 
 mod migrations;
@@ -15,6 +18,10 @@ mod migrations;
 pub static UNIQUE_VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
 pub static DERIVED_CANDLES: AtomicUsize = AtomicUsize::new(0);
 pub static CANDLES: AtomicUsize = AtomicUsize::new(0);
+// when the coverage tab was last drawn, see `show_coverage`
+static COVERAGE_SHOWN: AtomicI64 = AtomicI64::new(0);
+// how stale `COVERAGE` gets while shown, it scans every candle
+const COVERAGE_EVERY: i64 = 30_000;
 
 pub struct DbPool(Pool<PostgresConnectionManager<NoTls>>);
 pub type DbCon = PooledConnection<PostgresConnectionManager<NoTls>>;
@@ -22,6 +29,7 @@ pub type DbCon = PooledConnection<PostgresConnectionManager<NoTls>>;
 lazy_static! {
   pub static ref POOL: RwLock<HashMap<usize, DbPool>> =
     RwLock::new(HashMap::new());
+  pub static ref COVERAGE: RwLock<Vec<Coverage>> = RwLock::new(vec![]);
 }
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Order {
@@ -40,8 +48,77 @@ fn db() -> String {
   return "trader".into();
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct Coverage {
+  pub symbol: String,
+  pub interval: String,
+  pub count: usize,
+  pub derived: usize,
+  pub start: i64,
+  pub end: i64,
+  // number of holes between start and end
+  pub gaps: usize,
+  // number of candles missing from those holes
+  pub missing: usize,
+}
+
+/// How much history is stored for every symbol and interval.
+pub fn coverage() -> Result<Vec<Coverage>> {
+  let rows = con().query(
+    "
+SELECT symbol, interval, COUNT(*), COUNT(*) FILTER (WHERE derived),
+  MIN(open_time), MAX(open_time), MAX(close_time - open_time + 1),
+  COUNT(*) FILTER (WHERE gap > close_time - open_time + 1)
+FROM (
+  SELECT symbol, interval, open_time, close_time, derived,
+    open_time - LAG(open_time) OVER (
+      PARTITION BY symbol, interval ORDER BY open_time
+    ) AS gap
+  FROM candles
+) c
+GROUP BY symbol, interval
+ORDER BY symbol, MAX(close_time - open_time) DESC",
+    &[],
+  )?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|r| {
+        let count = r.get::<usize, i64>(2) as usize;
+        let (start, end, step): (i64, i64, i64) =
+          (r.get(4), r.get(5), r.get(6));
+        let expected = ((end - start) / step.max(1) + 1) as usize;
+        Coverage {
+          symbol: r.get(0),
+          interval: r.get(1),
+          count,
+          derived: r.get::<usize, i64>(3) as usize,
+          start,
+          end,
+          gaps: r.get::<usize, i64>(7) as usize,
+          missing: expected.saturating_sub(count),
+        }
+      })
+      .collect(),
+  )
+}
+
+/// Keep `COVERAGE` fresh for a while. Called when the coverage tab is
+/// drawn, it's left alone otherwise.
+pub fn show_coverage() { COVERAGE_SHOWN.store(now(), Relaxed); }
+
 pub fn candle_counting_thread() {
-  thread::spawn(move || loop {
+  let mut covered = 0;
+  terminal::jobs::spawn("Counting candles", move || loop {
+    terminal::jobs::current().check()?;
+    let shown = now() - COVERAGE_SHOWN.load(Relaxed) < 5_000;
+    if shown && now() - covered >= COVERAGE_EVERY {
+      if let Ok(c) = coverage() {
+        *COVERAGE.write().unwrap() = c;
+      }
+      covered = now();
+    }
     if let Ok(r) = con().query("select count(*) from candles;", &[]) {
       let v = r[0].get::<usize, i64>(0);
       CANDLES.store(v as usize, Relaxed);
@@ -437,13 +514,19 @@ INSERT INTO candles (
         Some(&SqlState::UNIQUE_VIOLATION) => {
           // maybe we'll want to do a replace in the future
           // but for now, let's just (mostly) ignore it.
-          log!("Unique violation.");
+          log!(debug: "Unique violation.");
           UNIQUE_VIOLATIONS.fetch_add(1, Relaxed);
+          return Ok(());
         }
         _ => Err(e)?,
       }
     }
 
+    web_server::push::publish(web_server::push::Event::Candle {
+      symbol: self.symbol.clone(),
+      interval: self.interval.clone(),
+      candle: candle.clone(),
+    });
     Ok(())
   }
 
@@ -513,11 +596,15 @@ fn init_pool() -> DbPool {
 
   if !database_exists() {
     if let Err(err) = create_db() {
-      log!("Create db: {:?}", err);
+      log!(error: "Create db: {:?}", err);
     }
     if let Err(err) = migrate_db() {
-      log!("Migrate db: {:?}", err);
+      log!(error: "Migrate db: {:?}", err);
     }
+  } else if let Err(err) = migrations::create_zones_table()
+    .and_then(|_| migrations::create_models_tables())
+  {
+    log!(error: "Migrate db: {:?}", err);
   }
   let manager = r2d2_postgres::PostgresConnectionManager::new(
     format!("host=127.0.0.1 user=postgres dbname={}", db())
@@ -579,55 +666,14 @@ fn migrate_db() -> Result<()> {
   migrations::create_candles_table()?;
   log!("Creating moving averages...");
   migrations::create_moving_averages_table()?;
+  log!("Creating zones...");
+  migrations::create_zones_table()?;
+  log!("Creating the model registry...");
+  migrations::create_models_tables()?;
   log!("Done");
   Ok(())
 }
 
-pub fn update_domain(con: &mut DbCon, id: &i32, (top, bottom): (i32, i32)) {
-  con
-    .execute(
-      "UPDATE candles SET top_domain = $2, bottom_domain = $3 WHERE id = $1",
-      &[&id, &top, &bottom],
-    )
-    .unwrap();
-}
-
-pub fn calculate_domain(con: &mut DbCon, interval: &str) -> Result<()> {
-  log!("Calculating domain for... {}", interval);
-  let step = interval.ms();
-  con.execute(
-    "
-UPDATE candles AS a
-SET top_domain = (
-  COALESCE(ABS(
-    (SELECT b.open_time FROM candles AS b
-    WHERE b.high >= a.high AND
-    b.open_time != a.open_time AND
-    b.symbol = a.symbol AND
-    b.interval = $2
-    ORDER BY ABS(a.open_time - b.open_time)
-    LIMIT 1) - a.open_time
-  ) / $1, 0)
-),
-bottom_domain = (
-  COALESCE(ABS(
-    (SELECT b.open_time FROM candles as b
-    WHERE b.low <= a.low AND
-    b.open_time != a.open_time AND
-    b.symbol = a.symbol AND
-    b.interval = $2
-    ORDER BY ABS(a.open_time - b.open_time)
-    LIMIT 1) - a.open_time
-  ) / $1, 0)
-)
-WHERE a.interval = $2
-AND (a.top_domain = 0 OR a.bottom_domain = 0);",
-    &[&(step as i64), &interval],
-  )?;
-
-  Ok(())
-}
-
 // (top, bottom)
 pub fn _breaking_candles(
   con: &mut DbCon,
@@ -696,6 +742,33 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  fn coverage_counts_gaps() -> Result<()> {
+    let mut query = Query::new("BTCUSDT", "1h");
+    let step = query.step();
+    let start = "2d".ago().round(step);
+
+    // two holes: one candle, then three candles
+    for i in [0, 2, 3, 7, 8] {
+      query.insert_candle(&Candle {
+        open_time: start + step * i,
+        close_time: start + step * (i + 1) - 1,
+        derived: i == 3,
+        ..Default::default()
+      })?;
+    }
+
+    let coverage = coverage()?;
+    let c = coverage.iter().find(|c| c.interval == "1h").unwrap();
+    assert_eq!(c.count, 5);
+    assert_eq!(c.derived, 1);
+    assert_eq!(c.gaps, 2);
+    assert_eq!(c.missing, 4);
+    assert_eq!(c.start..c.end, start..(start + step * 8));
+
+    Ok(())
+  }
+
   #[test]
   fn linear_regression() -> Result<()> {
     let mut query = Query::default();
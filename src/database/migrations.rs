@@ -35,6 +35,26 @@ CREATE TABLE import_candles AS TABLE candles WITH NO DATA;",
   Ok(())
 }
 
+// IF NOT EXISTS, since databases created before zones also run this
+pub fn create_zones_table() -> Result<()> {
+  con().batch_execute(
+    "
+CREATE TABLE IF NOT EXISTS zones (
+  symbol       VARCHAR(10) NOT NULL,
+  interval     VARCHAR(3) NOT NULL,
+  low          REAL NOT NULL,
+  high         REAL NOT NULL,
+  touches      INT NOT NULL,
+  first_touch  BIGINT NOT NULL,
+  last_touch   BIGINT NOT NULL,
+  breakout     VARCHAR(4) NOT NULL,
+  broken_at    BIGINT,
+  primary key  (symbol, interval, low)
+)",
+  )?;
+  Ok(())
+}
+
 pub fn create_moving_averages_table() -> Result<()> {
   log!("Creating moving averages table.");
   con().batch_execute(
@@ -51,3 +71,34 @@ CREATE TABLE moving_averages (
   )?;
   Ok(())
 }
+
+// IF NOT EXISTS, since databases created before the registry also run this
+pub fn create_models_tables() -> Result<()> {
+  con().batch_execute(
+    "
+CREATE TABLE IF NOT EXISTS models (
+  id            SERIAL PRIMARY KEY,
+  symbol        VARCHAR(10) NOT NULL,
+  algorithm     TEXT NOT NULL,
+  strat         TEXT NOT NULL,
+  label         TEXT NOT NULL,
+  schema        VARCHAR(16) NOT NULL,
+  normalization TEXT NOT NULL,
+  train_start   BIGINT NOT NULL,
+  train_end     BIGINT NOT NULL,
+  test_start    BIGINT NOT NULL,
+  test_end      BIGINT NOT NULL,
+  metrics       TEXT NOT NULL,
+  created       BIGINT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS predictions (
+  model         INT NOT NULL REFERENCES models (id),
+  ms            BIGINT NOT NULL,
+  close         REAL NOT NULL,
+  predicted     REAL[] NOT NULL,
+  made_at       BIGINT NOT NULL,
+  primary key   (model, ms)
+);",
+  )?;
+  Ok(())
+}
@@ -7,18 +7,21 @@ mod prelude;
 
 mod api;
 mod config;
+mod chart;
 mod core;
 pub mod database;
+mod model;
 mod normalized;
 mod strategy;
 mod terminal;
 mod web_server;
 
 fn main() {
-  std::thread::spawn(|| {
-    strategy::build_cache("BTCUSDT");
+  terminal::jobs::spawn("Building cache", || {
+    strategy::build_cache("BTCUSDT")
   });
 
   database::candle_counting_thread();
+  web_server::start();
   terminal::Terminal::new();
 }
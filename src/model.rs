@@ -0,0 +1,454 @@
+use crate::config::NormalizationConfig;
+use crate::normalized::scale::{Scaler, Scope};
+use crate::normalized::{strat1, Labeller, StratSpec};
+use crate::prelude::*;
+use std::sync::Mutex;
+
+pub mod registry;
+pub mod train;
+
+lazy_static! {
+  static ref CACHE: Mutex<HashMap<String, Cached>> = Mutex::new(HashMap::new());
+}
+
+// a symbol's last prediction, see `cached`
+#[derive(Default)]
+struct Cached {
+  // the cursor last predicted for, or being predicted for
+  cursor: i64,
+  prediction: Option<Prediction>,
+}
+
+/// A model over `strat1` samples, saved as JSON. It carries what its
+/// samples were exported with, so predictions are made on frames built and
+/// scaled the same way as the ones it was trained on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Model {
+  pub symbol: String,
+  pub strat: StratSpec,
+  pub label: Labeller,
+  // names of a frame row's features, see `strat1::columns`
+  pub columns: Vec<String>,
+  // frame rows per sample
+  pub rows: usize,
+  pub normalization: NormalizationConfig,
+  // fitted to the training samples when scaling globally
+  pub scaler: Option<Scaler>,
+  // one for each of the label's names
+  pub estimators: Vec<Estimator>,
+}
+
+/// Predicts one label from a flattened sample.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Estimator {
+  // a weight for each input, squashed to 0..1 when logistic
+  Linear {
+    weights: Vec<f32>,
+    bias: f32,
+    logistic: bool,
+  },
+  // gradient-boosted regression trees, each scaled by the learning rate
+  // and summed onto the base
+  Trees {
+    base: f32,
+    learning_rate: f32,
+    trees: Vec<Tree>,
+    logistic: bool,
+  },
+}
+
+/// A regression tree, walked from its first node.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Tree {
+  pub nodes: Vec<Node>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Node {
+  // inputs at or below the threshold go left, the rest (and NaN) go right.
+  // Children come after their parent
+  Split {
+    input: usize,
+    threshold: f32,
+    left: usize,
+    right: usize,
+  },
+  Leaf(f32),
+}
+
+/// What a model made of the frame ending at `cursor`.
+#[derive(Serialize, Clone, Debug)]
+pub struct Prediction {
+  // the registered model's id
+  pub model: i32,
+  pub symbol: String,
+  pub cursor: i64,
+  // where the labels are measured from
+  pub close: f32,
+  pub label: Labeller,
+  pub labels: Vec<String>,
+  pub values: Vec<f32>,
+}
+
+fn sigmoid(x: f32) -> f32 { 1. / (1. + (-x).exp()) }
+
+// inputs that aren't finite count as 0
+fn dot(weights: &[f32], inputs: &[f32]) -> f32 {
+  weights
+    .iter()
+    .zip(inputs)
+    .filter(|(_, x)| x.is_finite())
+    .map(|(w, x)| w * x)
+    .sum()
+}
+
+impl Tree {
+  fn eval(&self, inputs: &[f32]) -> f32 {
+    let mut i = 0;
+    loop {
+      match self.nodes[i] {
+        Node::Leaf(value) => return value,
+        Node::Split {
+          input,
+          threshold,
+          left,
+          right,
+        } => {
+          i = match inputs[input] <= threshold {
+            true => left,
+            false => right,
+          }
+        }
+      }
+    }
+  }
+
+  // children after their parent, so every walk ends at a leaf
+  fn check(&self, inputs: usize) -> Result<()> {
+    if self.nodes.is_empty() {
+      bail!("A tree has no nodes");
+    }
+    for (i, node) in self.nodes.iter().enumerate() {
+      if let Node::Split {
+        input, left, right, ..
+      } = *node
+      {
+        if input >= inputs {
+          bail!("Node {} splits on input {} of {}", i, input, inputs);
+        }
+        for child in [left, right] {
+          if child <= i || child >= self.nodes.len() {
+            bail!("Node {} has a child at {}", i, child);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Estimator {
+  pub fn predict(&self, inputs: &[f32]) -> f32 {
+    let (raw, logistic) = match self {
+      Self::Linear {
+        weights,
+        bias,
+        logistic,
+      } => (bias + dot(weights, inputs), *logistic),
+      Self::Trees {
+        base,
+        learning_rate,
+        trees,
+        logistic,
+      } => (
+        base
+          + learning_rate * trees.iter().map(|t| t.eval(inputs)).sum::<f32>(),
+        *logistic,
+      ),
+    };
+    match logistic {
+      true => sigmoid(raw),
+      false => raw,
+    }
+  }
+
+  fn check(&self, inputs: usize) -> Result<()> {
+    match self {
+      Self::Linear { weights, .. } if weights.len() != inputs => {
+        bail!("{} weights for samples of {} values", weights.len(), inputs)
+      }
+      Self::Linear { .. } => Ok(()),
+      Self::Trees { trees, .. } => {
+        trees.iter().try_for_each(|t| t.check(inputs))
+      }
+    }
+  }
+}
+
+impl Model {
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer_pretty(File::create(path)?, self)?;
+    Ok(())
+  }
+
+  /// Load a model, checking it fits the samples its strat exports.
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    let model: Self = match fs::read_to_string(path) {
+      Ok(json) => serde_json::from_str(&json)?,
+      Err(_) => bail!("No model at {}", path.display()),
+    };
+    model.check()?;
+    Ok(model)
+  }
+
+  fn check_columns(&self) -> Result<()> {
+    if self.columns != strat1::columns(&self.strat) {
+      bail!(
+        "The model's columns aren't the ones {} exports: {}",
+        self.strat,
+        self.columns.join(", ")
+      );
+    }
+    Ok(())
+  }
+
+  fn check(&self) -> Result<()> {
+    self.check_columns()?;
+    if self.estimators.len() != self.label.names().len() {
+      bail!(
+        "{} estimators for the {} labels of {}",
+        self.estimators.len(),
+        self.label.names().len(),
+        self.label
+      );
+    }
+    match (self.normalization.scope, &self.scaler) {
+      (Scope::Global, None) => bail!("A globally scaled model needs a scaler"),
+      (_, Some(scaler)) if scaler.columns != self.columns => {
+        bail!("The model's scaler was fitted to other columns")
+      }
+      _ => {}
+    }
+    let inputs = self.rows * self.columns.len();
+    self.estimators.iter().try_for_each(|e| e.check(inputs))
+  }
+
+  /// A value for each label from a flattened sample.
+  pub fn predict(&self, sample: &[f32]) -> Result<Vec<f32>> {
+    let inputs = self.rows * self.columns.len();
+    if sample.len() != inputs {
+      bail!(
+        "A sample of {} values for a model taking {}",
+        sample.len(),
+        inputs
+      );
+    }
+    Ok(self.estimators.iter().map(|e| e.predict(sample)).collect())
+  }
+
+  /// Predict from the frame ending at `cursor`, as the registered model
+  /// `id`.
+  pub fn predict_at(&self, id: i32, cursor: i64) -> Result<Prediction> {
+    let cursor = cursor.round("15m");
+    let (sample, close) = strat1::sample_at(
+      &self.strat,
+      &self.symbol,
+      cursor,
+      &self.normalization,
+      self.scaler.as_ref(),
+    )?;
+    Ok(Prediction {
+      model: id,
+      symbol: self.symbol.clone(),
+      cursor,
+      close,
+      label: self.label.clone(),
+      labels: self.label.names(),
+      values: self.predict(&sample)?,
+    })
+  }
+}
+
+// the frame `predict` exports ends here
+fn cursor() -> i64 { "1h".ago().round("15m") }
+
+/// The prediction of `symbol`'s latest registered model for the frame
+/// `predict` exports, logged for scoring, or `None` without a model.
+pub fn latest(symbol: &str) -> Result<Option<Prediction>> {
+  let cursor = cursor();
+  let prediction = predict(registry::latest(symbol)?, cursor)?;
+  let mut cache = CACHE.lock().unwrap();
+  let cached = cache.entry(symbol.to_owned()).or_default();
+  cached.cursor = cursor;
+  cached.prediction = prediction.clone();
+  Ok(prediction)
+}
+
+/// `latest`, from the latest model of `symbol` over `strat`. It isn't
+/// cached, so `cached` keeps following the latest model of any strat.
+pub fn latest_of(
+  symbol: &str,
+  strat: &StratSpec,
+) -> Result<Option<Prediction>> {
+  predict(registry::latest_of(symbol, strat)?, cursor())
+}
+
+/// `entry`'s prediction for the frame ending at `cursor`, logged for
+/// scoring.
+fn predict(
+  entry: Option<registry::Entry>,
+  cursor: i64,
+) -> Result<Option<Prediction>> {
+  let entry = match entry {
+    Some(entry) => entry,
+    None => return Ok(None),
+  };
+  let prediction = entry.load()?.predict_at(entry.id, cursor)?;
+  registry::log(&prediction)?;
+  Ok(Some(prediction))
+}
+
+/// The last prediction for `symbol`, without waiting on one. Once the
+/// cursor moves on, a background job predicts for the new one, and the
+/// previous prediction stands until it's done.
+pub fn cached(symbol: &str) -> Option<Prediction> {
+  let cursor = cursor();
+  let mut cache = CACHE.lock().unwrap();
+  let cached = cache.entry(symbol.to_owned()).or_default();
+  if cached.cursor != cursor {
+    // once per cursor, so a failing model isn't retried every render
+    cached.cursor = cursor;
+    let symbol = symbol.to_owned();
+    terminal::jobs::spawn(format!("Predicting {}", symbol), move || {
+      latest(&symbol).map(|_| ())
+    });
+  }
+  cached.prediction.clone()
+}
+
+/// Drop `symbol`'s cached prediction, so `cached` makes a new one.
+pub fn forget(symbol: &str) { CACHE.lock().unwrap().remove(symbol); }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn model(estimators: Vec<Estimator>) -> Model {
+    let strat: StratSpec = "4h:1h;1h:2:false".parse().unwrap();
+    Model {
+      symbol: "BTCUSDT".into(),
+      columns: strat1::columns(&strat),
+      strat,
+      label: "horizon:8h".parse().unwrap(),
+      rows: 1,
+      normalization: NormalizationConfig::default(),
+      scaler: None,
+      estimators,
+    }
+  }
+
+  #[test]
+  fn estimators_predict() -> Result<()> {
+    let inputs = [1., 2., 3.];
+    let linear = Estimator::Linear {
+      weights: vec![0.5, -1., 2.],
+      bias: 1.,
+      logistic: false,
+    };
+    assert_eq!(linear.predict(&inputs), 5.5);
+
+    let logistic = Estimator::Linear {
+      weights: vec![0.; 3],
+      bias: 0.,
+      logistic: true,
+    };
+    assert_eq!(logistic.predict(&inputs), 0.5);
+
+    // input 1 <= 2.5 ? (input 0 <= 0 ? -1 : 1) : 3
+    let tree = Tree {
+      nodes: vec![
+        Node::Split {
+          input: 1,
+          threshold: 2.5,
+          left: 1,
+          right: 4,
+        },
+        Node::Split {
+          input: 0,
+          threshold: 0.,
+          left: 2,
+          right: 3,
+        },
+        Node::Leaf(-1.),
+        Node::Leaf(1.),
+        Node::Leaf(3.),
+      ],
+    };
+    tree.check(3)?;
+    assert!(tree.check(1).is_err());
+    let trees = Estimator::Trees {
+      base: 0.5,
+      learning_rate: 0.1,
+      trees: vec![tree.clone(), tree],
+      logistic: false,
+    };
+    assert!((trees.predict(&inputs) - 0.7).abs() < 1e-6);
+    assert!((trees.predict(&[-1., 5., 0.]) - 1.1).abs() < 1e-6);
+
+    // a child before its parent could loop forever
+    let looping = Tree {
+      nodes: vec![Node::Split {
+        input: 0,
+        threshold: 0.,
+        left: 0,
+        right: 0,
+      }],
+    };
+    assert!(looping.check(3).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn models_check_their_samples() -> Result<()> {
+    let inputs = strat1::columns(&"4h:1h;1h:2:false".parse()?).len();
+    let linear = |n: usize| Estimator::Linear {
+      weights: vec![1.; n],
+      bias: 0.,
+      logistic: false,
+    };
+
+    let path =
+      std::env::temp_dir().join(format!("model_{}.json", std::process::id()));
+    let good = model(vec![linear(inputs)]);
+    good.save(&path)?;
+    assert_eq!(Model::load(&path)?, good);
+    assert_eq!(good.predict(&vec![1.; inputs])?, vec![inputs as f32]);
+    assert!(good.predict(&[1.]).is_err());
+
+    for bad in [
+      model(vec![linear(inputs - 1)]),
+      // horizon has one label
+      model(vec![linear(inputs), linear(inputs)]),
+      Model {
+        normalization: NormalizationConfig {
+          scope: Scope::Global,
+          ..Default::default()
+        },
+        ..good.clone()
+      },
+    ] {
+      bad.save(&path)?;
+      assert!(Model::load(&path).is_err());
+    }
+    fs::remove_file(&path)?;
+    assert!(Model::load(&path).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,313 @@
+use super::train::{self, Algorithm, Metrics};
+use super::{Model, Prediction};
+use crate::config::NormalizationConfig;
+use crate::normalized::label::PATH_INTERVAL;
+use crate::normalized::{Labeller, StratSpec};
+use crate::prelude::*;
+
+const COLUMNS: &str = "id, symbol, algorithm, strat, label, schema, normalization, train_start, train_end, test_start, test_end, metrics, created";
+
+/// A trained model as registered, with what produced it and how it scored.
+/// The model itself is saved under `builder/models/<symbol>/<id>.json`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+  pub id: i32,
+  pub symbol: String,
+  pub algorithm: String,
+  pub strat: String,
+  pub label: String,
+  // see `schema`
+  pub schema: String,
+  pub normalization: NormalizationConfig,
+  // the last fold's cursors, which the model was fitted to and tested on
+  pub train: Range<i64>,
+  pub test: Range<i64>,
+  // the mean of each label's test metrics over the folds
+  pub metrics: Vec<Metrics>,
+  pub created: i64,
+}
+
+/// A hash of what a model's inputs are: the columns and rows of its
+/// samples and how they're scaled. Models sharing it take the same
+/// samples.
+pub fn schema(model: &Model) -> String {
+  let layout =
+    serde_json::json!([model.columns, model.rows, model.normalization]);
+  // FNV-1a, which stays the same between builds
+  let hash = layout.to_string().bytes().fold(0xcbf29ce484222325, |h, b| {
+    (h ^ b as u64).wrapping_mul(0x100000001b3)
+  });
+  format!("{:016x}", hash)
+}
+
+impl Entry {
+  pub fn path(&self) -> PathBuf {
+    PathBuf::from(format!("builder/models/{}/{}.json", self.symbol, self.id))
+  }
+
+  /// Load the model, checking it's the one registered.
+  pub fn load(&self) -> Result<Model> {
+    let model = Model::load(self.path())?;
+    if schema(&model) != self.schema {
+      bail!(
+        "The model at {} doesn't match the schema registered for #{}",
+        self.path().display(),
+        self.id
+      );
+    }
+    Ok(model)
+  }
+}
+
+impl TryFrom<&postgres::Row> for Entry {
+  type Error = anyhow::Error;
+
+  fn try_from(row: &postgres::Row) -> Result<Self> {
+    Ok(Self {
+      id: row.get(0),
+      symbol: row.get(1),
+      algorithm: row.get(2),
+      strat: row.get(3),
+      label: row.get(4),
+      schema: row.get(5),
+      normalization: serde_json::from_str(row.get(6))?,
+      train: row.get(7)..row.get(8),
+      test: row.get(9)..row.get(10),
+      metrics: serde_json::from_str(row.get(11))?,
+      created: row.get(12),
+    })
+  }
+}
+
+/// Register `model`, fitted to the `train` cursors and last tested on the
+/// `test` ones, and save it.
+pub fn register(
+  model: &Model,
+  algorithm: Algorithm,
+  train: Range<i64>,
+  test: Range<i64>,
+  metrics: &[Metrics],
+) -> Result<Entry> {
+  let mut con = con();
+  let mut tx = con.transaction()?;
+  let row = tx.query_one(
+    "INSERT INTO models (symbol, algorithm, strat, label, schema, normalization, train_start, train_end, test_start, test_end, metrics, created) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id",
+    &[&model.symbol, &algorithm.to_string(), &model.strat.to_string(), &model.label.to_string(), &schema(model), &serde_json::to_string(&model.normalization)?, &train.start, &train.end, &test.start, &test.end, &serde_json::to_string(metrics)?, &now()],
+  )?;
+  let entry = get_with(&mut tx, row.get(0))?;
+  // the row only goes in once the model is saved
+  model.save(entry.path())?;
+  tx.commit()?;
+  // the new model predicts from here on
+  super::forget(&model.symbol);
+  Ok(entry)
+}
+
+fn get_with(con: &mut impl postgres::GenericClient, id: i32) -> Result<Entry> {
+  match con.query_opt(
+    format!("SELECT {} FROM models WHERE id = $1", COLUMNS).as_str(),
+    &[&id],
+  )? {
+    Some(row) => (&row).try_into(),
+    None => bail!("No model #{}", id),
+  }
+}
+
+pub fn get(id: i32) -> Result<Entry> { get_with(&mut *con(), id) }
+
+/// The models registered for `symbol`, oldest first.
+pub fn list(symbol: &str) -> Result<Vec<Entry>> {
+  con()
+    .query(
+      format!(
+        "SELECT {} FROM models WHERE symbol = $1 ORDER BY id",
+        COLUMNS
+      )
+      .as_str(),
+      &[&symbol],
+    )?
+    .iter()
+    .map(Entry::try_from)
+    .collect()
+}
+
+/// The model last registered for `symbol`, which predicts for it.
+pub fn latest(symbol: &str) -> Result<Option<Entry>> {
+  con()
+    .query_opt(
+      format!(
+        "SELECT {} FROM models WHERE symbol = $1 ORDER BY id DESC LIMIT 1",
+        COLUMNS
+      )
+      .as_str(),
+      &[&symbol],
+    )?
+    .as_ref()
+    .map(Entry::try_from)
+    .transpose()
+}
+
+/// The model last registered for `symbol` over `strat`.
+pub fn latest_of(symbol: &str, strat: &StratSpec) -> Result<Option<Entry>> {
+  con()
+    .query_opt(
+      format!(
+        "SELECT {} FROM models WHERE symbol = $1 AND strat = $2 ORDER BY id DESC LIMIT 1",
+        COLUMNS
+      )
+      .as_str(),
+      &[&symbol, &strat.to_string()],
+    )?
+    .as_ref()
+    .map(Entry::try_from)
+    .transpose()
+}
+
+/// Log a prediction with the cursor its inputs end at, once per model and
+/// cursor, so it can be scored when its label is settled.
+pub fn log(prediction: &Prediction) -> Result<()> {
+  con().execute(
+    "INSERT INTO predictions (model, ms, close, predicted, made_at) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+    &[&prediction.model, &prediction.cursor, &prediction.close, &prediction.values, &now()],
+  )?;
+  Ok(())
+}
+
+/// Score the predictions logged for `entry` against the labels they went
+/// on to get, from stored candles. Predictions whose label isn't settled,
+/// or whose candles aren't stored yet, are left out.
+pub fn score(entry: &Entry) -> Result<Vec<Metrics>> {
+  let labeller: Labeller = entry.label.parse()?;
+  let strat: StratSpec = entry.strat.parse()?;
+  let algorithm: Algorithm = entry.algorithm.parse()?;
+  // how far past a cursor its label's path reaches
+  let reach = labeller.path_range(strat.entry(0)).end;
+  let rows = con().query(
+    "SELECT ms, close, predicted FROM predictions WHERE model = $1 AND ms <= $2 ORDER BY ms",
+    &[&entry.id, &(now() - reach - PATH_INTERVAL.ms())],
+  )?;
+
+  let mut predicted: Vec<Vec<f32>> = vec![];
+  let mut targets: Vec<Vec<f32>> = vec![];
+  for row in &rows {
+    let (cursor, close): (i64, f32) = (row.get(0), row.get(1));
+    let mut query = Query::new(&entry.symbol, PATH_INTERVAL);
+    let entered = strat.entry(cursor);
+    query.set_range(labeller.path_range(entered));
+    let labels = match labeller.label(entered, close, &query.query_candles()?) {
+      Ok(labels) => labels,
+      Err(_) => continue,
+    };
+    predicted.push(row.get(2));
+    targets.push(
+      labels
+        .iter()
+        .map(|l| train::target(algorithm, *l))
+        .collect(),
+    );
+  }
+  if predicted.is_empty() {
+    bail!("Model #{} has no settled predictions to score", entry.id);
+  }
+
+  Ok(
+    labeller
+      .names()
+      .iter()
+      .enumerate()
+      .map(|(i, name)| {
+        let column = |values: &[Vec<f32>]| -> Vec<f32> {
+          values.iter().map(|v| v[i]).collect()
+        };
+        Metrics::new(
+          name,
+          &column(&predicted),
+          &column(&targets),
+          algorithm == Algorithm::Logistic,
+        )
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::Estimator;
+  use crate::normalized::strat1;
+
+  const STEP: i64 = 15 * 60_000;
+
+  fn model() -> Model {
+    let strat: StratSpec = "4h:1h;1h:2:false".parse().unwrap();
+    let columns = strat1::columns(&strat);
+    Model {
+      symbol: "REGTEST".into(),
+      estimators: vec![Estimator::Linear {
+        weights: vec![0.; columns.len()],
+        bias: 0.,
+        logistic: false,
+      }],
+      columns,
+      strat,
+      label: "horizon:1h".parse().unwrap(),
+      rows: 1,
+      normalization: NormalizationConfig::default(),
+      scaler: None,
+    }
+  }
+
+  #[test]
+  fn models_are_registered_and_scored() -> Result<()> {
+    assert_eq!(latest("REGTEST")?, None);
+    let model = model();
+    let metrics = vec![Metrics::new("return", &[0.], &[0.], false)];
+    let first = register(&model, Algorithm::Linear, 0..10, 20..30, &metrics)?;
+    let second = register(&model, Algorithm::Linear, 0..20, 30..40, &metrics)?;
+    assert_eq!(latest("REGTEST")?, Some(second.clone()));
+    assert_eq!(latest_of("REGTEST", &model.strat)?, Some(second.clone()));
+    let other: StratSpec = "1d:1h;".parse()?;
+    assert_eq!(latest_of("REGTEST", &other)?, None);
+    assert_eq!(get(first.id)?, first);
+    assert_eq!(first.train, 0..10);
+    assert_eq!(first.metrics, metrics);
+    assert_eq!(first.load()?, model);
+    // a different layout is a different schema
+    assert_ne!(schema(&Model { rows: 2, ..model }), first.schema);
+
+    // a rise from 100 to 110 over the hour after the entry candle
+    let cursor = (now() - "1d".ms()).round(STEP);
+    let mut query = Query::new("REGTEST", PATH_INTERVAL);
+    for i in 0..=5 {
+      query.insert_candle(&Candle {
+        open_time: cursor + i * STEP,
+        close_time: cursor + (i + 1) * STEP - 1,
+        open: if i == 5 { 110. } else { 100. },
+        ..Default::default()
+      })?;
+    }
+    let prediction = |cursor: i64, value: f32| Prediction {
+      model: second.id,
+      symbol: "REGTEST".into(),
+      cursor,
+      close: 100.,
+      label: second.label.parse().unwrap(),
+      labels: vec!["return".into()],
+      values: vec![value],
+    };
+    assert!(score(&second).is_err());
+    log(&prediction(cursor, 0.2))?;
+    // once per cursor
+    log(&prediction(cursor, 0.5))?;
+    // not settled
+    log(&prediction(now().round(STEP), 0.3))?;
+
+    let scored = score(&second)?;
+    assert_eq!(scored[0].samples, 1);
+    assert!((scored[0].mae - 0.1).abs() < 1e-6);
+    assert_eq!(scored[0].hits, 1.);
+
+    fs::remove_dir_all(first.path().parent().unwrap())?;
+    Ok(())
+  }
+}
@@ -0,0 +1,576 @@
+use super::registry::{self, Entry};
+use super::{dot, sigmoid, Estimator, Model, Node, Tree};
+use crate::config::TrainConfig;
+use crate::normalized::folds::Manifest;
+use crate::normalized::{npy, strat1};
+use crate::prelude::*;
+use rayon::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// What `train` fits, an estimator for each label.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+  // least squares with weight decay
+  Linear,
+  // the chance the label is above 0
+  Logistic,
+  // gradient-boosted regression trees
+  Trees,
+}
+
+impl Algorithm {
+  pub const NAMES: [&'static str; 3] = ["linear", "logistic", "trees"];
+}
+
+impl FromStr for Algorithm {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    Ok(match input {
+      "linear" => Self::Linear,
+      "logistic" => Self::Logistic,
+      "trees" => Self::Trees,
+      _ => bail!(
+        "Unknown algorithm '{}', expected {}",
+        input,
+        Self::NAMES.join(", ")
+      ),
+    })
+  }
+}
+
+impl fmt::Display for Algorithm {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Self::Linear => "linear",
+      Self::Logistic => "logistic",
+      Self::Trees => "trees",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// How a model did on a fold's test samples, for one label.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Metrics {
+  pub label: String,
+  pub samples: usize,
+  // mean squared and absolute error against the target
+  pub mse: f32,
+  pub mae: f32,
+  // share of the target's variance explained
+  pub r2: f32,
+  // share of samples predicted on the right side of 0.5 by logistic models,
+  // or of 0 by the rest, which leave out labels at 0
+  pub hits: f32,
+}
+
+impl Metrics {
+  pub fn new(
+    label: &str,
+    predicted: &[f32],
+    targets: &[f32],
+    logistic: bool,
+  ) -> Self {
+    let n = targets.len() as f32;
+    let mean = targets.iter().sum::<f32>() / n;
+    let errors = predicted.iter().zip(targets).map(|(p, y)| p - y);
+    let mse = errors.clone().map(|e| e * e).sum::<f32>() / n;
+    let var = targets.iter().map(|y| (y - mean).powi(2)).sum::<f32>() / n;
+    let sides: Vec<bool> = predicted
+      .iter()
+      .zip(targets)
+      .filter_map(|(p, y)| match logistic {
+        true => Some((*p > 0.5) == (*y > 0.5)),
+        false if *y != 0. => Some((*p > 0.) == (*y > 0.)),
+        false => None,
+      })
+      .collect();
+
+    Self {
+      label: label.to_owned(),
+      samples: targets.len(),
+      mse,
+      mae: errors.map(f32::abs).sum::<f32>() / n,
+      r2: match var > 0. {
+        true => 1. - mse / var,
+        false => 0.,
+      },
+      hits: sides.iter().filter(|s| **s).count() as f32
+        / sides.len().max(1) as f32,
+    }
+  }
+
+  /// The mean of each metric over `folds`, which are for the same label.
+  pub fn mean(folds: &[Metrics]) -> Self {
+    let n = folds.len() as f32;
+    let mean = |f: fn(&Metrics) -> f32| folds.iter().map(f).sum::<f32>() / n;
+    Self {
+      label: folds[0].label.clone(),
+      samples: folds.iter().map(|m| m.samples).sum(),
+      mse: mean(|m| m.mse),
+      mae: mean(|m| m.mae),
+      r2: mean(|m| m.r2),
+      hits: mean(|m| m.hits),
+    }
+  }
+}
+
+impl fmt::Display for Metrics {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} samples, mse {:.6}, mae {:.6}, r2 {:.3}, hits {:.1}%",
+      self.label,
+      self.samples,
+      self.mse,
+      self.mae,
+      self.r2,
+      self.hits * 100.
+    )
+  }
+}
+
+/// Fit `algorithm` to each walk-forward fold of `symbol`'s `build_csv`
+/// export of `strat` and score it on the fold's test samples. The last fold's model,
+/// trained on the most history, is registered with the mean of each
+/// label's metrics.
+pub fn train(symbol: &str, strat: &str, algorithm: Algorithm) -> Result<Entry> {
+  let token = terminal::jobs::current();
+  let dir = strat1::dir(symbol, strat);
+  let manifest = Manifest::load(dir.join(strat1::MANIFEST))?;
+  let (shape, features) = npy::read::<f32>(dir.join(&manifest.features.file))?;
+  let (_, labels) = npy::read::<f32>(dir.join(&manifest.labels.file))?;
+  let len = shape[1..].iter().product::<usize>();
+  let names = &manifest.labels.columns;
+
+  let mut model = Model {
+    symbol: symbol.to_owned(),
+    strat: manifest.strat.parse()?,
+    label: manifest.label.parse()?,
+    columns: manifest.features.columns.clone(),
+    rows: shape[1],
+    normalization: manifest.normalization,
+    scaler: None,
+    estimators: vec![],
+  };
+  model.check_columns()?;
+
+  log!(
+    "Training {} on {} samples of {} values, {} folds",
+    algorithm,
+    shape[0],
+    len,
+    manifest.folds.len()
+  );
+
+  let mut folds = vec![];
+  for (n, fold) in manifest.folds.iter().enumerate() {
+    token.check()?;
+    let (train, test) = (&fold.train_rows, &fold.test_rows);
+    if train.is_empty() || test.is_empty() {
+      bail!("Fold {} has no training or no test samples", n + 1);
+    }
+    let samples = |rows: &Range<usize>| {
+      let mut values = features[rows.start * len..rows.end * len].to_vec();
+      if let Some(scaler) = &fold.scaler {
+        scaler.apply(&mut values);
+      }
+      values
+    };
+    let (train_x, test_x) = (samples(train), samples(test));
+    let targets = |rows: &Range<usize>, label: usize| -> Vec<f32> {
+      rows
+        .clone()
+        .map(|i| target(algorithm, labels[i * names.len() + label]))
+        .collect()
+    };
+
+    let mut estimators = vec![];
+    let mut metrics = vec![];
+    for (label, name) in names.iter().enumerate() {
+      let estimator = fit(
+        algorithm,
+        &train_x,
+        len,
+        &targets(train, label),
+        &CONFIG.train,
+      );
+      let predicted: Vec<f32> =
+        test_x.chunks(len).map(|x| estimator.predict(x)).collect();
+      metrics.push(Metrics::new(
+        name,
+        &predicted,
+        &targets(test, label),
+        algorithm == Algorithm::Logistic,
+      ));
+      estimators.push(estimator);
+    }
+
+    log!(
+      "Fold {}: tested from {} to {}",
+      n + 1,
+      fold.test_start,
+      fold.test_end
+    );
+    for m in &metrics {
+      log!("  {}", m);
+    }
+    folds.push(metrics);
+    model.estimators = estimators;
+    model.scaler = fold.scaler.clone();
+  }
+
+  log!(ok: "Mean over {} folds:", folds.len());
+  let means: Vec<Metrics> = (0..names.len())
+    .map(|label| {
+      let label: Vec<Metrics> =
+        folds.iter().map(|f| f[label].clone()).collect();
+      Metrics::mean(&label)
+    })
+    .collect();
+  for m in &means {
+    log!("  {}", m);
+  }
+
+  model.check()?;
+  let last = manifest.folds.last().unwrap();
+  let entry = registry::register(
+    &model,
+    algorithm,
+    last.train.clone(),
+    last.test.clone(),
+    &means,
+  )?;
+  log!(
+    "Registered the last fold's model as #{} at {}.",
+    entry.id,
+    entry.path().display()
+  );
+  Ok(entry)
+}
+
+/// What `algorithm` is fitted to for a label.
+pub fn target(algorithm: Algorithm, label: f32) -> f32 {
+  match algorithm {
+    Algorithm::Logistic => (label > 0.) as u8 as f32,
+    Algorithm::Linear | Algorithm::Trees => label,
+  }
+}
+
+/// Fit an estimator to `inputs`, samples of `len` values, and a target for
+/// each.
+pub fn fit(
+  algorithm: Algorithm,
+  inputs: &[f32],
+  len: usize,
+  targets: &[f32],
+  config: &TrainConfig,
+) -> Estimator {
+  match algorithm {
+    Algorithm::Linear => fit_linear(inputs, len, targets, false, config),
+    Algorithm::Logistic => fit_linear(inputs, len, targets, true, config),
+    Algorithm::Trees => fit_trees(inputs, len, targets, config),
+  }
+}
+
+// full-batch gradient descent on squared error, or log loss when logistic
+fn fit_linear(
+  inputs: &[f32],
+  len: usize,
+  targets: &[f32],
+  logistic: bool,
+  config: &TrainConfig,
+) -> Estimator {
+  let n = targets.len() as f32;
+  let mean = targets.iter().sum::<f32>() / n;
+  // longer samples take smaller steps, so descent doesn't diverge
+  let norm = dot(inputs, inputs) / n;
+  let step = config.learning_rate / (1. + norm);
+
+  let mut weights = vec![0.; len];
+  let mut bias = match logistic {
+    true => {
+      let p = mean.clamp(0.01, 0.99);
+      (p / (1. - p)).ln()
+    }
+    false => mean,
+  };
+  for _ in 0..config.epochs {
+    let (gradient, bias_gradient) = inputs
+      .par_chunks(len)
+      .zip(targets)
+      .fold(
+        || (vec![0.; len], 0.),
+        |(mut gradient, bias_gradient), (x, y)| {
+          let raw = bias + dot(&weights, x);
+          let error = match logistic {
+            true => sigmoid(raw),
+            false => raw,
+          } - y;
+          for (g, v) in gradient.iter_mut().zip(x) {
+            if v.is_finite() {
+              *g += error * v;
+            }
+          }
+          (gradient, bias_gradient + error)
+        },
+      )
+      .reduce(
+        || (vec![0.; len], 0.),
+        |(mut a, a_bias), (b, b_bias)| {
+          a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+          (a, a_bias + b_bias)
+        },
+      );
+    for (w, g) in weights.iter_mut().zip(gradient) {
+      *w -= step * (g / n + config.l2 * *w);
+    }
+    bias -= step * bias_gradient / n;
+  }
+
+  Estimator::Linear {
+    weights,
+    bias,
+    logistic,
+  }
+}
+
+// each tree is fitted to what the ones before it left unexplained
+fn fit_trees(
+  inputs: &[f32],
+  len: usize,
+  targets: &[f32],
+  config: &TrainConfig,
+) -> Estimator {
+  let n = targets.len();
+  let base = targets.iter().sum::<f32>() / n as f32;
+  // bins index into u8s
+  let bins = config.bins.clamp(2, 256);
+  let thresholds: Vec<Vec<f32>> = (0..len)
+    .into_par_iter()
+    .map(|j| thresholds(inputs.iter().skip(j).step_by(len), bins))
+    .collect();
+  let binned: Vec<u8> = inputs
+    .par_chunks(len)
+    .flat_map_iter(|x| x.iter().zip(&thresholds).map(|(v, t)| bin(*v, t)))
+    .collect();
+
+  let mut predicted = vec![base; n];
+  let mut trees = vec![];
+  for _ in 0..config.trees {
+    let residuals: Vec<f32> =
+      targets.iter().zip(&predicted).map(|(y, p)| y - p).collect();
+    let mut grower = Grower {
+      binned: &binned,
+      len,
+      thresholds: &thresholds,
+      residuals: &residuals,
+      config,
+      nodes: vec![],
+    };
+    grower.grow((0..n).collect(), 0);
+    let tree = Tree {
+      nodes: grower.nodes,
+    };
+    for (p, x) in predicted.iter_mut().zip(inputs.chunks(len)) {
+      *p += config.shrinkage * tree.eval(x);
+    }
+    trees.push(tree);
+  }
+
+  Estimator::Trees {
+    base,
+    learning_rate: config.shrinkage,
+    trees,
+    logistic: false,
+  }
+}
+
+// up to `bins - 1` sorted thresholds between quantiles of the finite values
+fn thresholds<'a>(
+  values: impl Iterator<Item = &'a f32>,
+  bins: usize,
+) -> Vec<f32> {
+  let mut sorted: Vec<f32> =
+    values.copied().filter(|v| v.is_finite()).collect();
+  if sorted.is_empty() {
+    return vec![];
+  }
+  sorted.sort_unstable_by(f32::total_cmp);
+  let mut thresholds: Vec<f32> = (1..bins)
+    .map(|k| sorted[(sorted.len() - 1) * k / bins])
+    .collect();
+  thresholds.dedup();
+  thresholds
+}
+
+// values at or below threshold k fall in bins up to k, and NaN in the last,
+// matching how `Tree::eval` goes left
+fn bin(value: f32, thresholds: &[f32]) -> u8 {
+  match value.is_nan() {
+    true => thresholds.len() as u8,
+    false => thresholds.partition_point(|t| *t < value) as u8,
+  }
+}
+
+struct Grower<'a> {
+  // each sample's bins, `len` to a sample
+  binned: &'a [u8],
+  len: usize,
+  thresholds: &'a [Vec<f32>],
+  residuals: &'a [f32],
+  config: &'a TrainConfig,
+  nodes: Vec<Node>,
+}
+
+impl Grower<'_> {
+  // add a node for `samples` and its children, returning its index
+  fn grow(&mut self, samples: Vec<usize>, depth: usize) -> usize {
+    let index = self.nodes.len();
+    let sum: f32 = samples.iter().map(|&i| self.residuals[i]).sum();
+    self.nodes.push(Node::Leaf(sum / samples.len() as f32));
+    if depth >= self.config.depth {
+      return index;
+    }
+
+    if let Some((input, bin)) = self.split(&samples, sum) {
+      let (left, right): (Vec<usize>, Vec<usize>) = samples
+        .into_iter()
+        .partition(|&i| self.binned[i * self.len + input] <= bin as u8);
+      let left = self.grow(left, depth + 1);
+      let right = self.grow(right, depth + 1);
+      self.nodes[index] = Node::Split {
+        input,
+        threshold: self.thresholds[input][bin],
+        left,
+        right,
+      };
+    }
+    index
+  }
+
+  // the input and bin that most reduce squared error, leaving each side
+  // with enough samples
+  fn split(&self, samples: &[usize], sum: f32) -> Option<(usize, usize)> {
+    let n = samples.len();
+    let min = self.config.min_leaf.max(1);
+    let before = sum * sum / n as f32;
+    (0..self.len)
+      .into_par_iter()
+      .filter_map(|input| {
+        let thresholds = &self.thresholds[input];
+        let mut sums = vec![(0f32, 0usize); thresholds.len() + 1];
+        for &i in samples {
+          let bin = &mut sums[self.binned[i * self.len + input] as usize];
+          bin.0 += self.residuals[i];
+          bin.1 += 1;
+        }
+
+        let (mut left_sum, mut left_n) = (0., 0);
+        let mut best: Option<(f32, usize)> = None;
+        for (bin, (s, c)) in sums[..thresholds.len()].iter().enumerate() {
+          left_sum += s;
+          left_n += c;
+          let right_n = n - left_n;
+          if left_n < min || right_n < min {
+            continue;
+          }
+          let right_sum = sum - left_sum;
+          let gain = left_sum * left_sum / left_n as f32
+            + right_sum * right_sum / right_n as f32
+            - before;
+          if gain > 1e-9 && best.is_none_or(|(g, _)| gain > g) {
+            best = Some((gain, bin));
+          }
+        }
+        best.map(|(gain, bin)| (gain, input, bin))
+      })
+      .max_by(|a, b| a.0.total_cmp(&b.0))
+      .map(|(_, input, bin)| (input, bin))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // samples of (x0, x1) on a grid
+  fn grid() -> Vec<f32> {
+    (0..100)
+      .flat_map(|i| [(i % 10) as f32 / 10., (i / 10) as f32 / 10.])
+      .collect()
+  }
+
+  fn config() -> TrainConfig {
+    TrainConfig {
+      epochs: 2000,
+      min_leaf: 5,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn linear_models_fit_lines() {
+    let inputs = grid();
+    let targets: Vec<f32> =
+      inputs.chunks(2).map(|x| 2. * x[0] - x[1] + 0.5).collect();
+    let linear = fit(Algorithm::Linear, &inputs, 2, &targets, &config());
+    let predicted: Vec<f32> =
+      inputs.chunks(2).map(|x| linear.predict(x)).collect();
+    let metrics = Metrics::new("return", &predicted, &targets, false);
+    assert!(metrics.mse < 1e-3, "{}", metrics);
+    assert!(metrics.r2 > 0.99, "{}", metrics);
+
+    // above 0 when x0 is over x1
+    let labels: Vec<f32> = inputs.chunks(2).map(|x| x[0] - x[1]).collect();
+    let targets: Vec<f32> = labels
+      .iter()
+      .map(|l| target(Algorithm::Logistic, *l))
+      .collect();
+    let logistic = fit(Algorithm::Logistic, &inputs, 2, &targets, &config());
+    assert!(logistic.predict(&[0.9, 0.1]) > 0.9);
+    assert!(logistic.predict(&[0.1, 0.9]) < 0.1);
+  }
+
+  #[test]
+  fn trees_fit_steps() {
+    let inputs = grid();
+    // x1 doesn't matter
+    let targets: Vec<f32> = inputs
+      .chunks(2)
+      .map(|x| match x[0] < 0.45 {
+        true => -1.,
+        false => 1.,
+      })
+      .collect();
+    let trees = fit(Algorithm::Trees, &inputs, 2, &targets, &config());
+    if let Estimator::Trees { trees: t, .. } = &trees {
+      t.iter().try_for_each(|t| t.check(2)).unwrap();
+      assert!(matches!(t[0].nodes[0], Node::Split { input: 0, .. }));
+    }
+    assert!((trees.predict(&[0.2, 0.7]) + 1.).abs() < 0.01);
+    assert!((trees.predict(&[0.7, 0.2]) - 1.).abs() < 0.01);
+
+    assert_eq!(bin(f32::NAN, &[1., 2.]), 2);
+    assert_eq!(bin(1., &[1., 2.]), 0);
+    assert_eq!(bin(1.5, &[1., 2.]), 1);
+  }
+
+  #[test]
+  fn metrics_score_test_samples() -> Result<()> {
+    let m = Metrics::new("return", &[0.5, -1., 2.], &[1., 0., -1.], false);
+    assert_eq!((m.mse, m.mae), (10.25 / 3., 4.5 / 3.));
+    // the label at 0 isn't a hit or a miss
+    assert_eq!(m.hits, 0.5);
+    let m = Metrics::new("up", &[0.9, 0.4, 0.6], &[1., 0., 0.], true);
+    assert!((m.hits - 2. / 3.).abs() < 1e-6);
+
+    for name in Algorithm::NAMES {
+      assert_eq!(name.parse::<Algorithm>()?.to_string(), name);
+    }
+    assert!("knn".parse::<Algorithm>().is_err());
+    Ok(())
+  }
+}
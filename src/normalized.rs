@@ -1,7 +1,19 @@
 use crate::prelude::*;
-
+use features::Window;
+
+pub mod features;
+pub mod folds;
+pub mod label;
+pub mod npy;
+pub mod preload;
+pub mod scale;
+pub mod spec;
 pub mod strat1;
 
+pub use label::Labeller;
+pub use preload::Preloaded;
+pub use spec::StratSpec;
+
 // Things to track...
 // 1. Distance from MA/EMA
 //   - On a percent of the price multiplied by a constant
@@ -12,29 +24,6 @@ pub mod strat1;
 //   - 4d of hourly
 //   - 2d of 15m
 
-// MAD - Moving Average Description
-#[derive(Clone)]
-pub struct MAD {
-  pub len: i32,
-  pub interval: String,
-  pub exp: bool,
-}
-impl From<&str> for MAD {
-  fn from(input: &str) -> Self {
-    let pieces: Vec<&str> = input.split(":").collect();
-    Self {
-      interval: pieces[0].parse().unwrap(),
-      len: pieces[1].parse().unwrap(),
-      exp: pieces[2].parse().unwrap(),
-    }
-  }
-}
-#[derive(Clone)]
-pub struct CandlesChunkDesc {
-  pub len: String,
-  pub interval: String,
-}
-
 #[derive(Clone)]
 pub struct Frame {
   ms: i64,
@@ -43,70 +32,58 @@ pub struct Frame {
   high: f32,
   low: f32,
   ma: Vec<f32>,
+  // the strat's features, in order
+  features: Vec<f32>,
 }
 
-pub trait StratStr<'a> {
-  fn to_components(&'a self) -> (Vec<&'a str>, Vec<MAD>);
-  fn load(&self, symbol: &str, cursor: i64) -> Result<Vec<Frame>>;
-  fn strat_len(&self) -> i64;
-}
-impl<'a> StratStr<'a> for &'a str {
-  fn to_components(&self) -> (Vec<&str>, Vec<MAD>) {
-    if let [candle_strat, ma_strat] =
-      (self.split(";").collect::<Vec<&str>>())[..]
-    {
-      let ma_strats: Vec<&str> = ma_strat.split(",").collect();
-      let moving_averages: Vec<MAD> =
-        ma_strats.into_iter().map(|ma| ma.into()).collect();
-      let candle_strats: Vec<&str> = candle_strat.split(",").collect();
+impl StratSpec {
+  /// The frames at `cursor`, from candles and MAs loaded for it, with the
+  /// candles before each window that the features want.
+  pub fn frames(&self, data: &Preloaded, cursor: i64) -> Result<Vec<Frame>> {
+    let mut frames = vec![];
+    let entry = self.entry(cursor);
+    // found once for the cursor, and only when a feature reads them
+    let levels = match self.wants_levels() {
+      true => data.levels(entry)?,
+      false => vec![],
+    };
 
-      (candle_strats, moving_averages)
-    } else {
-      panic!("Strat str is malformed");
-    }
-  }
-  fn strat_len(&self) -> i64 {
-    let (candle_strats, _) = self.to_components();
-    candle_strats.into_iter().fold(0, |acc, s| {
-      match (s.split(":").collect::<Vec<&str>>())[..] {
-        [len, _] => acc + len.ms(),
-        _ => unreachable!(),
+    for window in &self.windows {
+      let step = window.interval.ms();
+      let range = window.range(entry);
+      let candles = data.candles(&window.interval, range.clone());
+
+      // a query over the range includes the candle at both ends
+      if candles.len() != range.round(step).num_candles(step) + 1 {
+        bail!(
+          "{} of {} candles closed by {} are incomplete",
+          window.len,
+          window.interval,
+          entry.to_human()
+        );
       }
-    })
-  }
-  fn load(&self, symbol: &str, cursor: i64) -> Result<Vec<Frame>> {
-    let mut frames = vec![];
-    let (candle_strats, moving_averages) = self.to_components();
-    let cursor = cursor.round("15m");
 
-    for strat in candle_strats {
-      let [len, interval] = match (strat.split(":").collect::<Vec<&str>>())[..]
-      {
-        [a, b] => [a, b],
-        _ => bail!("Strat str is malformed."),
+      let history = (range.start - self.history() as i64 * step)..range.end;
+      let with_history = data.candles(&window.interval, history);
+      let window = Window {
+        candles: with_history,
+        history: with_history.len() - candles.len(),
+        step,
+        entry,
+        levels: &levels,
       };
-      let mut query = Query::new(symbol, interval);
-      let len = len.ms();
-
-      query.set_range((cursor - len)..cursor);
-      let candles = API.save_candles(&mut query)?;
-
-      // TODO: Figure out why this is so
-      if candles.len() - 1 != query.num_candles() {
-        bail!("Data is no good.");
-      }
+      let features: Vec<Vec<Vec<f32>>> =
+        self.features.iter().map(|f| f.values(&window)).collect();
 
-      for candle in candles {
-        let ma_prices: Vec<f32> = moving_averages
+      for (row, candle) in candles.iter().enumerate() {
+        let ma_prices = self
+          .moving_averages
           .iter()
-          .map(|ma| {
-            query
-              .ma_price(symbol, &ma.interval, candle.open_time, ma.len, ma.exp)
-              .expect("Do not have MA price.")
+          .map(|ma| match data.ma(ma, candle.open_time) {
+            Some(price) => Ok(price),
+            None => bail!("No {} at {}", ma, candle.open_time.to_human()),
           })
-          .collect();
-
-        assert_eq!(ma_prices.len(), moving_averages.len());
+          .collect::<Result<Vec<f32>>>()?;
 
         frames.push(Frame {
           ms: candle.open_time,
@@ -115,6 +92,7 @@ impl<'a> StratStr<'a> for &'a str {
           high: candle.high,
           low: candle.low,
           ma: ma_prices,
+          features: features.iter().flat_map(|f| f[row].clone()).collect(),
         });
       }
     }
@@ -122,34 +100,3 @@ impl<'a> StratStr<'a> for &'a str {
     Ok(frames)
   }
 }
-
-pub trait ExportData {
-  fn export(&self, file: &mut File, label: f32) -> Result<()>;
-}
-impl ExportData for Vec<Frame> {
-  fn export(&self, file: &mut File, label: f32) -> Result<()> {
-    let mut result = vec![];
-    for d in self {
-      let ma: Vec<String> = d.ma.iter().map(|ma| ma.to_string()).collect();
-
-      result.push(format!(
-        "{},{},{},{},{}",
-        d.open,
-        d.close,
-        d.high,
-        d.low,
-        ma.join(",")
-      ));
-    }
-
-    write!(file, "{}", result.join(","))?;
-    let label = match label {
-      l if l > 0.02 => "pos",
-      l if l < -0.02 => "neg",
-      _ => "flt",
-    };
-    writeln!(file, ",{}", label)?;
-
-    Ok(())
-  }
-}
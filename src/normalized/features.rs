@@ -0,0 +1,447 @@
+use crate::chart::{self, CandlePos, Candles};
+use crate::core::confluence::{self, Level};
+use crate::core::{strong_point, zone};
+use crate::prelude::*;
+use std::f32::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// distance reported for a side with nothing on it, as a share of the price
+const NOTHING_NEAR: f32 = 1.;
+
+/// Features a strat can add to each row of its frames, by name:
+///
+///   volume(:<n>)        volume over the mean of the last n, default 20
+///   log_return          log of the close over the previous close
+///   realized_vol(:<n>)  deviation of the last n log returns, default 20
+///   wick_ratio          `Candle::wick_ratio`
+///   domains             top and bottom domain as known at the entry
+///   strong_points       distance to the nearest strong point above and
+///                       below, over the close
+///   trend_lines         distance to the nearest trend-line resistance above
+///                       and support below, over the close
+///   rsi(:<n>)           relative strength index from 0 to 1, default 14
+///   atr(:<n>)           average true range over the close, default 14
+///   time                hour of day and day of week, as sin and cos
+///   confluence          distance and score of the nearest confluence level
+///                       above and below, see `confluence::features`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Feature {
+  Volume(usize),
+  LogReturn,
+  RealizedVol(usize),
+  WickRatio,
+  Domains,
+  StrongPoints,
+  TrendLines,
+  Rsi(usize),
+  Atr(usize),
+  Time,
+  Confluence,
+}
+
+/// One window of a frame as its features see it: `history` earlier
+/// candles, then a candle per row.
+pub struct Window<'a> {
+  pub candles: &'a [Candle],
+  pub history: usize,
+  pub step: i64,
+  // when the frame is entered, every candle in it has closed by then
+  pub entry: i64,
+  // confluence levels as they stood at the entry, found when a feature
+  // wants them
+  pub levels: &'a [Level],
+}
+
+const NAMES: &[&str] = &[
+  "volume",
+  "log_return",
+  "realized_vol",
+  "wick_ratio",
+  "domains",
+  "strong_points",
+  "trend_lines",
+  "rsi",
+  "atr",
+  "time",
+  "confluence",
+];
+
+impl FromStr for Feature {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    let (name, period) = match input.split_once(':') {
+      Some((name, period)) => match period.parse() {
+        Ok(p) if p > 1 => (name, Some(p)),
+        _ => bail!("'{}' is not a period of at least 2", period),
+      },
+      None => (input, None),
+    };
+    let feature = match (name, period) {
+      ("volume", p) => Self::Volume(p.unwrap_or(20)),
+      ("realized_vol", p) => Self::RealizedVol(p.unwrap_or(20)),
+      ("rsi", p) => Self::Rsi(p.unwrap_or(14)),
+      ("atr", p) => Self::Atr(p.unwrap_or(14)),
+      ("log_return", None) => Self::LogReturn,
+      ("wick_ratio", None) => Self::WickRatio,
+      ("domains", None) => Self::Domains,
+      ("strong_points", None) => Self::StrongPoints,
+      ("trend_lines", None) => Self::TrendLines,
+      ("time", None) => Self::Time,
+      ("confluence", None) => Self::Confluence,
+      (name, Some(_)) if NAMES.contains(&name) => {
+        bail!("{} doesn't take a period", name)
+      }
+      _ => bail!("Unknown feature, expected one of {}", NAMES.join(", ")),
+    };
+    Ok(feature)
+  }
+}
+
+impl fmt::Display for Feature {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let with_period =
+      |f: &mut fmt::Formatter<'_>, name, n, default| match n == default {
+        true => write!(f, "{}", name),
+        false => write!(f, "{}:{}", name, n),
+      };
+    match *self {
+      Self::Volume(n) => with_period(f, "volume", n, 20),
+      Self::RealizedVol(n) => with_period(f, "realized_vol", n, 20),
+      Self::Rsi(n) => with_period(f, "rsi", n, 14),
+      Self::Atr(n) => with_period(f, "atr", n, 14),
+      Self::LogReturn => write!(f, "log_return"),
+      Self::WickRatio => write!(f, "wick_ratio"),
+      Self::Domains => write!(f, "domains"),
+      Self::StrongPoints => write!(f, "strong_points"),
+      Self::TrendLines => write!(f, "trend_lines"),
+      Self::Time => write!(f, "time"),
+      Self::Confluence => write!(f, "confluence"),
+    }
+  }
+}
+
+impl Feature {
+  /// Column names of the values the feature adds to a row.
+  pub fn columns(&self) -> Vec<String> {
+    match self {
+      Self::Volume(n) => vec![format!("volume{}", n)],
+      Self::RealizedVol(n) => vec![format!("realized_vol{}", n)],
+      Self::Rsi(n) => vec![format!("rsi{}", n)],
+      Self::Atr(n) => vec![format!("atr{}", n)],
+      Self::LogReturn => vec!["log_return".into()],
+      Self::WickRatio => vec!["wick_ratio".into()],
+      Self::Domains => vec!["top_domain".into(), "bottom_domain".into()],
+      Self::StrongPoints => vec!["sp_above".into(), "sp_below".into()],
+      Self::TrendLines => vec!["tl_above".into(), "tl_below".into()],
+      Self::Time => ["hour_sin", "hour_cos", "weekday_sin", "weekday_cos"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect(),
+      Self::Confluence => {
+        ["cf_above", "cf_above_score", "cf_below", "cf_below_score"]
+          .iter()
+          .map(|c| c.to_string())
+          .collect()
+      }
+    }
+  }
+
+  /// Candles wanted before a row to compute it. Strong points and trend
+  /// lines make do with the window.
+  pub fn history(&self) -> usize {
+    match *self {
+      Self::Volume(n) | Self::RealizedVol(n) | Self::Rsi(n) | Self::Atr(n) => n,
+      Self::LogReturn => 1,
+      _ => 0,
+    }
+  }
+
+  /// The feature's values for each row of `window`.
+  pub fn values(&self, window: &Window) -> Vec<Vec<f32>> {
+    let candles = window.candles;
+    let rows = window.history..candles.len();
+    // the last n candles up to and including row i
+    let last = |i: usize, n: usize| &candles[(i + 1).saturating_sub(n)..=i];
+    let log_return = |i: usize| match i {
+      0 => 0.,
+      i => (candles[i].close / candles[i - 1].close).ln(),
+    };
+
+    match *self {
+      Self::Volume(n) => rows
+        .map(|i| {
+          let past = last(i, n);
+          let mean =
+            past.iter().map(|c| c.volume).sum::<f32>() / past.len() as f32;
+          vec![match mean > 0. {
+            true => candles[i].volume / mean,
+            false => 0.,
+          }]
+        })
+        .collect(),
+      Self::LogReturn => rows.map(|i| vec![log_return(i)]).collect(),
+      Self::RealizedVol(n) => rows
+        .map(|i| {
+          let returns: Vec<f32> = ((i + 1).saturating_sub(n).max(1)..=i)
+            .map(log_return)
+            .collect();
+          vec![deviation(&returns)]
+        })
+        .collect(),
+      Self::WickRatio => rows.map(|i| vec![candles[i].wick_ratio()]).collect(),
+      Self::Domains => known(window)[window.history..]
+        .iter()
+        .map(|c| vec![c.top_domain as f32, c.bottom_domain as f32])
+        .collect(),
+      Self::StrongPoints => {
+        let points: Vec<(CandlePos, (i64, f32))> =
+          strong_point::generate_points(&known(window))
+            .into_iter()
+            .map(|p| (p.position, (p.x, p.y)))
+            .collect();
+        rows
+          .map(|i| nearest(&points, &candles[i], |(_, p), _| *p))
+          .collect()
+      }
+      Self::TrendLines => {
+        let all = Arc::new(Candles::new(candles.to_vec()));
+        let lines =
+          chart::rank(chart::trend_lines_in(&all, &[]), &CONFIG.trend_lines);
+        let lines: Vec<(CandlePos, &chart::Line)> = lines
+          .iter()
+          .map(|l| (l.line.roots[0].candle_position, &l.line))
+          .collect();
+        // a line is placed once its second root is
+        rows
+          .map(|i| {
+            nearest(&lines, &candles[i], |(_, line), ms| {
+              (line.roots[1].x, line.y_at_x(ms))
+            })
+          })
+          .collect()
+      }
+      Self::Rsi(n) => rows
+        .map(|i| {
+          let (mut gains, mut losses) = (0., 0.);
+          for j in (i + 1).saturating_sub(n).max(1)..=i {
+            let change = candles[j].close - candles[j - 1].close;
+            match change > 0. {
+              true => gains += change,
+              false => losses -= change,
+            }
+          }
+          vec![match gains + losses > 0. {
+            true => gains / (gains + losses),
+            false => 0.5,
+          }]
+        })
+        .collect(),
+      Self::Atr(n) => rows
+        .map(|i| vec![zone::atr(&candles[..=i], n) / candles[i].close])
+        .collect(),
+      Self::Time => rows
+        .map(|i| {
+          let day = "1d".ms();
+          let ms = candles[i].open_time;
+          let hour = (ms % day) as f32 / day as f32;
+          // the epoch was a Thursday, so this counts from Monday
+          let weekday = ((ms / day + 3) % 7) as f32 / 7.;
+          vec![
+            (TAU * hour).sin(),
+            (TAU * hour).cos(),
+            (TAU * weekday).sin(),
+            (TAU * weekday).cos(),
+          ]
+        })
+        .collect(),
+      Self::Confluence => rows
+        .map(|i| confluence::features(window.levels, candles[i].close).to_vec())
+        .collect(),
+    }
+  }
+}
+
+fn deviation(values: &[f32]) -> f32 {
+  if values.is_empty() {
+    return 0.;
+  }
+  let n = values.len() as f32;
+  let mean = values.iter().sum::<f32>() / n;
+  (values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt()
+}
+
+/// The window's candles with domains clipped to the candles closed by the
+/// entry, so later prices don't leak into a frame.
+fn known(window: &Window) -> Vec<Candle> {
+  window
+    .candles
+    .iter()
+    .map(|c| {
+      let known = ((window.entry - c.open_time) / window.step).max(0) as i32;
+      Candle {
+        top_domain: c.top_domain.min(known),
+        bottom_domain: c.bottom_domain.min(known),
+        fuzzy_domain: c.fuzzy_domain
+          || c.top_domain > known
+          || c.bottom_domain > known,
+        ..c.clone()
+      }
+    })
+    .collect()
+}
+
+/// Distance from `candle`'s close to the nearest HIGH above and LOW below,
+/// of the items placed by `at`, which is given the candle's open time.
+/// Items placed after the candle are ignored.
+fn nearest<T>(
+  items: &[(CandlePos, T)],
+  candle: &Candle,
+  at: impl Fn(&(CandlePos, T), i64) -> (i64, f32),
+) -> Vec<f32> {
+  let close = candle.close;
+  let mut above = NOTHING_NEAR;
+  let mut below = NOTHING_NEAR;
+  for item in items {
+    let (x, y) = at(item, candle.open_time);
+    if x > candle.open_time {
+      continue;
+    }
+    let distance = (y - close).abs() / close;
+    match item.0 {
+      CandlePos::HIGH if y >= close => above = above.min(distance),
+      CandlePos::LOW if y <= close => below = below.min(distance),
+      _ => {}
+    }
+  }
+  vec![above, below]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HOUR: i64 = 3_600_000;
+
+  fn candles(closes: &[f32]) -> Vec<Candle> {
+    closes
+      .iter()
+      .enumerate()
+      .map(|(i, &close)| Candle {
+        open_time: i as i64 * HOUR,
+        open: close - 1.,
+        close,
+        high: close + 1.,
+        low: close - 3.,
+        volume: i as f32 + 1.,
+        ..Default::default()
+      })
+      .collect()
+  }
+
+  fn values(
+    feature: &str,
+    candles: &[Candle],
+    history: usize,
+  ) -> Vec<Vec<f32>> {
+    let window = Window {
+      candles,
+      history,
+      step: HOUR,
+      entry: candles.len() as i64 * HOUR,
+      levels: &[],
+    };
+    let feature: Feature = feature.parse().unwrap();
+    let values = feature.values(&window);
+    assert_eq!(values.len(), candles.len() - history);
+    for row in &values {
+      assert_eq!(row.len(), feature.columns().len());
+    }
+    values
+  }
+
+  #[test]
+  fn features_read_the_window_and_its_history() {
+    let rising = candles(&[10., 11., 12., 13., 14., 15.]);
+    // 5 over the mean of 3, 4 and 5, then 6 over 5
+    assert_eq!(values("volume:3", &rising, 4), vec![vec![1.25], vec![1.2]]);
+    assert_eq!(
+      values("log_return", &rising, 5),
+      vec![vec![(15f32 / 14.).ln()]]
+    );
+    assert_eq!(values("rsi", &rising, 3)[0], vec![1.]);
+    assert!((values("wick_ratio", &rising, 5)[0][0] + 1. / 3.).abs() < 1e-6);
+    // a true range of 4 every candle
+    assert_eq!(values("atr:3", &rising, 5)[0], vec![4. / 15.]);
+
+    let choppy = candles(&[10., 12., 10., 12., 10.]);
+    assert_eq!(values("rsi:4", &choppy, 4)[0], vec![0.5]);
+    let flat = candles(&[10., 10., 10.]);
+    assert_eq!(values("realized_vol:2", &flat, 2)[0], vec![0.]);
+    assert!(values("realized_vol:2", &choppy, 4)[0][0] > 0.);
+    for feature in ["strong_points", "trend_lines"] {
+      for side in values(feature, &choppy, 2).concat() {
+        assert!((0. ..=NOTHING_NEAR).contains(&side), "{}", feature);
+      }
+    }
+
+    // nothing found on either side
+    assert_eq!(values("confluence", &choppy, 4)[0], vec![1., 0., 1., 0.]);
+
+    // Monday 1970-01-05 at 06:00
+    let mut monday = candles(&[1.]);
+    monday[0].open_time = 4 * 24 * HOUR + 6 * HOUR;
+    let time = &values("time", &monday, 0)[0];
+    assert!((time[0] - 1.).abs() < 1e-6 && time[1].abs() < 1e-6);
+    assert!(time[2].abs() < 1e-6 && (time[3] - 1.).abs() < 1e-6);
+  }
+
+  #[test]
+  fn domains_are_only_as_large_as_the_entry_allows() {
+    let mut window = candles(&[10., 12., 11.]);
+    window[0].top_domain = 50;
+    window[1].bottom_domain = 1;
+    let domains = values("domains", &window, 0);
+    // the entry is 3 candles after the first opened
+    assert_eq!(domains, vec![vec![3., 0.], vec![0., 1.], vec![0., 0.]]);
+  }
+
+  #[test]
+  fn nearest_skips_later_items() {
+    let candle = &candles(&[100.])[0];
+    let items = [
+      (CandlePos::HIGH, (0, 110.)),
+      (CandlePos::HIGH, (0, 105.)),
+      (CandlePos::LOW, (0, 90.)),
+      // placed after the candle
+      (CandlePos::LOW, (HOUR, 99.)),
+      // resistance below the close isn't above it
+      (CandlePos::HIGH, (0, 95.)),
+    ];
+    let near = nearest(&items, candle, |(_, p), _| *p);
+    assert_eq!(near, vec![0.05, 0.1]);
+    assert_eq!(nearest(&items[..0], candle, |(_, p), _| *p), vec![1., 1.]);
+  }
+
+  #[test]
+  fn features_parse_and_print() -> Result<()> {
+    for s in [
+      "volume",
+      "volume:50",
+      "rsi",
+      "atr:7",
+      "time",
+      "trend_lines",
+      "confluence",
+    ] {
+      assert_eq!(s.parse::<Feature>()?.to_string(), s);
+    }
+    assert_eq!("rsi:14".parse::<Feature>()?.to_string(), "rsi");
+    for s in ["rsi:1", "rsi:x", "time:3", "macd"] {
+      assert!(s.parse::<Feature>().is_err(), "{}", s);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,167 @@
+use super::npy;
+use super::scale::Scaler;
+use crate::config::NormalizationConfig;
+use crate::prelude::*;
+
+/// Cursors to train on and the cursors the model is then tested on. Every
+/// training label is settled, plus a gap, before testing starts.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Fold {
+  pub train: Range<i64>,
+  pub test: Range<i64>,
+}
+
+/// Split `range` into `folds` expanding walk-forward folds. The range is cut
+/// into `folds + 1` blocks. Fold n trains on every block before block n + 1,
+/// less `gap`, and tests on block n + 1. `gap` should cover the label
+/// horizon, so no training label looks into the test block, plus any
+/// embargo. Block edges are rounded to `stride`.
+pub fn walk_forward(
+  range: Range<i64>,
+  folds: usize,
+  gap: i64,
+  stride: i64,
+) -> Result<Vec<Fold>> {
+  if folds == 0 || stride <= 0 {
+    bail!("Need at least one fold and a positive stride");
+  }
+  let block = (range.end - range.start) / (folds as i64 + 1) / stride * stride;
+  if block <= gap {
+    bail!(
+      "{} folds from {} to {} leave blocks no longer than the {}ms gap",
+      folds,
+      range.start.to_human(),
+      range.end.to_human(),
+      gap
+    );
+  }
+
+  Ok(
+    (1..=folds as i64)
+      .map(|n| {
+        let boundary = range.start + block * n;
+        Fold {
+          train: range.start..boundary - gap,
+          test: boundary..match n == folds as i64 {
+            true => range.end,
+            false => boundary + block,
+          },
+        }
+      })
+      .collect(),
+  )
+}
+
+/// Cursors in `range`, `stride` apart.
+pub fn cursors(range: &Range<i64>, stride: i64) -> impl Iterator<Item = i64> {
+  (range.start..range.end).step_by(stride as usize)
+}
+
+/// A fold as written to the manifest. The rows are the fold's samples in
+/// the exported arrays, which are sorted by cursor. Globally scaled
+/// features come with the scaler fitted to the fold's training samples.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FoldEntry {
+  pub train: Range<i64>,
+  pub test: Range<i64>,
+  pub train_start: String,
+  pub test_start: String,
+  pub test_end: String,
+  pub train_rows: Range<usize>,
+  pub test_rows: Range<usize>,
+  pub scaler: Option<Scaler>,
+}
+
+/// Written next to the exported arrays, so a trainer can load them and
+/// slice out each fold.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+  pub symbol: String,
+  pub strat: String,
+  pub label: String,
+  pub stride_ms: i64,
+  pub gap_ms: i64,
+  // samples x frame rows x features
+  pub features: npy::Desc,
+  // the cursor of each sample
+  pub timestamps: npy::Desc,
+  // samples x labels
+  pub labels: npy::Desc,
+  pub normalization: NormalizationConfig,
+  pub folds: Vec<FoldEntry>,
+}
+
+impl Manifest {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+      Ok(json) => Ok(serde_json::from_str(&json)?),
+      Err(_) => bail!("No export at {}, run build_csv first", path.display()),
+    }
+  }
+
+  /// `exported` holds the sorted cursors that made it into the arrays.
+  pub fn entry(fold: &Fold, exported: &[i64]) -> FoldEntry {
+    let rows = |r: &Range<i64>| {
+      exported.partition_point(|&c| c < r.start)
+        ..exported.partition_point(|&c| c < r.end)
+    };
+    FoldEntry {
+      train_start: fold.train.start.to_human(),
+      test_start: fold.test.start.to_human(),
+      test_end: fold.test.end.to_human(),
+      train_rows: rows(&fold.train),
+      test_rows: rows(&fold.test),
+      scaler: None,
+      train: fold.train.clone(),
+      test: fold.test.clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HOUR: i64 = 3_600_000;
+
+  #[test]
+  fn folds_walk_forward_with_a_gap() -> Result<()> {
+    let folds = walk_forward(0..100 * HOUR, 4, 8 * HOUR, HOUR)?;
+    assert_eq!(folds.len(), 4);
+    assert_eq!(folds[0].train, 0..12 * HOUR);
+    assert_eq!(folds[0].test, 20 * HOUR..40 * HOUR);
+    // the last fold tests up to the end of the range
+    assert_eq!(folds[3].train, 0..72 * HOUR);
+    assert_eq!(folds[3].test, 80 * HOUR..100 * HOUR);
+
+    for (i, fold) in folds.iter().enumerate() {
+      // an 8h label from the last training cursor ends before testing
+      let last_train = cursors(&fold.train, HOUR).last().unwrap();
+      assert!(last_train + 8 * HOUR < fold.test.start);
+      if let Some(next) = folds.get(i + 1) {
+        assert_eq!(fold.test.end, next.test.start);
+        assert!(next.train.end > fold.train.end);
+      }
+    }
+
+    assert_eq!(cursors(&(0..HOUR), 15 * 60_000).count(), 4);
+
+    // cursors 0h to 99h, with 30h to 49h missing
+    let exported: Vec<i64> = (0..100)
+      .filter(|h| !(30..50).contains(h))
+      .map(|h| h * HOUR)
+      .collect();
+    let entry = Manifest::entry(&folds[1], &exported);
+    assert_eq!(entry.train_rows, 0..30);
+    assert_eq!(entry.test_rows, 30..40);
+    Ok(())
+  }
+
+  #[test]
+  fn folds_need_room_for_the_gap() {
+    assert!(walk_forward(0..10 * HOUR, 4, 2 * HOUR, HOUR).is_err());
+    assert!(walk_forward(0..10 * HOUR, 0, 0, HOUR).is_err());
+    assert!(walk_forward(0..10 * HOUR, 1, 0, 0).is_err());
+  }
+}
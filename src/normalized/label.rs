@@ -0,0 +1,337 @@
+use crate::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Candles the price path after a cursor is read from.
+pub const PATH_INTERVAL: &str = "15m";
+
+/// What an exported frame is labelled with, looking forward from the close
+/// at its entry. Written as:
+///
+///   horizon:<duration>                   return after the duration
+///   barrier:<take profit>:<stop>:<duration>
+///                                        1 if price rose by take profit
+///                                        (percent) before falling by stop,
+///                                        -1 the other way, 0 if neither
+///                                        happened within the duration
+///   excursion:<duration>                 furthest rise and fall, as two
+///                                        labels
+///   profitable(:<duration>)              1 if a long held that long clears
+///                                        fees and min_profit, -1 if a
+///                                        short does, else 0. Defaults to
+///                                        trade_duration_ms, written out
+///                                        when exported
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Labeller {
+  Horizon(String),
+  TripleBarrier {
+    take_profit: f32,
+    stop: f32,
+    horizon: String,
+  },
+  Excursion(String),
+  Profitable(Option<String>),
+}
+
+/// Trading costs, as shares of the price.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Costs {
+  // paid on entry and exit
+  pub per_fill: f32,
+  pub min_profit: f32,
+}
+
+impl Costs {
+  pub fn from_config() -> Self {
+    Self {
+      per_fill: CONFIG.exchange_fee + CONFIG.transaction_slippage / 100.,
+      min_profit: CONFIG.min_profit / 100.,
+    }
+  }
+}
+
+fn duration(input: &str) -> Result<String> {
+  match input.try_ms() {
+    Ok(ms) if ms > 0 => Ok(input.to_owned()),
+    _ => bail!("'{}' is not a positive duration", input),
+  }
+}
+
+/// `ms` as a duration in the largest unit it's a whole number of.
+fn whole_duration(ms: i64) -> Result<String> {
+  match ["w", "d", "h", "m"]
+    .iter()
+    .map(|unit| (unit, format!("1{}", unit).ms()))
+    .find(|(_, unit_ms)| ms % unit_ms == 0)
+  {
+    Some((unit, unit_ms)) => duration(&format!("{}{}", ms / unit_ms, unit)),
+    None => bail!("{}ms is not a whole number of minutes", ms),
+  }
+}
+
+fn percent(input: &str) -> Result<f32> {
+  match input.parse::<f32>() {
+    Ok(p) if p > 0. => Ok(p),
+    _ => bail!("'{}' is not a positive percent", input),
+  }
+}
+
+impl FromStr for Labeller {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    let parts: Vec<&str> = input.split(':').collect();
+    let labeller = match parts[..] {
+      ["horizon", d] => Self::Horizon(duration(d)?),
+      ["barrier", tp, stop, d] => Self::TripleBarrier {
+        take_profit: percent(tp)?,
+        stop: percent(stop)?,
+        horizon: duration(d)?,
+      },
+      ["excursion", d] => Self::Excursion(duration(d)?),
+      ["profitable"] => Self::Profitable(None),
+      ["profitable", d] => Self::Profitable(Some(duration(d)?)),
+      _ => bail!(
+        "Unknown label '{}', expected horizon:<duration>, barrier:<take profit>:<stop>:<duration>, excursion:<duration> or profitable(:<duration>)",
+        input
+      ),
+    };
+    Ok(labeller)
+  }
+}
+
+impl TryFrom<String> for Labeller {
+  type Error = anyhow::Error;
+  fn try_from(input: String) -> Result<Self> { input.parse() }
+}
+
+impl From<Labeller> for String {
+  fn from(labeller: Labeller) -> Self { labeller.to_string() }
+}
+
+impl fmt::Display for Labeller {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Horizon(d) => write!(f, "horizon:{}", d),
+      Self::TripleBarrier {
+        take_profit,
+        stop,
+        horizon,
+      } => write!(f, "barrier:{}:{}:{}", take_profit, stop, horizon),
+      Self::Excursion(d) => write!(f, "excursion:{}", d),
+      Self::Profitable(None) => write!(f, "profitable"),
+      Self::Profitable(Some(d)) => write!(f, "profitable:{}", d),
+    }
+  }
+}
+
+impl Labeller {
+  /// Names of the labels `label` returns.
+  pub fn names(&self) -> Vec<String> {
+    let names: &[&str] = match self {
+      Self::Horizon(_) => &["return"],
+      Self::TripleBarrier { .. } => &["barrier"],
+      Self::Excursion(_) => &["max_rise", "max_fall"],
+      Self::Profitable(_) => &["profitable"],
+    };
+    names.iter().map(|n| n.to_string()).collect()
+  }
+
+  /// The labeller with `profitable`'s duration filled in from the config,
+  /// so what's exported with it says how it was labelled rather than
+  /// following the config later.
+  pub fn resolved(&self) -> Result<Self> {
+    Ok(match self {
+      Self::Profitable(None) => {
+        Self::Profitable(Some(whole_duration(CONFIG.trade_duration_ms)?))
+      }
+      labeller => labeller.clone(),
+    })
+  }
+
+  /// Whether the labels are changes in price, rather than classes.
+  pub fn is_return(&self) -> bool {
+    matches!(self, Self::Horizon(_) | Self::Excursion(_))
+  }
+
+  /// How far past the entry the label looks.
+  pub fn horizon_ms(&self) -> i64 {
+    match self {
+      Self::Horizon(d)
+      | Self::TripleBarrier { horizon: d, .. }
+      | Self::Excursion(d)
+      | Self::Profitable(Some(d)) => d.ms(),
+      Self::Profitable(None) => CONFIG.trade_duration_ms,
+    }
+  }
+
+  /// When the `PATH_INTERVAL` candles labelling a frame entered at `entry`
+  /// open: from the entry, so no candle the frame saw is read, to the
+  /// horizon after it.
+  pub fn path_range(&self, entry: i64) -> Range<i64> {
+    entry..(entry + self.horizon_ms())
+  }
+
+  /// Label the frame entered at `entry`, see `StratSpec::entry`, at
+  /// `close` from the candles in its `path_range`.
+  pub fn label(
+    &self,
+    entry: i64,
+    close: f32,
+    path: &[Candle],
+  ) -> Result<Vec<f32>> {
+    let range = self.path_range(entry);
+    if path.first().is_some_and(|c| c.open_time < range.start) {
+      bail!(
+        "The path labelling {} starts before the entry",
+        entry.to_human()
+      );
+    }
+    match path.last() {
+      Some(last) if last.open_time + PATH_INTERVAL.ms() > range.end => {}
+      _ => bail!(
+        "Can't label {}, {} candles only go to {}",
+        entry.to_human(),
+        PATH_INTERVAL,
+        path.last().map_or(entry, |c| c.open_time).to_human()
+      ),
+    }
+    Ok(self.label_path(close, path, Costs::from_config()))
+  }
+
+  /// The label from the candles following the entry, the last one open
+  /// at the horizon.
+  pub fn label_path(
+    &self,
+    close: f32,
+    path: &[Candle],
+    costs: Costs,
+  ) -> Vec<f32> {
+    let change = |price: f32| price / close - 1.;
+    // the price at the horizon is where the last candle opened
+    let exit = path.last().map_or(close, |c| c.open);
+    let before_exit = &path[..path.len().saturating_sub(1)];
+
+    match self {
+      Self::Horizon(_) => vec![change(exit)],
+      Self::TripleBarrier {
+        take_profit, stop, ..
+      } => {
+        let hit = before_exit.iter().find_map(|c| {
+          // both in one candle can't be ordered, so assume the worst
+          if change(c.low) <= -stop / 100. {
+            Some(-1.)
+          } else if change(c.high) >= take_profit / 100. {
+            Some(1.)
+          } else {
+            None
+          }
+        });
+        vec![hit.unwrap_or(0.)]
+      }
+      Self::Excursion(_) => {
+        let high = before_exit.iter().fold(close, |h, c| h.max(c.high));
+        let low = before_exit.iter().fold(close, |l, c| l.min(c.low));
+        vec![change(high), -change(low)]
+      }
+      Self::Profitable(_) => {
+        let fees = 2. * costs.per_fill;
+        let r = change(exit);
+        vec![match () {
+          _ if r - fees >= costs.min_profit => 1.,
+          _ if -r - fees >= costs.min_profit => -1.,
+          _ => 0.,
+        }]
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn path(prices: &[(f32, f32, f32)]) -> Vec<Candle> {
+    prices
+      .iter()
+      .enumerate()
+      .map(|(i, &(open, low, high))| Candle {
+        open_time: i as i64,
+        open,
+        low,
+        high,
+        ..Default::default()
+      })
+      .collect()
+  }
+
+  const COSTS: Costs = Costs {
+    per_fill: 0.001,
+    min_profit: 0.01,
+  };
+
+  fn label(labeller: &str, path: &[Candle]) -> Vec<f32> {
+    let labeller: Labeller = labeller.parse().unwrap();
+    labeller.label_path(100., path, COSTS)
+  }
+
+  #[test]
+  fn labels_read_the_path_after_the_cursor() -> Result<()> {
+    let rising = path(&[(100., 99., 103.), (102., 96., 106.), (125., 0., 0.)]);
+    assert_eq!(label("horizon:8h", &rising), vec![0.25]);
+    assert_eq!(label("barrier:5:5:8h", &rising), vec![1.]);
+    // both in the same candle counts as the stop
+    assert_eq!(label("barrier:5:3:8h", &rising), vec![-1.]);
+    assert_eq!(label("barrier:10:10:8h", &rising), vec![0.]);
+    // the last candle only gives the exit price
+    let excursion = label("excursion:8h", &rising);
+    assert_eq!(
+      excursion.len(),
+      "excursion:8h".parse::<Labeller>()?.names().len()
+    );
+    assert!((excursion[0] - 0.06).abs() < 1e-6);
+    assert!((excursion[1] - 0.04).abs() < 1e-6);
+
+    assert_eq!(label("profitable", &rising), vec![1.]);
+    let flat = path(&[(100., 99., 101.), (101., 0., 0.)]);
+    // 1% up doesn't cover fees as well as the minimum profit
+    assert_eq!(label("profitable:1d", &flat), vec![0.]);
+    let falling = path(&[(100., 90., 101.), (95., 0., 0.)]);
+    assert_eq!(label("profitable:1d", &falling), vec![-1.]);
+    Ok(())
+  }
+
+  #[test]
+  fn labellers_parse_and_print() -> Result<()> {
+    for s in [
+      "horizon:8h",
+      "barrier:2:1.5:1d",
+      "excursion:4h",
+      "profitable",
+      "profitable:12h",
+    ] {
+      assert_eq!(s.parse::<Labeller>()?.to_string(), s);
+    }
+    for s in [
+      "horizon",
+      "horizon:-8h",
+      "barrier:2:x:1d",
+      "pnl:8h",
+      // too long to count in milliseconds
+      "horizon:99999999999999d",
+      "profitable:99999999999999999999m",
+    ] {
+      assert!(s.parse::<Labeller>().is_err(), "{}", s);
+    }
+
+    let profitable = "profitable".parse::<Labeller>()?.resolved()?;
+    assert!(profitable.to_string().starts_with("profitable:"));
+    assert_eq!(profitable.horizon_ms(), CONFIG.trade_duration_ms);
+    assert_eq!(profitable.to_string().parse::<Labeller>()?, profitable);
+    assert_eq!(whole_duration("1d".ms())?, "1d");
+    assert_eq!(whole_duration("90m".ms())?, "90m");
+    assert!(whole_duration(1500).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,210 @@
+// Just enough of NumPy's .npy format (version 1.0) to stream exports out
+// and read them back: little endian, C order, one dtype per file.
+
+use crate::prelude::*;
+use std::io::{Read, Seek, SeekFrom};
+
+const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+// the header is padded to this, so it can be rewritten with the final shape
+const HEADER_LEN: usize = 128;
+
+pub trait Element: Copy {
+  const DESCR: &'static str;
+  const SIZE: usize;
+  fn write_le(&self, out: &mut Vec<u8>);
+  fn read_le(bytes: &[u8]) -> Self;
+}
+
+impl Element for f32 {
+  const DESCR: &'static str = "<f4";
+  const SIZE: usize = 4;
+  fn write_le(&self, out: &mut Vec<u8>) {
+    out.extend_from_slice(&self.to_le_bytes())
+  }
+  fn read_le(bytes: &[u8]) -> Self {
+    f32::from_le_bytes(bytes.try_into().unwrap())
+  }
+}
+
+impl Element for i64 {
+  const DESCR: &'static str = "<i8";
+  const SIZE: usize = 8;
+  fn write_le(&self, out: &mut Vec<u8>) {
+    out.extend_from_slice(&self.to_le_bytes())
+  }
+  fn read_le(bytes: &[u8]) -> Self {
+    i64::from_le_bytes(bytes.try_into().unwrap())
+  }
+}
+
+fn header<T: Element>(shape: &[usize]) -> Result<Vec<u8>> {
+  let shape = match shape {
+    [n] => format!("({},)", n),
+    _ => format!(
+      "({})",
+      shape
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+    ),
+  };
+  let dict = format!(
+    "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+    T::DESCR,
+    shape
+  );
+  let len = HEADER_LEN - MAGIC.len() - 2;
+  if dict.len() >= len {
+    bail!("Shape {} doesn't fit in an npy header", shape);
+  }
+
+  let mut header = MAGIC.to_vec();
+  header.extend_from_slice(&(len as u16).to_le_bytes());
+  header.extend_from_slice(format!("{:<1$}\n", dict, len - 1).as_bytes());
+  Ok(header)
+}
+
+/// How an exported array is laid out, for the manifest.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Desc {
+  pub file: String,
+  pub dtype: String,
+  pub shape: Vec<usize>,
+  // names of the values along the last axis
+  pub columns: Vec<String>,
+}
+
+/// Writes an array one item at a time along its first axis.
+pub struct Writer<T: Element> {
+  file: File,
+  // shape of each item
+  item: Vec<usize>,
+  count: usize,
+  buffer: Vec<u8>,
+  _element: std::marker::PhantomData<T>,
+}
+
+impl<T: Element> Writer<T> {
+  pub fn create(path: impl AsRef<Path>, item: &[usize]) -> Result<Self> {
+    let mut file = File::create(path)?;
+    file.write_all(&header::<T>(&[0])?)?;
+    Ok(Self {
+      file,
+      item: item.to_vec(),
+      count: 0,
+      buffer: vec![],
+      _element: std::marker::PhantomData,
+    })
+  }
+
+  pub fn push(&mut self, values: &[T]) -> Result<()> {
+    let len: usize = self.item.iter().product();
+    if values.len() != len {
+      bail!("Expected {} values per item, got {}", len, values.len());
+    }
+    self.buffer.clear();
+    for v in values {
+      v.write_le(&mut self.buffer);
+    }
+    self.file.write_all(&self.buffer)?;
+    self.count += 1;
+    Ok(())
+  }
+
+  /// Write the final shape into the header, returning it.
+  pub fn finish(mut self) -> Result<Vec<usize>> {
+    let mut shape = vec![self.count];
+    shape.extend(&self.item);
+    self.file.seek(SeekFrom::Start(0))?;
+    self.file.write_all(&header::<T>(&shape)?)?;
+    Ok(shape)
+  }
+
+  /// Finish the file and describe it.
+  pub fn describe(self, file: &str, columns: Vec<String>) -> Result<Desc> {
+    Ok(Desc {
+      file: file.to_owned(),
+      dtype: T::DESCR.to_owned(),
+      shape: self.finish()?,
+      columns,
+    })
+  }
+}
+
+/// Read an array written by `Writer`, as its shape and flat values.
+pub fn read<T: Element>(
+  path: impl AsRef<Path>,
+) -> Result<(Vec<usize>, Vec<T>)> {
+  let mut bytes = vec![];
+  File::open(path)?.read_to_end(&mut bytes)?;
+  if !bytes.starts_with(MAGIC) || bytes.len() < MAGIC.len() + 2 {
+    bail!("Not an npy 1.0 file");
+  }
+  let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+  let data = 10 + len;
+  let dict = match bytes.get(10..data) {
+    Some(dict) => String::from_utf8_lossy(dict),
+    None => bail!("Truncated npy header"),
+  };
+  if !dict.contains(&format!("'descr': '{}'", T::DESCR)) {
+    bail!("Expected {} values in npy header {}", T::DESCR, dict.trim());
+  }
+
+  let shape: Vec<usize> = match (dict.find("'shape': ("), dict.rfind(')')) {
+    (Some(start), Some(end)) => dict[start + 10..end]
+      .split(',')
+      .map(str::trim)
+      .filter(|d| !d.is_empty())
+      .map(|d| d.parse())
+      .collect::<std::result::Result<_, _>>()?,
+    _ => bail!("No shape in npy header {}", dict.trim()),
+  };
+  let values: Vec<T> = bytes[data..]
+    .chunks_exact(T::SIZE)
+    .map(T::read_le)
+    .collect();
+  if values.len() != shape.iter().product::<usize>() {
+    bail!("Shape {:?} doesn't match {} values", shape, values.len());
+  }
+  Ok((shape, values))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn arrays_round_trip() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("npy_{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+
+    let mut features = Writer::<f32>::create(dir.join("f.npy"), &[2, 3])?;
+    features.push(&[1., 2., 3., 4., 5., 6.])?;
+    features.push(&[-1., 0.5, 0., 1e9, f32::MIN, 7.])?;
+    assert!(features.push(&[1.]).is_err());
+    assert_eq!(features.finish()?, vec![2, 2, 3]);
+
+    let (shape, values) = read::<f32>(dir.join("f.npy"))?;
+    assert_eq!(shape, vec![2, 2, 3]);
+    assert_eq!(values[..6], [1., 2., 3., 4., 5., 6.]);
+    assert_eq!(values[9], 1e9);
+
+    let mut times = Writer::<i64>::create(dir.join("t.npy"), &[])?;
+    times.push(&[1_600_000_000_000])?;
+    assert_eq!(times.finish()?, vec![1]);
+    assert_eq!(
+      read::<i64>(dir.join("t.npy"))?,
+      (vec![1], vec![1_600_000_000_000])
+    );
+    assert!(read::<f32>(dir.join("t.npy")).is_err());
+
+    // numpy wants the data aligned after the header
+    let bytes = fs::read(dir.join("t.npy"))?;
+    assert_eq!(bytes.len(), HEADER_LEN + 8);
+    assert_eq!(bytes[HEADER_LEN - 1], b'\n');
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,293 @@
+use super::label::PATH_INTERVAL;
+use super::spec::{FRAME_INTERVAL, MAD};
+use super::{Labeller, StratSpec};
+use crate::core::confluence::{self, Level};
+use crate::prelude::*;
+
+/// The candles and moving averages a strat export reads, loaded once for
+/// all of its cursors so each frame, label and confluence level comes from
+/// memory rather than a query.
+#[derive(Default)]
+pub struct Preloaded {
+  // by interval, oldest first
+  candles: HashMap<String, Vec<Candle>>,
+  // (open time, value) by interval, len and exp, oldest first
+  mas: HashMap<(String, i32, bool), Vec<(i64, f32)>>,
+}
+
+impl Preloaded {
+  /// Load what `strat` needs for frames ending at each of the sorted
+  /// `cursors`, along with the path after them `labeller` reads. Candles
+  /// missing from the windows are fetched first.
+  pub fn load(
+    symbol: &str,
+    strat: &StratSpec,
+    labeller: Option<&Labeller>,
+    cursors: &[i64],
+  ) -> Result<Self> {
+    let (first, last) = match (cursors.first(), cursors.last()) {
+      (Some(first), Some(last)) => (*first, *last),
+      _ => return Ok(Self::default()),
+    };
+
+    let mut ranges: HashMap<String, Range<i64>> = HashMap::new();
+    let mut need = |interval: &str, range: Range<i64>| {
+      let r = ranges.entry(interval.to_owned()).or_insert(range.clone());
+      *r = r.start.min(range.start)..r.end.max(range.end);
+    };
+
+    for window in &strat.windows {
+      let history = strat.history() as i64 * window.interval.ms();
+      let range = (window.range(strat.entry(first)).start - history)
+        ..window.range(strat.entry(last)).end;
+      let mut query = Query::new(symbol, &window.interval);
+      query.set_range(range.clone());
+      API.save_candles(&mut query)?;
+      need(&window.interval, range);
+    }
+    if strat.wants_levels() {
+      let config = &CONFIG.confluence;
+      let step = FRAME_INTERVAL.ms();
+      let higher = config.intervals.iter().filter(|i| i.ms() > step);
+      for interval in higher.map(String::as_str).chain([FRAME_INTERVAL]) {
+        let step = interval.ms();
+        need(
+          interval,
+          (first - step * (config.lookback as i64 + 1))..last,
+        );
+      }
+    }
+    if let Some(labeller) = labeller {
+      let path = labeller.path_range(strat.entry(first)).start
+        ..labeller.path_range(strat.entry(last)).end;
+      need(PATH_INTERVAL, path);
+    }
+
+    let mut data = Self::default();
+    for (interval, range) in ranges {
+      let mut query = Query::new(symbol, &interval);
+      query.set_range(range);
+      data.candles.insert(interval, query.query_candles()?);
+    }
+
+    // an MA value holds until the next one
+    let reach = strat
+      .windows
+      .iter()
+      .map(|w| w.len.ms() + w.interval.ms())
+      .max()
+      .unwrap_or(0);
+    for ma in &strat.moving_averages {
+      let range = (first - reach - ma.interval.ms())..last;
+      let mut series: Vec<(i64, f32)> = MovingAverage::query(
+        symbol,
+        &ma.interval,
+        ma.len,
+        ma.exp,
+        Some(range),
+      )?
+      .iter()
+      .map(|m| (m.ms, m.val))
+      .collect();
+      series.sort_unstable_by_key(|(ms, _)| *ms);
+      data.mas.insert(key(ma), series);
+    }
+
+    Ok(data)
+  }
+
+  /// Candles of `interval` opening from `range.start` through `range.end`,
+  /// both rounded to the interval, as a `Query` over the range finds them.
+  pub fn candles(&self, interval: &str, range: Range<i64>) -> &[Candle] {
+    let candles = match self.candles.get(interval) {
+      Some(c) => c,
+      None => return &[],
+    };
+    let range = range.round(interval);
+    let start = candles.partition_point(|c| c.open_time < range.start);
+    let end = candles.partition_point(|c| c.open_time <= range.end);
+    &candles[start..end.max(start)]
+  }
+
+  /// The last `limit` candles of `interval` opening by `end`.
+  pub fn last(&self, interval: &str, end: i64, limit: usize) -> &[Candle] {
+    let candles = match self.candles.get(interval) {
+      Some(c) => c,
+      None => return &[],
+    };
+    let end = candles.partition_point(|c| c.open_time <= end.round(interval));
+    &candles[end.saturating_sub(limit)..end]
+  }
+
+  /// The value of `ma` at `ms`, from the last one calculated by then.
+  pub fn ma(&self, ma: &MAD, ms: i64) -> Option<f32> {
+    let series = self.mas.get(&key(ma))?;
+    match series.partition_point(|(t, _)| *t <= ms) {
+      0 => None,
+      i => Some(series[i - 1].1),
+    }
+  }
+
+  /// Confluence levels around frames entered at `entry`, as
+  /// `confluence::levels_at` finds them.
+  pub fn levels(&self, entry: i64) -> Result<Vec<Level>> {
+    let source = |interval: &str, end: i64, limit: usize| {
+      Ok(self.last(interval, end, limit).to_vec())
+    };
+    confluence::levels_from(&source, FRAME_INTERVAL, entry)
+  }
+
+  /// `labeller`'s labels for the frame entered at `entry`.
+  pub fn label(
+    &self,
+    labeller: &Labeller,
+    entry: i64,
+    close: f32,
+  ) -> Result<Vec<f32>> {
+    let path = self.candles(PATH_INTERVAL, labeller.path_range(entry));
+    labeller.label(entry, close, path)
+  }
+
+  #[cfg(test)]
+  fn with(
+    candles: Vec<(&str, Vec<Candle>)>,
+    mas: Vec<(&MAD, Vec<(i64, f32)>)>,
+  ) -> Self {
+    Self {
+      candles: candles
+        .into_iter()
+        .map(|(i, c)| (i.to_owned(), c))
+        .collect(),
+      mas: mas.into_iter().map(|(ma, s)| (key(ma), s)).collect(),
+    }
+  }
+}
+
+fn key(ma: &MAD) -> (String, i32, bool) {
+  (ma.interval.clone(), ma.len, ma.exp)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::normalized::Frame;
+
+  const HOUR: i64 = 3_600_000;
+
+  #[test]
+  fn windows_come_from_memory() -> Result<()> {
+    let candles: Vec<Candle> = (0..48)
+      .map(|i| Candle {
+        open_time: i * HOUR,
+        close: i as f32,
+        ..Default::default()
+      })
+      .collect();
+    let strat: StratSpec = "4h:1h;1h:2:false".parse()?;
+    let ma = &strat.moving_averages[0];
+    let data = Preloaded::with(
+      vec![("1h", candles)],
+      vec![(ma, (10..48).map(|i| (i * HOUR, i as f32 * 10.)).collect())],
+    );
+
+    // like a query, both ends are included and rounded down
+    let window = data.candles("1h", 20 * HOUR + 1..24 * HOUR + 30);
+    assert_eq!(window.len(), 5);
+    assert_eq!(
+      (window[0].open_time, window[4].open_time),
+      (20 * HOUR, 24 * HOUR)
+    );
+    assert!(data.candles("1h", 100 * HOUR..110 * HOUR).is_empty());
+    assert!(data.candles("4h", 0..HOUR).is_empty());
+    let last = data.last("1h", 10 * HOUR + 1, 3);
+    assert_eq!(
+      last.iter().map(|c| c.close).collect::<Vec<_>>(),
+      [8., 9., 10.]
+    );
+
+    assert_eq!(data.ma(ma, 12 * HOUR + 5), Some(120.));
+    assert_eq!(data.ma(ma, 5 * HOUR), None);
+
+    // the candle opening at the cursor closes after the entry
+    let frames = strat.frames(&data, 24 * HOUR)?;
+    assert_eq!(frames.len(), 5);
+    assert_eq!(frames[4].close, 23.);
+    assert_eq!(frames[4].ma, vec![230.]);
+    // frames need every MA value
+    assert!(strat.frames(&data, 12 * HOUR).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn frames_only_hold_candles_closed_by_the_entry() -> Result<()> {
+    let strat: StratSpec = "2h:15m,4h:1h,8h:4h;".parse()?;
+    let labeller: Labeller = "horizon:1h".parse()?;
+    let intervals = ["15m", "1h", "4h"];
+    let candles = intervals
+      .iter()
+      .map(|&interval| {
+        let step = interval.ms();
+        let candles = (0..48 * HOUR / step)
+          .map(|i| Candle {
+            open_time: i * step,
+            ..Default::default()
+          })
+          .collect();
+        (interval, candles)
+      })
+      .collect();
+    let data = Preloaded::with(candles, vec![]);
+
+    // a cursor inside the coarser candles
+    let cursor = 25 * HOUR + 30 * 60_000;
+    let entry = labeller.path_range(strat.entry(cursor)).start;
+    let frames = strat.frames(&data, cursor)?;
+    let mut rows = frames.iter();
+    for (window, interval) in strat.windows.iter().zip(intervals) {
+      let step = interval.ms();
+      let window: Vec<&Frame> = rows
+        .by_ref()
+        .take((window.len.ms() / step) as usize + 1)
+        .collect();
+      for frame in &window {
+        assert!(frame.ms + step <= entry, "{} leaks", interval);
+      }
+      // and the last one closed is there
+      assert!(window.last().unwrap().ms + 2 * step > entry, "{}", interval);
+    }
+    assert!(rows.next().is_none());
+    Ok(())
+  }
+
+  #[test]
+  fn labels_start_after_the_entry_candle() -> Result<()> {
+    const STEP: i64 = 15 * 60_000;
+    let cursor = 8 * STEP;
+    // the entry candle opens at the cursor and spikes, it's already closed
+    // at 100 when the frame is entered
+    let candles = (0..16)
+      .map(|i| Candle {
+        open_time: i * STEP,
+        open: if i == 13 { 110. } else { 100. },
+        low: 100.,
+        high: if i * STEP == cursor { 120. } else { 101. },
+        close: 100.,
+        ..Default::default()
+      })
+      .collect();
+    let data = Preloaded::with(vec![(PATH_INTERVAL, candles)], vec![]);
+    let entry = "1h:15m;".parse::<StratSpec>()?.entry(cursor);
+    assert_eq!(entry, cursor + STEP);
+
+    let label = |l: &str| data.label(&l.parse().unwrap(), entry, 100.);
+    assert_eq!(label("barrier:5:5:1h")?, vec![0.]);
+    assert!(label("excursion:1h")?[0] < 0.02);
+    // a full hour after the entry, not after the cursor
+    assert!((label("horizon:1h")?[0] - 0.1).abs() < 1e-6);
+
+    let labeller: Labeller = "horizon:1h".parse()?;
+    let with_entry = data.candles(PATH_INTERVAL, cursor..cursor + HOUR);
+    assert!(labeller.label(entry, 100., with_entry).is_err());
+    Ok(())
+  }
+}
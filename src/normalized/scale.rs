@@ -0,0 +1,285 @@
+use crate::prelude::*;
+
+/// How each feature column is scaled.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Method {
+  // from 0 at the smallest value to 1 at the largest
+  MinMax,
+  // standard deviations from the mean
+  ZScore,
+  // interquartile ranges from the median, so outliers don't set the scale
+  Robust,
+}
+
+/// What a scaler is fitted to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Scope {
+  // each frame on its own, so nothing carries over between frames
+  Window,
+  // a fold's training frames, then kept for predictions
+  Global,
+}
+
+/// A fitted transform, taking each value in a column to
+/// `(value - center) / scale`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Scaler {
+  pub method: Method,
+  pub columns: Vec<String>,
+  pub center: Vec<f32>,
+  pub scale: Vec<f32>,
+}
+
+impl Scaler {
+  /// Fit to `values`, rows of one value per column. Columns that don't
+  /// vary keep their scale, so nothing is divided by 0.
+  pub fn fit(method: Method, columns: Vec<String>, values: &[f32]) -> Self {
+    let mut fitter = Fitter::new(method, columns);
+    fitter.push(values);
+    fitter.fit()
+  }
+
+  pub fn apply(&self, values: &mut [f32]) {
+    for row in values.chunks_mut(self.columns.len()) {
+      for (i, v) in row.iter_mut().enumerate() {
+        *v = (*v - self.center[i]) / self.scale[i];
+      }
+    }
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    serde_json::to_writer_pretty(File::create(path)?, self)?;
+    Ok(())
+  }
+
+  /// Load a scaler saved by an export, checking it was fitted to
+  /// `columns`.
+  pub fn load(path: impl AsRef<Path>, columns: &[String]) -> Result<Self> {
+    let path = path.as_ref();
+    let scaler: Self = match fs::read_to_string(path) {
+      Ok(json) => serde_json::from_str(&json)?,
+      Err(_) => bail!("No scaler at {}, run build_csv first", path.display()),
+    };
+    if scaler.columns != columns {
+      bail!(
+        "The scaler at {} was fitted to other columns: {}",
+        path.display(),
+        scaler.columns.join(", ")
+      );
+    }
+    Ok(scaler)
+  }
+}
+
+// values a quantile sketch holds before it's compacted
+const SKETCH: usize = 4096;
+
+/// Fits a scaler to rows pushed a batch at a time, without keeping them.
+/// Min-max and z-score keep running sums. Robust keeps a sketch of each
+/// column, which is exact until it holds `SKETCH` values and approximate
+/// after.
+#[derive(Clone, Debug)]
+pub struct Fitter {
+  method: Method,
+  columns: Vec<String>,
+  stats: Vec<Stats>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Stats {
+  count: u64,
+  min: f32,
+  max: f32,
+  // Welford's running mean and sum of squared differences
+  mean: f64,
+  m2: f64,
+  // sorted (value, weight) pairs, see `compact`
+  sketch: Vec<(f32, u64)>,
+  // which of each pair `compact` keeps, alternated so neither end drifts
+  odd: bool,
+}
+
+impl Stats {
+  fn push(&mut self, method: Method, v: f32) {
+    match self.count {
+      0 => (self.min, self.max) = (v, v),
+      _ => (self.min, self.max) = (self.min.min(v), self.max.max(v)),
+    }
+    self.count += 1;
+    let delta = v as f64 - self.mean;
+    self.mean += delta / self.count as f64;
+    self.m2 += delta * (v as f64 - self.mean);
+    if method == Method::Robust {
+      self.sketch.push((v, 1));
+      if self.sketch.len() >= 2 * SKETCH {
+        self.compact();
+      }
+    }
+  }
+
+  // halve the sketch, merging neighbours into one value carrying both
+  // weights
+  fn compact(&mut self) {
+    self.sketch.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+    let keep = self.odd as usize;
+    self.sketch = self
+      .sketch
+      .chunks(2)
+      .map(|pair| match pair {
+        [a, b] => (pair[keep].0, a.1 + b.1),
+        _ => pair[0],
+      })
+      .collect();
+    self.odd = !self.odd;
+  }
+
+  // the value at `q` of the way through, like indexing the sorted values
+  fn quantile(sorted: &[(f32, u64)], total: u64, q: f32) -> f32 {
+    let rank = ((total - 1) as f32 * q).round() as u64;
+    let mut seen = 0;
+    for &(v, w) in sorted {
+      seen += w;
+      if seen > rank {
+        return v;
+      }
+    }
+    sorted[sorted.len() - 1].0
+  }
+
+  // (center, scale)
+  fn fit(&self, method: Method) -> (f32, f32) {
+    if self.count == 0 {
+      return (0., 1.);
+    }
+    match method {
+      Method::MinMax => (self.min, self.max - self.min),
+      Method::ZScore => (
+        self.mean as f32,
+        (self.m2 / self.count as f64).sqrt() as f32,
+      ),
+      Method::Robust => {
+        let mut sorted = self.sketch.clone();
+        sorted.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let quantile = |q| Self::quantile(&sorted, self.count, q);
+        (quantile(0.5), quantile(0.75) - quantile(0.25))
+      }
+    }
+  }
+}
+
+impl Fitter {
+  pub fn new(method: Method, columns: Vec<String>) -> Self {
+    Self {
+      method,
+      stats: vec![Stats::default(); columns.len()],
+      columns,
+    }
+  }
+
+  /// Add rows of one value per column. Values that aren't finite are left
+  /// out.
+  pub fn push(&mut self, values: &[f32]) {
+    for row in values.chunks(self.columns.len()) {
+      for (stats, &v) in self.stats.iter_mut().zip(row) {
+        if v.is_finite() {
+          stats.push(self.method, v);
+        }
+      }
+    }
+  }
+
+  /// A scaler fitted to the rows pushed so far.
+  pub fn fit(&self) -> Scaler {
+    let (center, scale) = self
+      .stats
+      .iter()
+      .map(|stats| {
+        let (c, s) = stats.fit(self.method);
+        (c, if s.is_finite() && s > 0. { s } else { 1. })
+      })
+      .unzip();
+    Scaler {
+      method: self.method,
+      columns: self.columns.clone(),
+      center,
+      scale,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn columns() -> Vec<String> { vec!["a".into(), "flat".into()] }
+
+  #[test]
+  fn scalers_fit_each_column() {
+    // rows of (a, flat)
+    let values = [1., 5., 2., 5., 3., 5., 4., 5., 100., 5.];
+
+    let min_max = Scaler::fit(Method::MinMax, columns(), &values);
+    assert_eq!(
+      (min_max.center, min_max.scale),
+      (vec![1., 5.], vec![99., 1.])
+    );
+
+    let z = Scaler::fit(Method::ZScore, columns(), &values);
+    assert_eq!(z.center, vec![22., 5.]);
+    // a column that doesn't vary isn't divided by 0
+    assert_eq!(z.scale[1], 1.);
+
+    // the outlier moves neither the median nor the quartiles
+    let robust = Scaler::fit(Method::Robust, columns(), &values);
+    assert_eq!(robust.center, vec![3., 5.]);
+    assert_eq!(robust.scale, vec![2., 1.]);
+
+    let mut scaled = values;
+    robust.apply(&mut scaled);
+    assert_eq!(scaled[..4], [-1., 0., -0.5, 0.]);
+
+    let empty = Scaler::fit(Method::ZScore, columns(), &[f32::NAN, 1.]);
+    assert_eq!((empty.center[0], empty.scale[0]), (0., 1.));
+  }
+
+  #[test]
+  fn scalers_only_load_for_their_columns() -> Result<()> {
+    let path =
+      std::env::temp_dir().join(format!("scaler_{}.json", std::process::id()));
+    let scaler = Scaler::fit(Method::ZScore, columns(), &[1., 2., 3., 4.]);
+    scaler.save(&path)?;
+
+    assert_eq!(Scaler::load(&path, &columns())?, scaler);
+    assert!(Scaler::load(&path, &["a".into()]).is_err());
+    fs::remove_file(&path)?;
+    assert!(Scaler::load(&path, &columns()).is_err());
+    Ok(())
+  }
+  #[test]
+  fn fitters_stream_in_batches() {
+    // 0 to 19999 out of order, past the size of the sketch
+    let values: Vec<f32> = (0..20_000)
+      .flat_map(|i| [(i * 7919 % 20_000) as f32, 5.])
+      .collect();
+    let fit = |method| {
+      let mut fitter = Fitter::new(method, columns());
+      for batch in values.chunks(2 * 300) {
+        fitter.push(batch);
+      }
+      let scaler = fitter.fit();
+      assert_eq!((scaler.center[1], scaler.scale[1]), (5., 1.));
+      (scaler.center[0], scaler.scale[0])
+    };
+
+    assert_eq!(fit(Method::MinMax), (0., 19_999.));
+    let (mean, sd) = fit(Method::ZScore);
+    assert_eq!(mean, 9_999.5);
+    assert!((sd - 5_773.5).abs() < 0.1);
+    // the quartiles are close, not exact
+    let (median, iqr) = fit(Method::Robust);
+    assert!((median - 10_000.).abs() < 50.);
+    assert!((iqr - 10_000.).abs() < 100.);
+  }
+}
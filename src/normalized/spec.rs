@@ -0,0 +1,319 @@
+use super::features::Feature;
+use crate::prelude::*;
+use crate::terminal::command::INTERVALS;
+use std::fmt;
+use std::str::FromStr;
+
+// Strat strings describe what goes into each exported frame:
+//
+//   spec     := windows ";" mas (";" features)
+//   windows  := window ("," window)*
+//   window   := length ":" interval      e.g. 52w:1w, 52 weeks of 1w candles
+//   mas      := "" | ma ("," ma)*
+//   ma       := interval ":" len ":" exp e.g. 4h:200:true, the 4h ema200
+//   features := feature ("," feature)*   e.g. volume,rsi:7,time, see
+//                                        `features::Feature`
+//
+// Lengths and intervals use the usual units (15m, 4h, 1d, 1w, 1M).
+
+/// Frames are cut at this interval, the candle of it opening at the cursor
+/// is the last one a frame is built from.
+pub const FRAME_INTERVAL: &str = "15m";
+
+/// A run of candles of one interval, ending at the last one closed when
+/// the frame is entered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CandlesChunkDesc {
+  pub len: String,
+  pub interval: String,
+}
+
+// MAD - Moving Average Description
+#[derive(Clone, Debug, PartialEq)]
+pub struct MAD {
+  pub len: i32,
+  pub interval: String,
+  pub exp: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct StratSpec {
+  pub windows: Vec<CandlesChunkDesc>,
+  pub moving_averages: Vec<MAD>,
+  pub features: Vec<Feature>,
+}
+
+fn interval(input: &str) -> Result<String> {
+  if !INTERVALS.contains(&input) {
+    bail!(
+      "'{}' is not an interval candles are kept for, expected one of {}",
+      input,
+      INTERVALS.join(", ")
+    );
+  }
+  Ok(input.to_owned())
+}
+
+impl CandlesChunkDesc {
+  /// The open times of the window's candles in a frame entered at `entry`,
+  /// ending with the last candle closed by then. Coarser candles opening
+  /// at the cursor close after the entry, so they're left out.
+  pub fn range(&self, entry: i64) -> Range<i64> {
+    let end = entry - self.interval.ms();
+    (end - self.len.ms())..end
+  }
+}
+
+impl FromStr for CandlesChunkDesc {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    let (len, interval_str) = match input.split_once(':') {
+      Some(parts) => parts,
+      None => bail!("expected <length>:<interval>"),
+    };
+    let chunk = Self {
+      interval: interval(interval_str)?,
+      len: len.to_owned(),
+    };
+    let (len_ms, step) = match len.try_ms() {
+      Ok(ms) if ms > 0 => (ms, chunk.interval.ms()),
+      _ => bail!("'{}' is not a positive length", len),
+    };
+    if len_ms < step || len_ms % step != 0 {
+      bail!("{} isn't a whole number of {} candles", len, interval_str);
+    }
+    Ok(chunk)
+  }
+}
+
+impl FromStr for MAD {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    let (interval_str, len, exp) = match input.split(':').collect::<Vec<_>>()[..]
+    {
+      [interval, len, exp] => (interval, len, exp),
+      _ => bail!("expected <interval>:<len>:<exp>"),
+    };
+    let len = match len.parse() {
+      Ok(len) if len > 1 => len,
+      _ => bail!("'{}' is not a moving average length", len),
+    };
+    let exp = match exp {
+      "true" => true,
+      "false" => false,
+      _ => bail!("exp must be true or false, got '{}'", exp),
+    };
+    Ok(Self {
+      interval: interval(interval_str)?,
+      len,
+      exp,
+    })
+  }
+}
+
+impl FromStr for StratSpec {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    let (windows, mas) = match input.split_once(';') {
+      Some(parts) => parts,
+      None => bail!("Strat '{}' needs a ';' between windows and MAs", input),
+    };
+    let (mas, features) = match mas.split_once(';') {
+      Some((mas, features)) => (mas, Some(features)),
+      None => (mas, None),
+    };
+
+    let windows = windows
+      .split(',')
+      .enumerate()
+      .map(|(i, w)| {
+        w.parse().map_err(|e| {
+          anyhow::anyhow!("Strat '{}', window {} '{}': {}", input, i + 1, w, e)
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let moving_averages = match mas {
+      "" => vec![],
+      mas => mas
+        .split(',')
+        .enumerate()
+        .map(|(i, ma)| {
+          ma.parse().map_err(|e| {
+            anyhow::anyhow!("Strat '{}', MA {} '{}': {}", input, i + 1, ma, e)
+          })
+        })
+        .collect::<Result<Vec<_>>>()?,
+    };
+    let features = match features {
+      None => vec![],
+      Some(features) => features
+        .split(',')
+        .enumerate()
+        .map(|(i, f)| {
+          f.parse().map_err(|e| {
+            anyhow::anyhow!(
+              "Strat '{}', feature {} '{}': {}",
+              input,
+              i + 1,
+              f,
+              e
+            )
+          })
+        })
+        .collect::<Result<Vec<_>>>()?,
+    };
+
+    Ok(Self {
+      windows,
+      moving_averages,
+      features,
+    })
+  }
+}
+
+impl TryFrom<String> for StratSpec {
+  type Error = anyhow::Error;
+  fn try_from(input: String) -> Result<Self> { input.parse() }
+}
+
+impl From<StratSpec> for String {
+  fn from(spec: StratSpec) -> Self { spec.to_string() }
+}
+
+impl fmt::Display for CandlesChunkDesc {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.len, self.interval)
+  }
+}
+
+impl fmt::Display for MAD {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}:{}", self.interval, self.len, self.exp)
+  }
+}
+
+impl fmt::Display for StratSpec {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let join = |parts: Vec<String>| parts.join(",");
+    write!(
+      f,
+      "{};{}",
+      join(self.windows.iter().map(|w| w.to_string()).collect()),
+      join(self.moving_averages.iter().map(|m| m.to_string()).collect())
+    )?;
+    if !self.features.is_empty() {
+      let features = self.features.iter().map(|f| f.to_string()).collect();
+      write!(f, ";{}", join(features))?;
+    }
+    Ok(())
+  }
+}
+
+impl StratSpec {
+  /// How far back the windows reach from the cursor.
+  pub fn len_ms(&self) -> i64 { self.windows.iter().map(|w| w.len.ms()).sum() }
+
+  /// When the frame at `cursor` is entered, at the close of the
+  /// `FRAME_INTERVAL` candle opening at the cursor.
+  pub fn entry(&self, cursor: i64) -> i64 {
+    cursor.round(FRAME_INTERVAL) + FRAME_INTERVAL.ms()
+  }
+
+  /// Candles the features want before each window.
+  pub fn history(&self) -> usize {
+    self
+      .features
+      .iter()
+      .map(Feature::history)
+      .max()
+      .unwrap_or(0)
+  }
+
+  /// Whether a feature reads confluence levels, which are costly to find.
+  pub fn wants_levels(&self) -> bool {
+    self.features.contains(&Feature::Confluence)
+  }
+
+  /// Check the moving averages are stored for `symbol`, since frames can't
+  /// be loaded without them.
+  pub fn check_available(&self, symbol: &str) -> Result<()> {
+    for ma in &self.moving_averages {
+      let available = MovingAverage::available(symbol, &ma.interval)?;
+      if !available.contains(&(ma.len, ma.exp)) {
+        bail!(
+          "{} {} {}{} isn't calculated yet",
+          symbol,
+          ma.interval,
+          if ma.exp { "ema" } else { "ma" },
+          ma.len
+        );
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STRAT1: &str =
+    "52w:1w,6w:1d,1w:4h,4d:1h,2d:15m;4h:200:true,1d:50:false";
+
+  #[test]
+  fn strat_strings_round_trip() -> Result<()> {
+    let spec: StratSpec = STRAT1.parse()?;
+    assert_eq!(spec.windows.len(), 5);
+    assert_eq!(
+      spec.moving_averages[0],
+      MAD {
+        interval: "4h".into(),
+        len: 200,
+        exp: true
+      }
+    );
+    assert_eq!(spec.to_string(), STRAT1);
+    assert_eq!(
+      spec.len_ms(),
+      "52w".ms() + "6w".ms() + "1w".ms() + "6d".ms()
+    );
+
+    let bare: StratSpec = "2d:15m;".parse()?;
+    assert!(bare.moving_averages.is_empty());
+    assert_eq!(bare.to_string(), "2d:15m;");
+
+    let featured: StratSpec = "2d:15m;;volume,rsi:7,time".parse()?;
+    assert_eq!(featured.features[1], Feature::Rsi(7));
+    assert_eq!(featured.history(), 20);
+    assert_eq!(featured.to_string(), "2d:15m;;volume,rsi:7,time");
+
+    let json = serde_json::to_string(&spec)?;
+    assert_eq!(serde_json::from_str::<StratSpec>(&json)?, spec);
+    Ok(())
+  }
+
+  #[test]
+  fn malformed_strats_say_what_is_wrong() {
+    let error = |s: &str| s.parse::<StratSpec>().unwrap_err().to_string();
+    assert!(error("52w:1w").contains("';'"));
+    assert!(error("52w:1w,6w;").contains("window 2 '6w'"));
+    assert!(error("52w:2w;").contains("'2w' is not an interval"));
+    assert!(error("1h:4h;").contains("whole number of 4h candles"));
+    assert!(error("90m:1h;").contains("whole number"));
+    assert!(error("xw:1h;").contains("'xw' is not a positive length"));
+    assert!(error("1d:1h;4h:200").contains("MA 1 '4h:200'"));
+    assert!(error("1d:1h;4h:one:true").contains("'one'"));
+    assert!(error("1d:1h;4h:200:yes").contains("'yes'"));
+    assert!(error("1d:1h;;rsi,macd").contains("feature 2 'macd'"));
+    assert!(error("1d:1h;;").contains("feature 1 ''"));
+    assert!(serde_json::from_str::<StratSpec>("\"1d:1h\"").is_err());
+    // too long to count in milliseconds
+    assert!(error("99999999999999d:1d;").contains("not a positive length"));
+    let overflowing = "\"99999999999999d:1d;4h:200:true\"";
+    assert!(serde_json::from_str::<StratSpec>(overflowing).is_err());
+  }
+}
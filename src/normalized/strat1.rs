@@ -1,10 +1,25 @@
-use super::{Frame, StratStr};
+use super::folds::{self, Manifest};
+use super::npy;
+use super::scale::{Fitter, Scaler, Scope};
+use super::{Frame, Labeller, Preloaded, StratSpec};
+use crate::config::NormalizationConfig;
 use crate::prelude::*;
+use rayon::prelude::*;
+
+const FEATURES: &str = "features.npy";
+const TIMESTAMPS: &str = "timestamps.npy";
+const LABELS: &str = "labels.npy";
+pub const MANIFEST: &str = "manifest.json";
+// fitted to the last fold's training samples when scaling globally
+const SCALER: &str = "scaler.json";
+// cursors built in parallel between writes and progress updates
+const CHUNK: usize = 256;
 
 /// dp: delta-price
 /// wm: wick-magnitude (ratio vs dp)
 /// wpp: wick-percent-positive
-/// ma: moving-average prices
+/// ma: moving-average prices over the close
+/// features: the strat's own, see `features::Feature`
 pub struct Row {
   ms: i64,
   // close price (not normalized)
@@ -17,122 +32,294 @@ pub struct Row {
   wpp: f32,
   // moving averages
   ma: Vec<f32>,
+  features: Vec<f32>,
 }
 
-trait WriteableRows {
-  fn write(&self, file: &mut File, label: f32) -> Result<()>;
-}
-impl WriteableRows for Vec<Row> {
-  fn write(&self, file: &mut File, label: f32) -> Result<()> {
-    for row in self {
-      let ma = row
-        .ma
-        .iter()
-        .map(|ma| ma.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-      writeln!(file, "{},{},{},{}", row.dp, row.wm, row.wpp, ma)?;
-    }
-    write!(file, "{}", label)?;
-    Ok(())
+impl Row {
+  /// The row's features, in the order of `columns`.
+  fn values(&self) -> impl Iterator<Item = f32> + '_ {
+    [self.dp, self.wm, self.wpp]
+      .into_iter()
+      .chain(self.ma.iter().copied())
+      .chain(self.features.iter().copied())
   }
 }
 
+/// Names of a row's features under `strat`.
+pub fn columns(strat: &StratSpec) -> Vec<String> {
+  let mut columns: Vec<String> =
+    ["dp", "wm", "wpp"].iter().map(|c| c.to_string()).collect();
+  columns.extend(strat.moving_averages.iter().map(|ma| {
+    format!(
+      "{}_{}{}",
+      ma.interval,
+      if ma.exp { "ema" } else { "ma" },
+      ma.len
+    )
+  }));
+  columns.extend(strat.features.iter().flat_map(|f| f.columns()));
+  columns
+}
+
+/// Where `export_all` writes `symbol`'s export of the strat called `name`.
+pub fn dir(symbol: &str, name: &str) -> PathBuf {
+  PathBuf::from(format!("builder/csv/{}/{}", symbol, name))
+}
+
+/// Export the frame at `cursor` of the strat called `name` as a row of
+/// values per line, labelled unless it's for a prediction. Features are
+/// scaled the way `export_all` scaled them.
 pub fn export_at(
-  strat: &str,
+  name: &str,
+  labeller: Option<&Labeller>,
   symbol: &str,
   cursor: i64,
   file: &mut File,
 ) -> Result<()> {
-  let frames = strat.load(symbol, cursor)?;
-  let mut result = convert(&frames)?;
-  normalize(&mut result)?;
+  let strat = CONFIG.strat(name)?;
+  let labeller = labeller.map(Labeller::resolved).transpose()?;
+  let labeller = labeller.as_ref();
+  let cursor = cursor.round("15m");
+  let columns = columns(strat);
+  let scaler = match CONFIG.normalization.scope {
+    Scope::Window => None,
+    Scope::Global => {
+      Some(Scaler::load(dir(symbol, name).join(SCALER), &columns)?)
+    }
+  };
+  let data = Preloaded::load(symbol, strat, labeller, &[cursor])?;
+  let result = rows(strat, &data, cursor)?;
+
+  let close = result.last().unwrap().close;
+  let labels = match labeller {
+    Some(l) => Some(data.label(l, strat.entry(cursor), close)?),
+    None => None,
+  };
+
+  let values =
+    scaled(&result, &columns, &CONFIG.normalization, scaler.as_ref());
+  for row in values.chunks(columns.len()) {
+    let row: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+    writeln!(file, "{}", row.join(","))?;
+  }
+  if let Some(labels) = labels {
+    let labels: Vec<String> = labels.iter().map(|l| l.to_string()).collect();
+    write!(file, "{}", labels.join(","))?;
+  }
 
-  let q = Query::new(symbol, "15m");
-  let label = q.price(cursor + "8h".ms()).expect("Could not get price.");
+  Ok(())
+}
 
-  let close = frames.last().unwrap().close;
-  let label = (label - close) / close;
+/// The frame ending at `cursor` as one sample of `export_all`'s features,
+/// flattened and scaled by `normalization` with `scaler`, for a model
+/// trained on them. Comes with the close at the cursor, which the sample's
+/// label would be measured from.
+pub fn sample_at(
+  strat: &StratSpec,
+  symbol: &str,
+  cursor: i64,
+  normalization: &NormalizationConfig,
+  scaler: Option<&Scaler>,
+) -> Result<(Vec<f32>, f32)> {
+  let cursor = cursor.round("15m");
+  let data = Preloaded::load(symbol, strat, None, &[cursor])?;
+  let result = rows(strat, &data, cursor)?;
+  let close = result.last().unwrap().close;
+  Ok((
+    scaled(&result, &columns(strat), normalization, scaler),
+    close,
+  ))
+}
 
-  result.write(file, label)?;
+/// The rows of the frame ending at `cursor`, before scaling.
+fn rows(strat: &StratSpec, data: &Preloaded, cursor: i64) -> Result<Vec<Row>> {
+  let frames = strat.frames(data, cursor)?;
+  convert(&frames)
+}
 
-  Ok(())
+/// The values of `rows`, scaled to themselves when scaling per window, or
+/// with `scaler` when it's given.
+fn scaled(
+  rows: &[Row],
+  columns: &[String],
+  normalization: &NormalizationConfig,
+  scaler: Option<&Scaler>,
+) -> Vec<f32> {
+  let mut values: Vec<f32> = rows.iter().flat_map(Row::values).collect();
+  match (normalization.scope, scaler) {
+    (Scope::Window, _) => {
+      Scaler::fit(normalization.method, columns.to_vec(), &values)
+        .apply(&mut values)
+    }
+    (Scope::Global, Some(scaler)) => scaler.apply(&mut values),
+    (Scope::Global, None) => {}
+  }
+  values
 }
 
-// data exported from here is not normalized
-pub fn export_all(strat: &str, symbol: &str) -> Result<()> {
+/// Export a frame for every cursor used by the walk-forward folds into
+/// `builder/csv/<symbol>/strat1` as NumPy arrays, sorted by cursor:
+/// `features.npy` (samples x rows x features), `timestamps.npy` (each
+/// sample's cursor) and `labels.npy` (samples x labels). `manifest.json`
+/// beside them names the columns and gives each fold's samples. Features
+/// scaled globally are left unscaled, with a scaler fitted to each fold's
+/// training samples in the manifest; the last fold's is also saved for
+/// `export_at`.
+pub fn export_all(name: &str, labeller: &Labeller, symbol: &str) -> Result<()> {
+  let token = terminal::jobs::current();
+  let strat = CONFIG.strat(name)?;
+  let labeller = &labeller.resolved()?;
+  strat.check_available(symbol)?;
+  let config = &CONFIG.walk_forward;
+  let stride = config.stride.try_ms()?;
+  // how far past a cursor its label's path reaches
+  let reach = labeller.path_range(strat.entry(0)).end;
+  let gap = reach + config.embargo.try_ms()?;
+
   let start = (now() - format!("{}d", CONFIG.history_start).ms()
-    + strat.strat_len())
+    + strat.len_ms())
   .round("1d");
-  let end = (now() - "2d".ms()).round("1d");
-  let train_end =
-    (((end as f64 - start as f64) * 0.75) as i64 + start).round("1d");
+  // labels need the candles up to their horizon
+  let end = (now() - reach - "1d".ms()).round(stride);
+  let folds = folds::walk_forward(start..end, config.folds, gap, stride)?;
 
-  let train_path =
-    PathBuf::from(format!("builder/csv/{}/strat1/train", symbol));
-  let _ = fs::remove_dir_all(train_path.parent().unwrap());
-  fs::create_dir_all(&train_path)?;
-  let test_path = train_path.parent().unwrap().join("test");
-  fs::create_dir_all(&test_path)?;
+  let dir = dir(symbol, name);
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir)?;
 
   log!(
-    "Start: {}, Training End: {}, End: {}",
+    "Start: {}, End: {}, {} folds, a frame every {}",
     start.to_human(),
-    train_end.to_human(),
-    end.to_human()
+    end.to_human(),
+    folds.len(),
+    config.stride
   );
 
-  let pb_label = "Exporting train CSV...".to_string();
-  let _ = terminal::PB.0.send((pb_label.clone(), 0.));
-  for cursor in (start..train_end).step_by("1d".ms() as usize) {
-    let pct = (cursor - start) as f64 / (train_end - start) as f64;
-    let _ = terminal::PB.0.send((pb_label.clone(), pct));
-
-    let frames = match strat.load(symbol, cursor) {
-      Ok(f) => f,
-      Err(e) => {
-        log!("Error: {:?}", e);
+  // the folds overlap, so each cursor is exported once
+  let mut wanted: Vec<i64> = folds
+    .iter()
+    .flat_map(|f| {
+      folds::cursors(&f.train, stride).chain(folds::cursors(&f.test, stride))
+    })
+    .collect();
+  wanted.sort_unstable();
+  wanted.dedup();
+
+  let mut exported = vec![];
+  let mut features: Option<npy::Writer<f32>> = None;
+  let mut timestamps = npy::Writer::<i64>::create(dir.join(TIMESTAMPS), &[])?;
+  let mut labels_out =
+    npy::Writer::<f32>::create(dir.join(LABELS), &[labeller.names().len()])?;
+  let columns = columns(strat);
+  // samples come sorted and every fold trains on a prefix of them, so each
+  // fold's scaler is fitted as the samples stream past its training cursors
+  let mut fitter = match CONFIG.normalization.scope {
+    Scope::Window => None,
+    Scope::Global => {
+      Some(Fitter::new(CONFIG.normalization.method, columns.clone()))
+    }
+  };
+  let mut scalers = vec![];
+
+  let data = Preloaded::load(symbol, strat, Some(labeller), &wanted)?;
+
+  let pb_label = "Exporting frames...".to_string();
+  pb(&pb_label, 0.);
+  for (i, chunk) in wanted.chunks(CHUNK).enumerate() {
+    if let Err(e) = token.check() {
+      // a partial export would pass for a complete but shorter dataset
+      let _ = fs::remove_dir_all(&dir);
+      return Err(e);
+    }
+    pb(&pb_label, (i * CHUNK) as f64 / wanted.len() as f64);
+
+    let samples = chunk
+      .par_iter()
+      .map(|&cursor| {
+        let result = match rows(strat, &data, cursor) {
+          Ok(r) => r,
+          Err(e) => {
+            log!(error: "{:?}", e);
+            return None;
+          }
+        };
+        let close = result.last().unwrap().close;
+        // e.g. candles missing from the label's path
+        let labels = match data.label(labeller, strat.entry(cursor), close) {
+          Ok(l) => l,
+          Err(e) => {
+            log!(error: "Skipping {}: {:?}", cursor.to_human(), e);
+            return None;
+          }
+        };
+        Some((cursor, result, labels))
+      })
+      .collect::<Vec<_>>();
+
+    for (cursor, result, labels) in samples.into_iter().flatten() {
+      // every sample has the shape of the first
+      let features = match &mut features {
+        Some(f) => f,
+        None => features.insert(npy::Writer::create(
+          dir.join(FEATURES),
+          &[result.len(), columns.len()],
+        )?),
+      };
+      let values = scaled(&result, &columns, &CONFIG.normalization, None);
+      if let Err(e) = features.push(&values) {
+        log!(error: "Skipping {}: {:?}", cursor.to_human(), e);
         continue;
       }
-    };
-    let mut result = convert(&frames)?;
-    normalize(&mut result)?;
-
-    let p = train_path.join(format!("{}.csv", cursor));
-    let mut file = File::create(&p)?;
-
-    let q = Query::new(symbol, "15m");
-    let label = q.price(cursor + "8h".ms()).expect("Could not get price.");
-
-    let close = frames.last().unwrap().close;
-    let label = (label - close) / close;
-
-    result.write(&mut file, label)?;
+      if let Some(fitter) = &mut fitter {
+        while folds
+          .get(scalers.len())
+          .is_some_and(|f| f.train.end <= cursor)
+        {
+          scalers.push(fitter.fit());
+        }
+        fitter.push(&values);
+      }
+      timestamps.push(&[cursor])?;
+      labels_out.push(&labels)?;
+      exported.push(cursor);
+    }
   }
-  let _ = terminal::PB.0.send((pb_label.clone(), -1.));
-
-  let pb_label = "Exporting test CSV...".to_string();
-  let _ = terminal::PB.0.send((pb_label.clone(), 0.));
-  for cursor in (train_end..end).step_by("1d".ms() as usize) {
-    let pct = (cursor - train_end) as f64 / (end - train_end) as f64;
-    let _ = terminal::PB.0.send((pb_label.clone(), pct));
-
-    let frames = strat.load(symbol, cursor)?;
-    let mut result = convert(&frames)?;
-    normalize(&mut result)?;
-
-    let p = test_path.join(format!("{}.csv", cursor));
-    let mut file = File::create(&p)?;
-
-    let q = Query::new(symbol, "15m");
-    let label = q.price(cursor + "8h".ms()).expect("Could not get price.");
-
-    let close = frames.last().unwrap().close;
-    let label = (label - close) / close;
-
-    result.write(&mut file, label)?;
+  pb(&pb_label, -1.);
+
+  let features = match features {
+    Some(f) => f,
+    None => bail!("No frames could be exported for {}", symbol),
+  };
+
+  let features = features.describe(FEATURES, columns)?;
+  let mut entries: Vec<_> = folds
+    .iter()
+    .map(|f| Manifest::entry(f, &exported))
+    .collect();
+  if let Some(fitter) = fitter {
+    scalers.resize_with(folds.len(), || fitter.fit());
+    if let Some(scaler) = scalers.last() {
+      scaler.save(dir.join(SCALER))?;
+    }
+    for (entry, scaler) in entries.iter_mut().zip(scalers) {
+      entry.scaler = Some(scaler);
+    }
   }
-  let _ = terminal::PB.0.send((pb_label.clone(), -1.));
+
+  let manifest = Manifest {
+    symbol: symbol.to_owned(),
+    strat: strat.to_string(),
+    label: labeller.to_string(),
+    stride_ms: stride,
+    gap_ms: gap,
+    features,
+    timestamps: timestamps.describe(TIMESTAMPS, vec!["cursor".into()])?,
+    labels: labels_out.describe(LABELS, labeller.names())?,
+    normalization: CONFIG.normalization,
+    folds: entries,
+  };
+  let file = File::create(dir.join(MANIFEST))?;
+  serde_json::to_writer_pretty(file, &manifest)?;
 
   Ok(())
 }
@@ -150,11 +337,11 @@ fn convert(frames: &Vec<Frame>) -> Result<Vec<Row>> {
     let wb = bl - f.low; // wick bottom: will be positive
     let wm = wt + wb; // wick magnitude
 
-    // TODO: risk of divide by 0.
-    let mut wpp = wt / wm;
-    if wpp.is_nan() {
-      wpp = 0.;
-    }
+    // a candle without wicks has none on top
+    let wpp = match wm > 0. {
+      true => wt / wm,
+      false => 0.,
+    };
 
     let dp = f.close - frames[i - 1].close;
 
@@ -164,26 +351,10 @@ fn convert(frames: &Vec<Frame>) -> Result<Vec<Row>> {
       dp,
       wm,
       wpp,
-      ma: f.ma.clone(),
+      ma: f.ma.iter().map(|ma| ma / f.close).collect(),
+      features: f.features.clone(),
     })
   }
 
   Ok(result)
 }
-
-/// normalize delta-price data on a scale from 1 to -1
-/// normalize moving-average data to percent of price
-fn normalize(rows: &mut Vec<Row>) -> Result<()> {
-  let r = &rows[0];
-  let max = rows.iter().fold(r.dp.abs(), |max, r| r.dp.max(max.abs()));
-
-  for r in rows {
-    for i in 0..r.ma.len() {
-      r.ma[i] = r.ma[i] / r.close;
-    }
-    r.dp = r.dp / max;
-    r.wm = r.wm / max;
-  }
-
-  Ok(())
-}
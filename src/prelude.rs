@@ -35,16 +35,40 @@ lazy_static! {
   pub static ref API: Api = Binance::new();
 }
 
+/// `log!("..")` logs at info level; prefix the format string with
+/// `debug:`, `warn:` or `error:` for other levels, or `ok:` to highlight
+/// a success.
 macro_rules! log {
-  ($($arg:tt)*) => {
+  (debug: $($arg:tt)*) => { log!(@ Debug, $($arg)*) };
+  (warn: $($arg:tt)*) => { log!(@ Warn, $($arg)*) };
+  (error: $($arg:tt)*) => { log!(@ Error, $($arg)*) };
+  (ok: $($arg:tt)*) => {
     #[cfg(test)]
     println!($($arg)*);
     #[cfg(not(test))]
-    crate::terminal::log(format!($($arg)*));
+    crate::terminal::logs::log(
+      crate::terminal::logs::Record::new(
+        crate::terminal::logs::Level::Info,
+        module_path!(),
+        format!($($arg)*),
+      )
+      .ok(),
+    );
   };
+  (@ $level:ident, $($arg:tt)*) => {
+    #[cfg(test)]
+    println!($($arg)*);
+    #[cfg(not(test))]
+    crate::terminal::logs::log(crate::terminal::logs::Record::new(
+      crate::terminal::logs::Level::$level,
+      module_path!(),
+      format!($($arg)*),
+    ));
+  };
+  ($($arg:tt)*) => { log!(@ Info, $($arg)*) };
 }
 pub fn pb(label: impl AsRef<str>, pct: f64) {
-  let _ = terminal::PB.0.send((label.as_ref().to_string(), pct));
+  terminal::jobs::progress(label.as_ref(), pct);
 }
 
 pub trait Candles {
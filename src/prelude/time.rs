@@ -1,4 +1,4 @@
-use chrono;
+use anyhow::{bail, Result};
 use chrono::Datelike;
 use chrono::{prelude::*, Utc};
 use regex::Regex;
@@ -13,6 +13,8 @@ lazy_static! {
 pub trait AsMs {
   fn ms(&self) -> i64;
   fn ago(&self) -> i64;
+  fn try_ms(&self) -> Result<i64> { Ok(self.ms()) }
+  fn try_ago(&self) -> Result<i64> { Ok(self.ago()) }
 }
 
 impl AsMs for i64 {
@@ -27,59 +29,94 @@ impl AsMs for DateTime<Utc> {
 impl AsMs for String {
   fn ms(&self) -> i64 { self.as_str().ms() }
   fn ago(&self) -> i64 { self.as_str().ago() }
+  fn try_ms(&self) -> Result<i64> { self.as_str().try_ms() }
+  fn try_ago(&self) -> Result<i64> { self.as_str().try_ago() }
 }
 
 impl AsMs for &str {
   fn ms(&self) -> i64 {
-    let caps = RE_INTERVAL.captures(self).unwrap();
-    let q: u128 = caps["n"].parse().unwrap_or(1);
+    match self.try_ms() {
+      Ok(ms) => ms,
+      Err(e) => panic!("{}", e),
+    }
+  }
+  fn ago(&self) -> i64 {
+    match self.try_ago() {
+      Ok(ms) => ms,
+      Err(e) => panic!("{}", e),
+    }
+  }
+  fn try_ms(&self) -> Result<i64> {
+    let caps = interval_captures(self)?;
+    let q: i64 = caps["n"].parse()?;
 
-    // get self in seconds
-    let milliseconds = match &caps["unit"] {
+    // get self in minutes
+    let minutes: i64 = match &caps["unit"] {
       "m" => 1,
       "h" => 60,
       "d" => 60 * 24,
       "w" => 60 * 24 * 7,
       "M" => 60 * 24 * 30,
       "y" => 60 * 24 * 365,
-      v => panic!("{} is not a supported step", v),
-    } * q as i64
-      * 60000;
-
-    match self.chars().nth(0).unwrap() {
-      '-' => -milliseconds,
+      v => bail!("{} is not a supported step", v),
+    };
+    let milliseconds =
+      match minutes.checked_mul(q).and_then(|m| m.checked_mul(60000)) {
+        Some(ms) => ms,
+        None => bail!("'{}' is too long", self),
+      };
+
+    Ok(match self.chars().nth(0) {
+      Some('-') => -milliseconds,
       _ => milliseconds,
-    }
+    })
   }
-  fn ago(&self) -> i64 {
+  fn try_ago(&self) -> Result<i64> {
     let input = self.as_ref();
-    let caps = RE_INTERVAL.captures(input).unwrap();
+    let caps = interval_captures(input)?;
 
-    match &caps["unit"] {
+    let ago = match &caps["unit"] {
       "y" | "M" => {
-        let now = chrono::Utc::now();
-        let n: u32 = caps["n"].parse().unwrap();
-        let mut year = now.year() as u32;
-        let mut month = now.month() as i32;
-        match &caps["unit"] {
-          "y" => year -= n,
-          "M" => {
-            month -= n as i32;
-            // math problem can fix this..later
-            while month <= 0 {
-              month += 12;
-              year -= 1;
-            }
-          }
-          _ => unreachable!(),
-        }
-        Utc
-          .ymd(year as i32, month as u32, now.day())
-          .and_hms(now.hour(), now.minute(), 0)
-          .ms()
+        let n: u32 = caps["n"].parse()?;
+        let months = match &caps["unit"] {
+          "y" => n.checked_mul(12),
+          _ => Some(n),
+        };
+        months
+          .and_then(|months| months_before(Utc::now(), months))
+          .map(|date| date.ms())
       }
-      _ => now() - input.ms(),
+      _ => now().checked_sub(input.try_ms()?),
+    };
+    match ago {
+      Some(ms) if ms >= 0 => Ok(ms),
+      _ => bail!("'{}' reaches back before 1970", input),
+    }
+  }
+}
+
+/// The same day and minute `months` calendar months before `date`, clamped
+/// to the last day of the target month.
+fn months_before(date: DateTime<Utc>, months: u32) -> Option<DateTime<Utc>> {
+  let total = date.year() as i64 * 12 + date.month0() as i64 - months as i64;
+  let year = i32::try_from(total.div_euclid(12)).ok()?;
+  let month = total.rem_euclid(12) as u32 + 1;
+  let day = (28..=date.day())
+    .rev()
+    .find(|d| NaiveDate::from_ymd_opt(year, month, *d).is_some())
+    .unwrap_or(date.day());
+  Utc
+    .with_ymd_and_hms(year, month, day, date.hour(), date.minute(), 0)
+    .single()
+}
+
+/// Captures for a whole interval string such as `15m`, `4h` or `-1d`.
+fn interval_captures(input: &str) -> Result<regex::Captures<'_>> {
+  match RE_INTERVAL.captures(input) {
+    Some(caps) if caps[0].len() == input.trim_start_matches('-').len() => {
+      Ok(caps)
     }
+    _ => bail!("'{}' is not a valid interval (e.g. 15m, 4h, 1d)", input),
   }
 }
 
@@ -140,6 +177,40 @@ mod prelude_time_tests {
     assert_eq!(now.month(), one_y_ago.month());
   }
 
+  #[test]
+  fn malformed_intervals_are_errors() {
+    use crate::prelude::*;
+
+    assert_eq!("15m".try_ms().unwrap(), 15 * 60000);
+    assert_eq!("-1h".try_ms().unwrap(), -3600000);
+    assert!("abc".try_ms().is_err());
+    assert!("15".try_ms().is_err());
+    assert!("15mx".try_ms().is_err());
+    assert!("3q".try_ms().is_err());
+    assert!("".try_ago().is_err());
+    assert!("99999999999999d".try_ms().is_err());
+    assert!("99999999999999999999m".try_ms().is_err());
+    assert!("3000y".try_ago().is_err());
+  }
+
+  #[test]
+  fn months_are_clamped_to_the_target_month() {
+    use super::months_before;
+    use chrono::{TimeZone, Utc};
+
+    let march = Utc.with_ymd_and_hms(2024, 3, 31, 10, 15, 0).unwrap();
+    let feb = Utc.with_ymd_and_hms(2024, 2, 29, 10, 15, 0).unwrap();
+    assert_eq!(months_before(march, 1), Some(feb));
+    assert_eq!(
+      months_before(feb, 12),
+      Utc.with_ymd_and_hms(2023, 2, 28, 10, 15, 0).single()
+    );
+    assert_eq!(
+      months_before(march, 15),
+      Utc.with_ymd_and_hms(2022, 12, 31, 10, 15, 0).single()
+    );
+  }
+
   #[test]
   fn time_weeks() {
     use crate::prelude::*;
@@ -1,3 +1,4 @@
+use crate::chart::{self, CandlePos};
 use crate::prelude::*;
 
 pub fn build_cache(symbol: &str) -> Result<()> {
@@ -20,8 +21,158 @@ pub fn build_cache(symbol: &str) -> Result<()> {
 
   MovingAverage::calculate_ema(symbol, "4h", 200)?;
   MovingAverage::calculate_ma(symbol, "1d", 50)?;
+  for interval in ZONE_INTERVALS {
+    Zone::calculate(symbol, interval)?;
+  }
 
   log!("Cache built.");
 
   Ok(())
 }
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Signal {
+  pub interval: String,
+  pub name: String,
+  // percent distance from the latest close
+  pub value: f32,
+  pub note: String,
+}
+
+// candles scanned for strong points when looking for support / resistance
+const SIGNAL_LOOKBACK: usize = 500;
+// intervals zones are stored for
+const ZONE_INTERVALS: [&str; 4] = ["1w", "1d", "4h", "1h"];
+
+/// Where the latest close sits relative to stored moving averages, the
+/// nearest strong points, trend lines and stored zones, per interval.
+pub fn signals(symbol: &str) -> Result<Vec<Signal>> {
+  let mut signals = vec![];
+  let mut latest_close = None;
+
+  for interval in ["1w", "1d", "4h", "1h", "15m"] {
+    let mut q = Query::new(symbol, interval);
+    q.set_all(vec![End(now()), Limit(SIGNAL_LOOKBACK), Order(DESC)]);
+    let mut candles = q.query_candles()?;
+    candles.reverse();
+
+    let close = match candles.last() {
+      Some(c) => c.close,
+      None => continue,
+    };
+    latest_close = Some(close);
+    let pct = |v: f32| (v - close) / close * 100.;
+
+    for (len, exp) in MovingAverage::available(symbol, interval)? {
+      let ms = candles.last().unwrap().open_time;
+      if let Some(ma) = q.ma_price(symbol, interval, ms, len, exp) {
+        signals.push(Signal {
+          interval: interval.to_owned(),
+          name: format!("{}{}", if exp { "ema" } else { "ma" }, len),
+          value: pct(ma),
+          note: match close >= ma {
+            true => "close above".into(),
+            false => "close below".into(),
+          },
+        });
+      }
+    }
+
+    let points = strong_point::generate_points(&candles);
+    let resistance = points
+      .iter()
+      .filter(|p| p.y > close)
+      .min_by(|a, b| a.y.total_cmp(&b.y));
+    let support = points
+      .iter()
+      .filter(|p| p.y < close)
+      .max_by(|a, b| a.y.total_cmp(&b.y));
+    for (name, point) in [("resistance", resistance), ("support", support)] {
+      if let Some(p) = point {
+        signals.push(Signal {
+          interval: interval.to_owned(),
+          name: name.to_owned(),
+          value: pct(p.y),
+          note: format!("{} at {}", p.y, p.x.to_human()),
+        });
+      }
+    }
+
+    // where each trend line is at the latest candle
+    let ms = candles.last().unwrap().open_time;
+    let lines: Vec<(CandlePos, f32)> = chart::trend_lines(&q, candles)?
+      .iter()
+      .map(|l| (l.line.roots[0].candle_position, l.line.y_at_x(ms)))
+      .collect();
+    let resistance = lines
+      .iter()
+      .filter(|(pos, y)| *pos == CandlePos::HIGH && *y > close)
+      .map(|(_, y)| *y)
+      .min_by(f32::total_cmp);
+    let support = lines
+      .iter()
+      .filter(|(pos, y)| *pos == CandlePos::LOW && *y < close)
+      .map(|(_, y)| *y)
+      .max_by(f32::total_cmp);
+    for (name, y) in
+      [("trend resistance", resistance), ("trend support", support)]
+    {
+      if let Some(y) = y {
+        signals.push(Signal {
+          interval: interval.to_owned(),
+          name: name.to_owned(),
+          value: pct(y),
+          note: format!("line at {}", y),
+        });
+      }
+    }
+
+    let zones = Zone::query(symbol, interval)?;
+    let resistance = zones.iter().find(|z| z.low > close);
+    let support = zones.iter().rev().find(|z| z.high < close);
+    for (name, zone) in
+      [("zone resistance", resistance), ("zone support", support)]
+    {
+      if let Some(z) = zone {
+        signals.push(Signal {
+          interval: interval.to_owned(),
+          name: name.to_owned(),
+          value: pct(if z.low > close { z.low } else { z.high }),
+          note: format!("{} - {}, {} touches", z.low, z.high, z.touches),
+        });
+      }
+    }
+  }
+
+  // zones that several intervals agree on, around the latest close
+  if let Some(close) = latest_close {
+    for z in Zone::query_across(symbol, &ZONE_INTERVALS)? {
+      if z.intervals.len() > 1 && z.contains(close) {
+        signals.push(Signal {
+          interval: z.intervals.join(","),
+          name: "in zone".into(),
+          value: ((z.low + z.high) / 2. - close) / close * 100.,
+          note: format!("{} - {}, {} touches", z.low, z.high, z.touches),
+        });
+      }
+    }
+  }
+
+  // predicted in the background, a failing model shows up as a failed job
+  if let Some(p) = model::cached(symbol) {
+    // returns as percents, like the other signals
+    for (name, value) in p.labels.iter().zip(&p.values) {
+      signals.push(Signal {
+        interval: "15m".into(),
+        name: format!("model {}", name),
+        value: match p.label.is_return() {
+          true => value * 100.,
+          false => *value,
+        },
+        note: format!("{} at {}", p.label, p.cursor.to_human()),
+      });
+    }
+  }
+
+  Ok(signals)
+}
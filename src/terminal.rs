@@ -1,9 +1,15 @@
-mod command;
+mod chart;
+pub mod command;
+pub mod jobs;
+pub mod logs;
+mod panes;
 
 use crate::database;
 use crate::prelude::*;
 use anyhow::Result;
-use regex::Regex;
+use chart::{ChartPane, CHART};
+use logs::{Logs, FILTER};
+use panes::{SignalsPane, Tab};
 use std::{collections::VecDeque, io, thread, time::Duration};
 use termion::{
   event::Key,
@@ -18,7 +24,7 @@ use tui::{
   layout::{Constraint, Direction, Layout},
   style::{Color, Style},
   text::{Span, Spans},
-  widgets::{Block, Borders, List, ListItem, Paragraph},
+  widgets::{Block, Borders, List, ListItem, Paragraph, Tabs},
   Terminal as TuiTerminal,
 };
 
@@ -27,21 +33,14 @@ enum Event<I> {
   Tick,
 }
 
-pub fn log(msg: impl AsRef<str>) {
-  let _ = LOG.0.send(msg.as_ref().to_string());
-}
-
-lazy_static! {
-  static ref RE_FORMAT: Regex =
-    Regex::new(r"^(/(?P<fmt>[a-zA-Z]*)\s)?(?P<text>.+)$").unwrap();
-  pub static ref LOG: (Sender<String>, Receiver<String>) = unbounded();
-  pub static ref PB: (Sender<(String, f64)>, Receiver<(String, f64)>) =
-    unbounded();
-}
-
 pub struct Terminal {
   events: Receiver<Event<Key>>,
   input: String,
+  // completion candidates from the last tab press
+  candidates: Vec<&'static str>,
+  tab: Tab,
+  chart: ChartPane,
+  signals: SignalsPane,
 }
 
 impl Terminal {
@@ -68,10 +67,14 @@ impl Terminal {
     let mut t = Terminal {
       events,
       input: String::new(),
+      candidates: vec![],
+      tab: Tab::Logs,
+      chart: ChartPane::new(),
+      signals: SignalsPane::new(),
     };
 
     if let Err(e) = t.render_loop() {
-      log!("{:?}", e);
+      log!(error: "{:?}", e);
     };
   }
 
@@ -82,19 +85,31 @@ impl Terminal {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = TuiTerminal::new(backend)?;
 
-    let mut logs: VecDeque<String> = VecDeque::new();
+    let mut logs = Logs::default();
     let mut cmd_index = 0;
     let mut log_offset = 0;
 
-    let mut progress_bars: HashMap<String, f64> = HashMap::new();
-
     loop {
+      // progress of every running job
+      let progress_bars: Vec<(String, f64)> = jobs::list()
+        .into_iter()
+        .flat_map(|job| {
+          job.progress.into_iter().map(move |(label, p)| {
+            (format!("#{} {}: {}", job.id, job.name, label), p)
+          })
+        })
+        .collect();
+
+      let filtered = logs.filtered();
+      let num_logs = filtered.len();
+
       terminal.draw(|f| {
         let chunks = Layout::default()
           .direction(Direction::Vertical)
           // .margin(2)
           .constraints(
             [
+              Constraint::Length(1),
               Constraint::Length(1),
               Constraint::Length(3),
               Constraint::Length(progress_bars.len() as u16),
@@ -133,17 +148,43 @@ impl Terminal {
         ]);
         f.render_widget(Paragraph::new(stats), chunks[0]);
 
+        // ==============================
+        // Tabs
+        // ==============================
+        let titles = Tab::ALL
+          .iter()
+          .enumerate()
+          .map(|(i, t)| Spans::from(format!("F{} {}", i + 1, t.title())))
+          .collect();
+        let tabs = Tabs::new(titles)
+          .select(Tab::ALL.iter().position(|t| *t == self.tab).unwrap())
+          .highlight_style(
+            Style::default()
+              .fg(Color::Cyan)
+              .add_modifier(Modifier::BOLD),
+          );
+        f.render_widget(tabs, chunks[1]);
+
         // ==============================
         // Text input
         // ==============================
+        let hint = match self.candidates.len() {
+          0 => command::hint(&self.input),
+          _ => self.candidates.join(" | "),
+        };
         let input = Paragraph::new(self.input.as_ref())
           .style(Style::default())
-          .block(Block::default().borders(Borders::ALL).title("Input"));
+          .block(Block::default().borders(Borders::ALL).title(Spans::from(
+            vec![
+              Span::raw("Input "),
+              Span::styled(hint, Style::default().fg(Color::DarkGray)),
+            ],
+          )));
         f.set_cursor(
-          chunks[1].x + self.input.len() as u16 + 1,
-          chunks[1].y + 1,
+          chunks[2].x + self.input.len() as u16 + 1,
+          chunks[2].y + 1,
         );
-        f.render_widget(input, chunks[1]);
+        f.render_widget(input, chunks[2]);
 
         // ==============================
         // Progress bars
@@ -151,7 +192,7 @@ impl Terminal {
         let pb_chunks = Layout::default()
           .direction(Direction::Vertical)
           .constraints(vec![Constraint::Length(1); progress_bars.len()])
-          .split(chunks[2]);
+          .split(chunks[3]);
 
         for (i, (name, p)) in progress_bars.iter().enumerate() {
           let g = Gauge::default()
@@ -162,131 +203,174 @@ impl Terminal {
         }
 
         // ==============================
-        // Logs
+        // Body
         // ==============================
-        let mut logs: VecDeque<ListItem> = logs
+        let body = chunks[4];
+        match self.tab {
+          Tab::Chart => return self.chart.render(f, body),
+          Tab::Jobs => return panes::render_jobs(f, body),
+          Tab::Coverage => return panes::render_coverage(f, body),
+          Tab::Signals => return self.signals.render(f, body),
+          Tab::Logs => {}
+        }
+
+        let filter = FILTER.read().unwrap().clone();
+        let mut items: VecDeque<ListItem> = filtered
           .iter()
-          .rev()
-          .enumerate()
-          .rev()
           .skip(log_offset)
-          .take(chunks[3].height as usize)
-          .fold(VecDeque::new(), |mut vec, (i, l)| {
-            let i = i.to_string();
-            let caps = RE_FORMAT.captures(l).unwrap();
-            let fmt = caps.name("fmt").map_or("", |m| m.as_str());
-            let text =
-              caps.name("text").map_or("MISSING MESSAGE", |m| m.as_str());
+          .take(body.height as usize)
+          .fold(VecDeque::new(), |mut vec, r| {
+            let prefix =
+              format!("{} {:<5} {:<16}| ", r.time(), r.level, r.short_module());
+            let width =
+              (body.width as usize).saturating_sub(prefix.len()).max(1);
 
             // inneficient
-            let new_items: Vec<ListItem> = text
+            let lines: Vec<String> = r
+              .message
               .chars()
               .collect::<Vec<char>>()
-              .chunks(chunks[3].width as usize - (i.len() + 2))
+              .chunks(width)
               .map(|c| c.iter().collect::<String>())
-              .collect::<Vec<String>>()
-              .into_iter()
-              .map(|l| {
-                let mut style = Style::default();
-                for fmt in fmt.chars() {
-                  match fmt {
-                    'g' => style = style.fg(Color::Green),
-                    'b' => style = style.fg(Color::Blue),
-                    'y' => style = style.fg(Color::Yellow),
-                    'r' => style = style.fg(Color::Red),
-                    'B' => style = style.add_modifier(Modifier::BOLD),
-                    _ => {}
-                  }
-                }
-                let spans = Spans::from(vec![
-                  Span::raw(format!("{}| ", i)),
-                  Span::styled(l, style),
-                ]);
-                ListItem::new(spans)
-              })
               .collect();
-            vec.extend(new_items);
+            for l in lines {
+              vec.push_back(ListItem::new(Spans::from(vec![
+                Span::styled(
+                  prefix.clone(),
+                  Style::default().fg(Color::DarkGray),
+                ),
+                Span::styled(l, r.style),
+              ])));
+            }
             vec
           });
 
         if log_offset > 0 {
-          logs.push_front(ListItem::new(format!(
+          items.push_front(ListItem::new(format!(
             "--- More ({}) ---",
             log_offset
           )));
         }
-        logs.truncate(chunks[3].height as usize);
+        items.truncate(body.height as usize);
 
         f.render_widget(
-          List::new(logs)
-            .block(Block::default().borders(Borders::TOP).title("Logs")),
-          chunks[3],
+          List::new(items).block(
+            Block::default()
+              .borders(Borders::TOP)
+              .title(format!("Logs ({}, Ctrl-l level)", filter)),
+          ),
+          body,
         );
       })?;
 
-      for log in LOG.1.try_iter() {
-        logs.push_front(log);
-        logs.truncate(300);
+      logs.receive();
+      for (symbol, interval) in CHART.1.try_iter() {
+        self.chart.set(symbol, interval);
+        self.tab = Tab::Chart;
       }
-      for (name, p) in PB.1.try_iter() {
-        if p == -1. {
-          progress_bars.remove(&name);
-        } else {
-          progress_bars.insert(name, p);
+
+      match self.events.recv()? {
+        Event::Input(Key::Char('Q')) => {
+          drop(terminal);
+          std::process::exit(0);
         }
+        Event::Input(k) => {
+          self.candidates.clear();
+          self.handle_key(k, &mut cmd_index, &mut log_offset, num_logs)?
+        }
+        Event::Tick => {}
       }
+    }
+  }
 
-      match self.events.recv()? {
-        Event::Input(k) => match k {
-          Key::Char('Q') => {
-            drop(terminal);
-            std::process::exit(0);
-          }
-          Key::Ctrl('p') => {
-            let meta = Meta::load()?;
-            let cmd = meta.cmds.get(cmd_index);
-            if let Some(cmd) = cmd {
-              self.input = cmd.clone();
-              cmd_index += 1;
-            }
-          }
-          Key::Ctrl('n') => {
-            cmd_index = cmd_index.saturating_sub(1);
-            let meta = Meta::load()?;
-            let cmd = meta.cmds.get(cmd_index);
-            if let Some(cmd) = cmd {
-              self.input = cmd.clone();
-            }
-          }
-          // up
-          Key::Ctrl('u') => {
-            log_offset = log_offset.saturating_sub(1);
-          }
-          // down
-          Key::Ctrl('d') => {
-            log_offset = log_offset.saturating_add(1).min(logs.len());
-          }
-          Key::Char('\n') => {
-            let cmd = std::mem::replace(&mut self.input, String::new());
-            Meta::log_command(&cmd)?;
-            cmd_index = 0;
+  fn handle_key(
+    &mut self,
+    k: Key,
+    cmd_index: &mut usize,
+    log_offset: &mut usize,
+    num_logs: usize,
+  ) -> Result<()> {
+    match k {
+      Key::Ctrl('p') => {
+        let meta = Meta::load()?;
+        let cmd = meta.cmds.get(*cmd_index);
+        if let Some(cmd) = cmd {
+          self.input = cmd.clone();
+          *cmd_index += 1;
+        }
+      }
+      Key::Ctrl('n') => {
+        *cmd_index = cmd_index.saturating_sub(1);
+        let meta = Meta::load()?;
+        let cmd = meta.cmds.get(*cmd_index);
+        if let Some(cmd) = cmd {
+          self.input = cmd.clone();
+        }
+      }
+      // up
+      Key::Ctrl('u') => {
+        *log_offset = log_offset.saturating_sub(1);
+      }
+      // down
+      Key::Ctrl('d') => {
+        *log_offset = log_offset.saturating_add(1).min(num_logs);
+      }
+      Key::Char('\t') => {
+        let completion = command::complete(&self.input);
+        self.input = completion.input;
+        if completion.candidates.len() > 1 {
+          self.candidates = completion.candidates;
+        }
+      }
+      Key::Char('\n') => {
+        let cmd = std::mem::replace(&mut self.input, String::new());
+        Meta::log_command(&cmd)?;
+        *cmd_index = 0;
 
-            thread::spawn(move || {
-              if let Err(e) = command::parse_command(cmd) {
-                log!("Command: {:?}", e);
-              }
-            });
-          }
-          Key::Backspace => {
-            self.input.pop();
+        match command::spawn(&cmd) {
+          Ok(Some(id)) => {
+            log!(ok: "Command recognized, started job #{}.", id);
           }
-          Key::Char(ch) => {
-            self.input.push(ch);
+          Ok(None) => {}
+          Err(e) => {
+            log!(warn: "{}", e);
           }
-          _ => (),
-        },
-        Event::Tick => {}
+        }
+      }
+      Key::F(n @ 1..=5) => self.tab = Tab::ALL[n as usize - 1],
+      Key::Alt(n @ '1'..='5') => {
+        self.tab = Tab::ALL[n.to_digit(10).unwrap() as usize - 1]
+      }
+      Key::Ctrl('c') => match jobs::cancel_latest() {
+        Some(id) => {
+          log!(warn: "Cancelling job #{}.", id);
+        }
+        None => {
+          log!("No running commands to cancel.");
+        }
+      },
+      Key::Ctrl('l') => {
+        let mut filter = FILTER.write().unwrap();
+        filter.level = filter.level.next();
+      }
+      Key::Esc => self.tab = Tab::Logs,
+      Key::Left | Key::Right if self.tab == Tab::Chart => {
+        self.chart.pan(k == Key::Right)
+      }
+      Key::Up | Key::Down if self.tab == Tab::Chart => {
+        self.chart.zoom(k == Key::Up)
+      }
+      Key::PageUp | Key::PageDown if self.tab == Tab::Chart => {
+        self.chart.cycle_interval(k == Key::PageUp)
+      }
+      Key::Backspace => {
+        self.input.pop();
+      }
+      Key::Char(ch) => {
+        self.input.push(ch);
       }
+      _ => (),
     }
+    Ok(())
   }
 }
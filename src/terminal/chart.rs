@@ -0,0 +1,421 @@
+use super::command::INTERVALS;
+use super::jobs;
+use crate::chart::{self, TrendLine};
+use crate::core::strong_point::{generate_points, CandlePos};
+use crate::prelude::*;
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::time::Instant;
+use tui::{
+  backend::Backend,
+  layout::Rect,
+  style::{Color, Style},
+  text::{Span, Spans},
+  widgets::{
+    canvas::{Canvas, Line, Points},
+    Block, Borders, Paragraph,
+  },
+  Frame,
+};
+
+const MA_COLORS: [Color; 4] =
+  [Color::Magenta, Color::Cyan, Color::Blue, Color::LightYellow];
+const MIN_WIDTH: usize = 16;
+const MAX_WIDTH: usize = 2000;
+// how often to pull new candles while looking at the latest ones
+const REFRESH: Duration = Duration::from_secs(5);
+
+// (symbol, interval)
+type ChartRequest = (String, String);
+
+lazy_static! {
+  // requests from the `chart` command
+  pub static ref CHART: (Sender<ChartRequest>, Receiver<ChartRequest>) =
+    unbounded();
+}
+
+pub struct ChartPane {
+  pub symbol: String,
+  pub interval: String,
+  // number of candles between the right edge and the latest candle
+  offset: usize,
+  // number of candles in view
+  width: usize,
+  loaded: Loaded,
+  loaded_at: Option<Instant>,
+  // swapped in by the job `render` starts to load the view
+  next: Arc<Mutex<Option<(View, Loaded)>>>,
+  loading: Arc<AtomicBool>,
+}
+
+/// Which candles the chart shows.
+#[derive(Clone, Debug, PartialEq)]
+struct View {
+  symbol: String,
+  interval: String,
+  offset: usize,
+  width: usize,
+}
+
+/// What the chart draws for a `View`.
+#[derive(Default)]
+struct Loaded {
+  candles: Vec<Candle>,
+  moving_averages: Vec<(String, Vec<(f64, f64)>)>,
+  strong_points: Vec<StrongPoint>,
+  trend_lines: Vec<TrendLine>,
+}
+
+impl View {
+  fn load(&self) -> Result<Loaded> {
+    let step = self.interval.ms();
+    let end = now() - self.offset as i64 * step;
+    let mut query = Query::new(&self.symbol, &self.interval);
+    query.set_all(vec![End(end), Limit(self.width), Order(DESC)]);
+    let mut candles = query.query_candles()?;
+    candles.reverse();
+
+    let mut loaded = Loaded {
+      strong_points: generate_points(&candles),
+      trend_lines: chart::trend_lines(&query, candles.clone())?,
+      ..Default::default()
+    };
+    if let (Some(first), Some(last)) = (candles.first(), candles.last()) {
+      let range = first.open_time..last.open_time;
+      for (len, exp) in MovingAverage::available(&self.symbol, &self.interval)?
+      {
+        let ma = MovingAverage::query(
+          &self.symbol,
+          &self.interval,
+          len,
+          exp,
+          Some(range.clone()),
+        )?;
+        let label = format!("{}{}", if exp { "ema" } else { "ma" }, len);
+        let points = ma
+          .iter()
+          .map(|m| {
+            ((m.ms - first.open_time) as f64 / step as f64, m.val as f64)
+          })
+          .collect();
+        loaded.moving_averages.push((label, points));
+      }
+    }
+    loaded.candles = candles;
+
+    Ok(loaded)
+  }
+}
+
+impl ChartPane {
+  pub fn new() -> Self {
+    Self {
+      symbol: "BTCUSDT".into(),
+      interval: "15m".into(),
+      offset: 0,
+      width: 120,
+      loaded: Loaded::default(),
+      loaded_at: None,
+      next: Arc::default(),
+      loading: Arc::default(),
+    }
+  }
+
+  pub fn set(&mut self, symbol: String, interval: String) {
+    self.symbol = symbol;
+    self.interval = interval;
+    self.offset = 0;
+    self.loaded_at = None;
+  }
+
+  pub fn pan(&mut self, right: bool) {
+    let step = (self.width / 4).max(1);
+    self.offset = match right {
+      true => self.offset.saturating_sub(step),
+      false => self.offset + step,
+    };
+    self.loaded_at = None;
+  }
+
+  pub fn zoom(&mut self, zoom_in: bool) {
+    self.width = match zoom_in {
+      true => self.width / 2,
+      false => self.width * 2,
+    }
+    .clamp(MIN_WIDTH, MAX_WIDTH);
+    self.loaded_at = None;
+  }
+
+  pub fn cycle_interval(&mut self, up: bool) {
+    let i = INTERVALS
+      .iter()
+      .position(|i| *i == self.interval)
+      .unwrap_or(0);
+    let i = match up {
+      true => (i + 1).min(INTERVALS.len() - 1),
+      false => i.saturating_sub(1),
+    };
+    self.interval = INTERVALS[i].to_owned();
+    self.offset = 0;
+    self.loaded_at = None;
+  }
+
+  fn view(&self) -> View {
+    View {
+      symbol: self.symbol.clone(),
+      interval: self.interval.clone(),
+      offset: self.offset,
+      width: self.width,
+    }
+  }
+
+  /// Swap in a view the background job finished loading, then start
+  /// loading the next one when the view changed or the latest candles are
+  /// stale. The last view loaded is drawn until then.
+  fn refresh(&mut self) {
+    if let Some((view, loaded)) = self.next.lock().unwrap().take() {
+      // views moved away from while loading are dropped
+      if view == self.view() {
+        self.loaded = loaded;
+      }
+    }
+    match self.loaded_at {
+      Some(t) if self.offset > 0 || t.elapsed() < REFRESH => return,
+      _ if self.loading.swap(true, Relaxed) => return,
+      _ => self.loaded_at = Some(Instant::now()),
+    }
+
+    let view = self.view();
+    let next = self.next.clone();
+    let loading = self.loading.clone();
+    let name = format!("Charting {} {}", view.symbol, view.interval);
+    jobs::spawn(name, move || {
+      let result = view.load();
+      loading.store(false, Relaxed);
+      *next.lock().unwrap() = Some((view, result?));
+      Ok(())
+    });
+  }
+
+  pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+    self.refresh();
+    let loaded = &self.loaded;
+
+    let mut title = vec![Span::styled(
+      format!(" {} {} ", self.symbol, self.interval),
+      Style::default().fg(Color::White),
+    )];
+    if let (Some(first), Some(last)) =
+      (loaded.candles.first(), loaded.candles.last())
+    {
+      title.push(Span::raw(format!(
+        "{} - {} close: {} ",
+        first.open_time.to_human(),
+        last.open_time.to_human(),
+        last.close
+      )));
+    } else {
+      title.push(Span::raw("no candles "));
+    }
+    for (i, (label, _)) in loaded.moving_averages.iter().enumerate() {
+      title.push(Span::styled(
+        format!("{} ", label),
+        Style::default().fg(MA_COLORS[i % MA_COLORS.len()]),
+      ));
+    }
+
+    let (low, high) = bounds(&loaded.candles);
+
+    let highs: Vec<(f64, f64)> = self.points(CandlePos::HIGH);
+    let lows: Vec<(f64, f64)> = self.points(CandlePos::LOW);
+
+    let canvas = Canvas::default()
+      .block(
+        Block::default()
+          .borders(Borders::ALL)
+          .title(Spans::from(title)),
+      )
+      .x_bounds([-1., self.width as f64])
+      .y_bounds([low, high])
+      .paint(|ctx| {
+        for (i, c) in loaded.candles.iter().enumerate() {
+          let x = i as f64;
+          let color = match c.close >= c.open {
+            true => Color::Green,
+            false => Color::Red,
+          };
+          ctx.draw(&Line {
+            x1: x,
+            y1: c.low as f64,
+            x2: x,
+            y2: c.high as f64,
+            color,
+          });
+          // widen the body a little so it stands out from the wick
+          for dx in [-0.25, 0.25] {
+            ctx.draw(&Line {
+              x1: x + dx,
+              y1: c.open as f64,
+              x2: x + dx,
+              y2: c.close as f64,
+              color,
+            });
+          }
+        }
+        ctx.layer();
+        for (i, (_, points)) in loaded.moving_averages.iter().enumerate() {
+          for w in points.windows(2) {
+            ctx.draw(&Line {
+              x1: w[0].0,
+              y1: w[0].1,
+              x2: w[1].0,
+              y2: w[1].1,
+              color: MA_COLORS[i % MA_COLORS.len()],
+            });
+          }
+        }
+        if let Some(last) = loaded.candles.last() {
+          let x2 = (loaded.candles.len() - 1) as f64;
+          for l in &loaded.trend_lines {
+            let root = &l.line.roots[0];
+            ctx.draw(&Line {
+              x1: root.candle_index as f64,
+              y1: root.y as f64,
+              x2,
+              y2: l.line.y_at_x(last.open_time) as f64,
+              color: match root.candle_position {
+                CandlePos::HIGH => Color::LightRed,
+                CandlePos::LOW => Color::LightGreen,
+              },
+            });
+          }
+        }
+        ctx.draw(&Points {
+          coords: &highs,
+          color: Color::Yellow,
+        });
+        ctx.draw(&Points {
+          coords: &lows,
+          color: Color::LightBlue,
+        });
+      });
+
+    f.render_widget(canvas, area);
+
+    // tag the last close on the right border
+    if let Some(last) = loaded.candles.last() {
+      let tag = format!("{}", last.close);
+      let rows = area.height.saturating_sub(2);
+      let width = (tag.len() as u16).min(area.width);
+      if rows > 0 {
+        let y = area.y + 1 + row(last.close as f64, (low, high), rows);
+        let tag_area = Rect::new(area.right() - width, y, width, 1);
+        let tag = Span::styled(tag, Style::default().fg(Color::White));
+        f.render_widget(Paragraph::new(tag), tag_area);
+      }
+    }
+  }
+
+  fn points(&self, position: CandlePos) -> Vec<(f64, f64)> {
+    self
+      .loaded
+      .strong_points
+      .iter()
+      .filter(|p| p.position == position)
+      .map(|p| (p.candle_index as f64, p.y as f64))
+      .collect()
+  }
+}
+
+/// The lowest low and highest high of `candles`, the prices the chart
+/// spans.
+fn bounds(candles: &[Candle]) -> (f64, f64) {
+  let (low, high) =
+    candles.iter().fold((f64::MAX, f64::MIN), |(low, high), c| {
+      (low.min(c.low as f64), high.max(c.high as f64))
+    });
+  match low <= high {
+    true => (low, high),
+    false => (0., 1.),
+  }
+}
+
+/// The row, from the top of a chart `rows` high spanning `bounds`, that
+/// `price` is drawn on.
+fn row(price: f64, (low, high): (f64, f64), rows: u16) -> u16 {
+  let last = rows.saturating_sub(1);
+  match high > low {
+    true => {
+      let share = ((high - price) / (high - low)).clamp(0., 1.);
+      (share * last as f64).round() as u16
+    }
+    false => last / 2,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn panning_zooming_and_intervals_change_the_view() {
+    let mut pane = ChartPane::new();
+    pane.loaded_at = Some(Instant::now());
+    // a quarter of the width at a time, not past the latest candle
+    pane.pan(false);
+    assert_eq!(pane.offset, 30);
+    assert!(pane.loaded_at.is_none());
+    pane.pan(true);
+    pane.pan(true);
+    assert_eq!(pane.offset, 0);
+
+    pane.zoom(true);
+    assert_eq!(pane.width, 60);
+    for _ in 0..10 {
+      pane.zoom(true);
+    }
+    assert_eq!(pane.width, MIN_WIDTH);
+    for _ in 0..10 {
+      pane.zoom(false);
+    }
+    assert_eq!(pane.width, MAX_WIDTH);
+
+    pane.pan(false);
+    pane.cycle_interval(true);
+    assert_eq!((pane.interval.as_str(), pane.offset), ("1h", 0));
+    for _ in 0..INTERVALS.len() {
+      pane.cycle_interval(true);
+    }
+    assert_eq!(pane.interval, INTERVALS[INTERVALS.len() - 1]);
+    for _ in 0..INTERVALS.len() {
+      pane.cycle_interval(false);
+    }
+    assert_eq!(pane.interval, INTERVALS[0]);
+
+    let view = pane.view();
+    pane.set("ETHUSDT".into(), "4h".into());
+    assert_ne!(pane.view(), view);
+    assert_eq!(pane.view().symbol, "ETHUSDT");
+  }
+
+  #[test]
+  fn prices_map_to_rows_from_the_top() {
+    let candle = |low: f32, high: f32| Candle {
+      low,
+      high,
+      ..Default::default()
+    };
+    let bounds = bounds(&[candle(100., 120.), candle(90., 110.)]);
+    assert_eq!(bounds, (90., 120.));
+    assert_eq!(super::bounds(&[]), (0., 1.));
+
+    assert_eq!(row(120., bounds, 31), 0);
+    assert_eq!(row(90., bounds, 31), 30);
+    assert_eq!(row(105., bounds, 31), 15);
+    // clamped to the chart
+    assert_eq!(row(200., bounds, 31), 0);
+    assert_eq!(row(0., bounds, 31), 30);
+    // a flat chart draws in the middle
+    assert_eq!(row(100., (100., 100.), 31), 15);
+    assert_eq!(row(100., bounds, 0), 0);
+  }
+}
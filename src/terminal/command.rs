@@ -1,63 +1,679 @@
+use super::logs::{Level, FILTER};
+use crate::model::train::Algorithm;
+use crate::normalized::Labeller;
 use crate::prelude::*;
 use anyhow::Result;
-use normalized::*;
 
-fn recognized() {
-  log!("/gB Command recognized.");
+const SYMBOLS: [&str; 1] = ["BTCUSDT"];
+pub const INTERVALS: [&str; 6] = ["15m", "1h", "4h", "1d", "1w", "1M"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ArgKind {
+  Interval,
+  // start(..end), e.g. 1y..6M
+  Range,
+  Symbol,
+  // the name of another command
+  Command,
+  Job,
+  Level,
+  // a strat named in the config
+  Strat,
+  // see `normalized::label`
+  Label,
+  // see `model::train`
+  Algorithm,
+  // a registered model's id
+  Model,
+  // the rest of the line
+  Text,
 }
 
-pub fn parse_command(cmd: String) -> Result<()> {
-  let parts: Vec<&str> = cmd.split(" ").collect();
+pub struct Arg {
+  pub name: &'static str,
+  pub kind: ArgKind,
+  pub optional: bool,
+  pub help: &'static str,
+}
 
-  if parts.is_empty() {
-    return Ok(());
+pub struct Command {
+  pub name: &'static str,
+  pub args: Vec<Arg>,
+  pub help: &'static str,
+  run: fn(&Args) -> Result<()>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+  Interval(String),
+  Range(Range<i64>),
+  Symbol(String),
+  Command(String),
+  Job(usize),
+  Level(Level),
+  Strat(String),
+  Label(Labeller),
+  Algorithm(Algorithm),
+  Model(i32),
+  Text(String),
+}
+
+#[derive(Default, Debug)]
+pub struct Args {
+  values: HashMap<&'static str, Value>,
+}
+
+impl Args {
+  pub fn get(&self, name: &str) -> Option<&Value> { self.values.get(name) }
+  pub fn interval(&self, name: &str) -> Result<&str> {
+    match self.get(name) {
+      Some(Value::Interval(i)) => Ok(i),
+      _ => bail!("Missing <{}>", name),
+    }
+  }
+  pub fn range(&self, name: &str) -> Result<Range<i64>> {
+    match self.get(name) {
+      Some(Value::Range(r)) => Ok(r.clone()),
+      _ => bail!("Missing <{}>", name),
+    }
+  }
+  pub fn job(&self, name: &str) -> Result<usize> {
+    match self.get(name) {
+      Some(Value::Job(id)) => Ok(*id),
+      _ => bail!("Missing <{}>", name),
+    }
+  }
+  pub fn text(&self, name: &str) -> Option<&str> {
+    match self.get(name) {
+      Some(Value::Text(t)) => Some(t),
+      _ => None,
+    }
+  }
+  pub fn strat(&self, name: &str) -> &str {
+    match self.get(name) {
+      Some(Value::Strat(s)) => s,
+      _ => &CONFIG.default_strat,
+    }
+  }
+  pub fn label(&self, name: &str) -> &Labeller {
+    match self.get(name) {
+      Some(Value::Label(l)) => l,
+      _ => &CONFIG.label,
+    }
+  }
+  pub fn algorithm(&self, name: &str) -> Algorithm {
+    match self.get(name) {
+      Some(Value::Algorithm(a)) => *a,
+      _ => Algorithm::Linear,
+    }
+  }
+  pub fn model(&self, name: &str) -> Option<i32> {
+    match self.get(name) {
+      Some(Value::Model(id)) => Some(*id),
+      _ => None,
+    }
+  }
+  pub fn symbol(&self, name: &str) -> &str {
+    match self.get(name) {
+      Some(Value::Symbol(s)) => s,
+      _ => SYMBOLS[0],
+    }
+  }
+}
+
+impl Arg {
+  fn required(name: &'static str, kind: ArgKind, help: &'static str) -> Self {
+    Self {
+      name,
+      kind,
+      help,
+      optional: false,
+    }
+  }
+  fn optional(name: &'static str, kind: ArgKind, help: &'static str) -> Self {
+    Self {
+      name,
+      kind,
+      help,
+      optional: true,
+    }
   }
 
-  match parts[0] {
-    // download interval start(..end)
-    "reset" => {
-      recognized();
-      con().batch_execute("delete from candles;")?;
-      log!("Deleted all candles.");
+  fn usage(&self) -> String {
+    match self.optional {
+      true => format!("[{}]", self.name),
+      false => format!("<{}>", self.name),
     }
-    "download" if parts.len() > 2 => {
-      recognized();
-      let range_parts: Vec<&str> = parts[2].split("..").collect();
-      let start = range_parts[0].ago();
-      let end = match range_parts.get(1) {
-        Some(p) => p.ago(),
-        _ => now(),
-      };
-      let mut query = Query::new("BTCUSDT", parts[1]);
-      query.set_all(vec![Start(start), End(end)]);
-      if start > end {
-        bail!("Start of range must be before end.");
+  }
+
+  fn parse(&self, input: &str) -> Result<Value> {
+    Ok(match self.kind {
+      ArgKind::Interval => {
+        if input.try_ms()? <= 0 {
+          bail!("<{}> must be a positive interval", self.name);
+        }
+        Value::Interval(input.to_owned())
+      }
+      ArgKind::Range => {
+        let range_parts: Vec<&str> = input.split("..").collect();
+        let start = range_parts[0].try_ago()?;
+        let end = match range_parts.get(1) {
+          Some(p) => p.try_ago()?,
+          _ => now(),
+        };
+        if start > end {
+          bail!("Start of range must be before end.");
+        }
+        Value::Range(start..end)
+      }
+      ArgKind::Symbol => Value::Symbol(input.to_uppercase()),
+      ArgKind::Command => match find(input) {
+        Some(cmd) => Value::Command(cmd.name.to_owned()),
+        None => bail!("Unknown command '{}'", input),
+      },
+      ArgKind::Job => match input.trim_start_matches('#').parse() {
+        Ok(id) => Value::Job(id),
+        Err(_) => bail!("'{}' is not a job id", input),
+      },
+      ArgKind::Level => Value::Level(Level::parse(input)?),
+      ArgKind::Strat => {
+        CONFIG.strat(input)?;
+        Value::Strat(input.to_owned())
+      }
+      ArgKind::Label => Value::Label(input.parse()?),
+      ArgKind::Algorithm => Value::Algorithm(input.parse()?),
+      ArgKind::Model => match input.trim_start_matches('#').parse() {
+        Ok(id) => Value::Model(id),
+        Err(_) => bail!("'{}' is not a model id", input),
+      },
+      ArgKind::Text => Value::Text(input.to_owned()),
+    })
+  }
+
+  fn candidates(&self) -> Vec<&'static str> {
+    match self.kind {
+      ArgKind::Interval => INTERVALS.to_vec(),
+      ArgKind::Symbol => SYMBOLS.to_vec(),
+      ArgKind::Command => COMMANDS.iter().map(|c| c.name).collect(),
+      ArgKind::Level => Level::NAMES.to_vec(),
+      ArgKind::Strat => CONFIG.strats.keys().map(|k| k.as_str()).collect(),
+      ArgKind::Label => {
+        vec!["horizon:8h", "barrier:2:1:1d", "excursion:1d", "profitable"]
+      }
+      ArgKind::Algorithm => Algorithm::NAMES.to_vec(),
+      ArgKind::Range | ArgKind::Job | ArgKind::Model | ArgKind::Text => {
+        vec![]
       }
-      log!("Downloading candles from {} to {}.", start, end);
+    }
+  }
+}
 
-      let before_count = query.count_candles()?;
-      let api = Binance::new();
-      let _ = api.save_candles(&mut query)?;
-      let after_count = query.count_candles()?;
-      log!("Downloaded {} candles.", after_count - before_count);
+impl Command {
+  pub fn run(&self, args: &Args) -> Result<()> { (self.run)(args) }
+
+  pub fn usage(&self) -> String {
+    let mut usage = vec![self.name.to_owned()];
+    usage.extend(self.args.iter().map(|a| a.usage()));
+    usage.join(" ")
+  }
+
+  fn parse(&self, parts: &[&str]) -> Result<Args> {
+    // free text swallows the rest of the line
+    let text;
+    let mut parts = parts.to_vec();
+    if let Some(ArgKind::Text) = self.args.last().map(|a| a.kind) {
+      if parts.len() > self.args.len() {
+        text = parts.split_off(self.args.len() - 1).join(" ");
+        parts.push(&text);
+      }
     }
-    "predict" => {
-      recognized();
-      let data1 = "52w:1w,6w:1d,1w:4h,4d:1h,2d:15m;4h:200:true,1d:50:false";
 
-      fs::remove_file("predict.csv");
-      let mut file = File::create("predict.csv")?;
-      normalized::strat1::export_at(data1, "BTCUSDT", "1h".ago(), &mut file)?;
+    if parts.len() > self.args.len() {
+      bail!("Too many arguments. Usage: {}", self.usage());
     }
-    "build_csv" => {
-      recognized();
 
-      let data1 = "52w:1w,6w:1d,1w:4h,4d:1h,2d:15m;4h:200:true,1d:50:false";
-      normalized::strat1::export_all(data1, "BTCUSDT")?;
+    let mut args = Args::default();
+    for (i, arg) in self.args.iter().enumerate() {
+      match parts.get(i) {
+        Some(part) => {
+          let value = arg
+            .parse(part)
+            .map_err(|e| anyhow::anyhow!("{}. Usage: {}", e, self.usage()))?;
+          args.values.insert(arg.name, value);
+        }
+        None if arg.optional => {}
+        None => bail!("Missing <{}>. Usage: {}", arg.name, self.usage()),
+      }
     }
-    _ => {
-      log!("/yB Command not recognized.");
+    Ok(args)
+  }
+}
+
+lazy_static! {
+  pub static ref COMMANDS: Vec<Command> = vec![
+    Command {
+      name: "help",
+      args: vec![Arg::optional(
+        "command",
+        ArgKind::Command,
+        "show details for a single command"
+      )],
+      help: "List commands and their arguments.",
+      run: help,
+    },
+    Command {
+      name: "reset",
+      args: vec![],
+      help: "Delete all candles.",
+      run: |_| {
+        con().batch_execute("delete from candles;")?;
+        log!("Deleted all candles.");
+        Ok(())
+      },
+    },
+    Command {
+      name: "download",
+      args: vec![
+        Arg::required(
+          "interval",
+          ArgKind::Interval,
+          "candle interval, e.g. 15m"
+        ),
+        Arg::required("range", ArgKind::Range, "start(..end) ago, e.g. 1y..6M"),
+        Arg::optional("symbol", ArgKind::Symbol, "defaults to BTCUSDT"),
+      ],
+      help: "Download missing candles for a range.",
+      run: download,
+    },
+    Command {
+      name: "chart",
+      args: vec![
+        Arg::required(
+          "interval",
+          ArgKind::Interval,
+          "candle interval, e.g. 4h"
+        ),
+        Arg::optional("symbol", ArgKind::Symbol, "defaults to BTCUSDT"),
+      ],
+      help: "Chart stored candles. Arrows pan/zoom, PgUp/PgDn switch \
+        interval, Esc hides.",
+      run: |args| {
+        let symbol = args.symbol("symbol").to_owned();
+        let interval = args.interval("interval")?.to_owned();
+        super::chart::CHART.0.send((symbol, interval))?;
+        Ok(())
+      },
+    },
+    Command {
+      name: "cancel",
+      args: vec![Arg::required("job", ArgKind::Job, "id from the jobs tab")],
+      help: "Stop a running job. Ctrl-c stops the latest command.",
+      run: |args| {
+        let id = args.job("job")?;
+        super::jobs::cancel(id)?;
+        log!("Cancelling job #{}.", id);
+        Ok(())
+      },
+    },
+    Command {
+      name: "level",
+      args: vec![Arg::required(
+        "level",
+        ArgKind::Level,
+        "debug|info|warn|error"
+      )],
+      help: "Only show logs at or above a level. Ctrl-l cycles levels.",
+      run: |args| {
+        if let Some(Value::Level(level)) = args.get("level") {
+          FILTER.write().unwrap().level = *level;
+        }
+        Ok(())
+      },
+    },
+    Command {
+      name: "module",
+      args: vec![Arg::optional(
+        "module",
+        ArgKind::Text,
+        "part of a module path, e.g. api. Omit to show all"
+      )],
+      help: "Only show logs from matching modules.",
+      run: |args| {
+        FILTER.write().unwrap().module = args.text("module").map(Into::into);
+        Ok(())
+      },
+    },
+    Command {
+      name: "search",
+      args: vec![Arg::optional(
+        "text",
+        ArgKind::Text,
+        "case-insensitive. Omit to clear"
+      )],
+      help: "Only show logs containing some text.",
+      run: |args| {
+        FILTER.write().unwrap().search = args.text("text").map(Into::into);
+        Ok(())
+      },
+    },
+    Command {
+      name: "predict",
+      args: vec![Arg::optional(
+        "strat",
+        ArgKind::Strat,
+        "from the config, defaults to its default_strat"
+      )],
+      help: "Export the strat's current frame to predict.csv, and predict \
+        from it with the symbol's latest model of the strat if there is one.",
+      run: |args| {
+        let name = args.strat("strat");
+        let _ = fs::remove_file("predict.csv");
+        let mut file = File::create("predict.csv")?;
+        normalized::strat1::export_at(
+          name,
+          None,
+          "BTCUSDT",
+          "1h".ago(),
+          &mut file,
+        )?;
+
+        match model::latest_of("BTCUSDT", CONFIG.strat(name)?)? {
+          Some(p) => {
+            log!(ok: "#{} {} at {}", p.model, p.label, p.cursor.to_human());
+            for (name, value) in p.labels.iter().zip(&p.values) {
+              log!("  {}: {}", name, value);
+            }
+          }
+          None => {
+            log!(
+              "No {} model is registered for BTCUSDT, run train first.",
+              name
+            );
+          }
+        }
+        Ok(())
+      },
+    },
+    Command {
+      name: "build_csv",
+      args: vec![
+        Arg::optional(
+          "strat",
+          ArgKind::Strat,
+          "from the config, defaults to its default_strat"
+        ),
+        Arg::optional(
+          "label",
+          ArgKind::Label,
+          "e.g. barrier:2:1:1d, defaults to the config's"
+        ),
+      ],
+      help: "Export the strat's frames for the walk-forward folds as NumPy \
+        arrays.",
+      run: |args| normalized::strat1::export_all(
+        args.strat("strat"),
+        args.label("label"),
+        "BTCUSDT"
+      ),
+    },
+    Command {
+      name: "train",
+      args: vec![
+        Arg::optional(
+          "algorithm",
+          ArgKind::Algorithm,
+          "linear|logistic|trees, defaults to linear"
+        ),
+        Arg::optional(
+          "strat",
+          ArgKind::Strat,
+          "whose build_csv export to train on, defaults to the default_strat"
+        ),
+      ],
+      help: "Train a model on each fold of the build_csv export, report \
+        its test metrics and register the last fold's for predict.",
+      run: |args| {
+        model::train::train(
+          "BTCUSDT",
+          args.strat("strat"),
+          args.algorithm("algorithm"),
+        )?;
+        Ok(())
+      },
+    },
+    Command {
+      name: "models",
+      args: vec![Arg::optional(
+        "symbol",
+        ArgKind::Symbol,
+        "defaults to BTCUSDT"
+      )],
+      help: "List registered models. The latest predicts.",
+      run: |args| {
+        for entry in model::registry::list(args.symbol("symbol"))? {
+          log!(ok:
+            "#{} {} {} {}, schema {}",
+            entry.id,
+            entry.algorithm,
+            entry.strat,
+            entry.label,
+            entry.schema
+          );
+          log!(
+            "  trained {} to {}, tested to {}",
+            entry.train.start.to_human(),
+            entry.train.end.to_human(),
+            entry.test.end.to_human()
+          );
+          for m in &entry.metrics {
+            log!("  {}", m);
+          }
+        }
+        Ok(())
+      },
+    },
+    Command {
+      name: "score",
+      args: vec![Arg::optional(
+        "model",
+        ArgKind::Model,
+        "id from models, defaults to the latest"
+      )],
+      help: "Score a model's logged predictions against the labels they \
+        went on to get.",
+      run: |args| {
+        let entry = match args.model("model") {
+          Some(id) => model::registry::get(id)?,
+          None => match model::registry::latest("BTCUSDT")? {
+            Some(entry) => entry,
+            None => bail!("No model is registered for BTCUSDT"),
+          },
+        };
+        log!(ok: "#{} {}", entry.id, entry.label);
+        for m in model::registry::score(&entry)? {
+          log!("  {}", m);
+        }
+        Ok(())
+      },
+    },
+  ];
+}
+
+fn help(args: &Args) -> Result<()> {
+  if let Some(Value::Command(name)) = args.get("command") {
+    let cmd = find(name).unwrap();
+    log!(ok: "{}", cmd.usage());
+    log!("  {}", cmd.help);
+    for arg in &cmd.args {
+      log!("  {:<12} {}", arg.usage(), arg.help);
     }
+    return Ok(());
   }
+
+  for cmd in COMMANDS.iter() {
+    log!(ok: "{}", cmd.usage());
+    log!("  {}", cmd.help);
+  }
+  Ok(())
+}
+
+fn download(args: &Args) -> Result<()> {
+  let range = args.range("range")?;
+  let mut query = Query::new(args.symbol("symbol"), args.interval("interval")?);
+  query.set_range(range.clone());
+  log!("Downloading candles from {} to {}.", range.start, range.end);
+
+  let before_count = query.count_candles()?;
+  let _ = API.save_candles(&mut query)?;
+  let after_count = query.count_candles()?;
+  log!("Downloaded {} candles.", after_count - before_count);
   Ok(())
 }
+
+fn find(name: &str) -> Option<&'static Command> {
+  COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Validate a line of input against the command registry.
+pub fn parse(input: &str) -> Result<Option<(&'static Command, Args)>> {
+  let parts: Vec<&str> = input.split_whitespace().collect();
+  let name = match parts.first() {
+    Some(name) => *name,
+    None => return Ok(None),
+  };
+
+  match find(name) {
+    Some(cmd) => Ok(Some((cmd, cmd.parse(&parts[1..])?))),
+    None => bail!("Unknown command '{}'. Type 'help' for commands.", name),
+  }
+}
+
+/// Validate a line of input and run it as a job, returning the job id.
+pub fn spawn(input: &str) -> Result<Option<usize>> {
+  Ok(parse(input)?.map(|(command, args)| {
+    super::jobs::spawn_command(input.trim(), move || command.run(&args))
+  }))
+}
+
+/// Usage hint for whatever has been typed so far.
+pub fn hint(input: &str) -> String {
+  let parts: Vec<&str> = input.split_whitespace().collect();
+  let name = match parts.first() {
+    Some(name) => *name,
+    None => return "Type 'help' for commands, Tab to complete".into(),
+  };
+
+  if let Some(cmd) = find(name) {
+    // the arg currently being typed
+    let i = match input.ends_with(' ') {
+      true => parts.len() - 1,
+      false => parts.len().saturating_sub(2),
+    };
+    return match (parts.len() > 1 || input.ends_with(' '), cmd.args.get(i)) {
+      (true, Some(arg)) => {
+        format!("{} - {}: {}", cmd.usage(), arg.name, arg.help)
+      }
+      _ => format!("{} - {}", cmd.usage(), cmd.help),
+    };
+  }
+
+  let matches: Vec<&str> = COMMANDS
+    .iter()
+    .map(|c| c.name)
+    .filter(|n| n.starts_with(name))
+    .collect();
+  match matches.is_empty() {
+    true => format!("Unknown command '{}'", name),
+    false => matches.join(" | "),
+  }
+}
+
+pub struct Completion {
+  pub input: String,
+  pub candidates: Vec<&'static str>,
+}
+
+/// Tab completion for the token under the cursor (the end of the input).
+pub fn complete(input: &str) -> Completion {
+  let mut parts: Vec<&str> = input.split_whitespace().collect();
+  if input.is_empty() || input.ends_with(' ') {
+    parts.push("");
+  }
+  let partial = parts.pop().unwrap_or("");
+
+  let candidates: Vec<&'static str> = match parts.first() {
+    None => COMMANDS.iter().map(|c| c.name).collect(),
+    Some(name) => match find(name).and_then(|c| c.args.get(parts.len() - 1)) {
+      Some(arg) => arg.candidates(),
+      None => vec![],
+    },
+  }
+  .into_iter()
+  .filter(|c| c.starts_with(partial))
+  .collect();
+
+  let completed = match candidates.len() {
+    0 => partial.to_owned(),
+    1 => format!("{} ", candidates[0]),
+    _ => common_prefix(&candidates),
+  };
+  parts.push(&completed);
+
+  Completion {
+    input: parts.join(" "),
+    candidates,
+  }
+}
+
+fn common_prefix(words: &[&str]) -> String {
+  let mut prefix = words[0].to_owned();
+  for w in &words[1..] {
+    while !w.starts_with(&prefix) {
+      prefix.pop();
+    }
+  }
+  prefix
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bad_arguments_are_errors() {
+    assert!(parse("download").is_err());
+    assert!(parse("download 15m").is_err());
+    assert!(parse("download 15x 1d").is_err());
+    assert!(parse("download 15m nonsense").is_err());
+    assert!(parse("download 15m 1d..2d").is_err());
+    assert!(parse("download 15m 3000y").is_err());
+    assert!(parse("download 15m 99999999999999d").is_err());
+    assert!(parse("download 99999999999999999999m 1d").is_err());
+    assert!(parse("download 15m 2d..1d BTCUSDT extra").is_err());
+    assert!(parse("help nope").is_err());
+    assert!(parse("nope").is_err());
+    assert!(parse("   ").unwrap().is_none());
+
+    let (cmd, args) = parse("download 15m 2d..1d").unwrap().unwrap();
+    assert_eq!(cmd.name, "download");
+    assert_eq!(args.interval("interval").unwrap(), "15m");
+    assert_eq!(args.symbol("symbol"), "BTCUSDT");
+
+    assert!(parse("level loud").is_err());
+    let (_, args) = parse("search rate  limit").unwrap().unwrap();
+    assert_eq!(args.text("text"), Some("rate limit"));
+  }
+
+  #[test]
+  fn completion() {
+    assert_eq!(complete("dow").input, "download ");
+    assert_eq!(complete("download 1").input, "download 1");
+    assert_eq!(
+      complete("download 1").candidates,
+      vec!["15m", "1h", "1d", "1w", "1M"]
+    );
+    assert_eq!(complete("download 4").input, "download 4h ");
+    assert_eq!(complete("help bu").input, "help build_csv ");
+    assert_eq!(complete("").candidates.len(), COMMANDS.len());
+  }
+}
@@ -0,0 +1,266 @@
+use crate::prelude::*;
+use std::sync::{atomic::AtomicBool, Arc, RwLock};
+
+// finished jobs kept around for the jobs tab
+const HISTORY: usize = 50;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+lazy_static! {
+  static ref JOBS: RwLock<Vec<Job>> = RwLock::new(vec![]);
+  // thread id -> job id
+  static ref THREADS: RwLock<HashMap<usize, usize>> =
+    RwLock::new(HashMap::new());
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Status {
+  Running,
+  Done,
+  Cancelled,
+  Failed(String),
+}
+
+/// Shared flag a job polls to find out it should stop.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  pub fn cancel(&self) { self.0.store(true, Relaxed); }
+  pub fn is_cancelled(&self) -> bool { self.0.load(Relaxed) }
+  /// Bail out of the current job if it has been cancelled.
+  pub fn check(&self) -> Result<()> {
+    match self.is_cancelled() {
+      true => bail!("Cancelled"),
+      false => Ok(()),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+  pub id: usize,
+  pub name: String,
+  pub started: i64,
+  pub finished: Option<i64>,
+  pub status: Status,
+  // (label, pct) for every progress bar the job currently has open
+  pub progress: Vec<(String, f64)>,
+  // started by a command rather than in the background, so Ctrl-c can
+  // cancel it
+  pub command: bool,
+  #[serde(skip)]
+  pub token: CancelToken,
+}
+
+/// Run `f` on its own thread, tracked as a background job.
+pub fn spawn<F>(name: impl Into<String>, f: F) -> usize
+where F: FnOnce() -> Result<()> + Send + 'static {
+  start(name.into(), false, f)
+}
+
+/// Run a command's `f` on its own thread, tracked as a job.
+pub fn spawn_command<F>(name: impl Into<String>, f: F) -> usize
+where F: FnOnce() -> Result<()> + Send + 'static {
+  start(name.into(), true, f)
+}
+
+fn start<F>(name: String, command: bool, f: F) -> usize
+where F: FnOnce() -> Result<()> + Send + 'static {
+  let id = register(name, command);
+  thread::spawn(move || {
+    THREADS.write().unwrap().insert(database::thread_id(), id);
+    let result = f();
+    THREADS.write().unwrap().remove(&database::thread_id());
+
+    finish(
+      id,
+      match result {
+        Ok(_) => Status::Done,
+        Err(_) if token(id).is_cancelled() => {
+          log!(warn: "Job #{} cancelled.", id);
+          Status::Cancelled
+        }
+        Err(e) => {
+          log!(error: "Job #{}: {:?}", id, e);
+          Status::Failed(e.to_string())
+        }
+      },
+    );
+  });
+  id
+}
+
+fn register(name: String, command: bool) -> usize {
+  let id = NEXT_ID.fetch_add(1, Relaxed);
+  JOBS.write().unwrap().push(Job {
+    id,
+    name,
+    started: now(),
+    finished: None,
+    status: Status::Running,
+    progress: vec![],
+    command,
+    token: CancelToken::default(),
+  });
+  id
+}
+
+fn finish(id: usize, status: Status) {
+  let mut jobs = JOBS.write().unwrap();
+  if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+    job.status = status;
+    job.finished = Some(now());
+    job.progress.clear();
+  }
+
+  let finished = jobs.iter().filter(|j| j.finished.is_some()).count();
+  if finished > HISTORY {
+    let mut excess = finished - HISTORY;
+    jobs.retain(|j| match (excess, j.finished) {
+      (1.., Some(_)) => {
+        excess -= 1;
+        false
+      }
+      _ => true,
+    });
+  }
+}
+
+/// Report progress for the job running on the current thread.
+/// A `pct` of -1 closes the progress bar.
+pub fn progress(label: &str, pct: f64) {
+  let thread = database::thread_id();
+  let id = THREADS.read().unwrap().get(&thread).copied();
+
+  // progress from a thread that was not started as a job
+  let id = match id {
+    Some(id) => id,
+    None if pct < 0. => return,
+    None => {
+      let id = register(label.to_owned(), false);
+      THREADS.write().unwrap().insert(thread, id);
+      id
+    }
+  };
+
+  let mut jobs = JOBS.write().unwrap();
+  let job = match jobs.iter_mut().find(|j| j.id == id) {
+    Some(job) => job,
+    None => return,
+  };
+
+  match job.progress.iter_mut().find(|(l, _)| l == label) {
+    _ if pct < 0. => job.progress.retain(|(l, _)| l != label),
+    Some((_, p)) => *p = pct,
+    None => job.progress.push((label.to_owned(), pct)),
+  }
+
+  // implicit jobs end with their last progress bar
+  if job.progress.is_empty() && job.name == label {
+    drop(jobs);
+    THREADS.write().unwrap().remove(&thread);
+    finish(id, Status::Done);
+  }
+}
+
+fn token(id: usize) -> CancelToken {
+  match JOBS.read().unwrap().iter().find(|j| j.id == id) {
+    Some(job) => job.token.clone(),
+    None => CancelToken::default(),
+  }
+}
+
+/// The cancellation token of the job running on the current thread.
+/// Threads that are not jobs get a token that is never cancelled.
+pub fn current() -> CancelToken {
+  match THREADS.read().unwrap().get(&database::thread_id()) {
+    Some(id) => token(*id),
+    None => CancelToken::default(),
+  }
+}
+
+pub fn cancel(id: usize) -> Result<()> {
+  match get(id) {
+    Some(job) if job.status == Status::Running => job.token.cancel(),
+    Some(_) => bail!("Job #{} is not running", id),
+    None => bail!("No job #{}", id),
+  }
+  Ok(())
+}
+
+/// Cancel the most recently started command that is still running.
+/// Background jobs are left alone.
+pub fn cancel_latest() -> Option<usize> {
+  let jobs = JOBS.read().unwrap();
+  let job = jobs.iter().rev().find(|j| {
+    j.command && j.status == Status::Running && !j.token.is_cancelled()
+  })?;
+  job.token.cancel();
+  Some(job.id)
+}
+
+pub fn list() -> Vec<Job> { JOBS.read().unwrap().clone() }
+
+pub fn get(id: usize) -> Option<Job> {
+  JOBS.read().unwrap().iter().find(|j| j.id == id).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn jobs_can_be_cancelled() {
+    let id = spawn("cancel me", move || loop {
+      current().check()?;
+      thread::sleep(Duration::from_millis(1));
+    });
+
+    assert!(cancel(id + 1000).is_err());
+    cancel(id).unwrap();
+    while get(id).unwrap().status == Status::Running {
+      thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(get(id).unwrap().status, Status::Cancelled);
+    assert!(cancel(id).is_err());
+  }
+
+  #[test]
+  fn ctrl_c_only_cancels_commands() {
+    let wait = || loop {
+      current().check()?;
+      thread::sleep(Duration::from_millis(1));
+    };
+    let command = spawn_command("command", wait);
+    let background = spawn("background", wait);
+
+    assert_eq!(cancel_latest(), Some(command));
+    assert!(!get(background).unwrap().token.is_cancelled());
+    cancel(background).unwrap();
+  }
+
+  #[test]
+  fn jobs_track_progress_and_status() {
+    let (tx, rx) = bounded(0);
+    let id = spawn("test job", move || {
+      progress("working", 0.5);
+      tx.send(())?;
+      tx.send(())?;
+      bail!("failed on purpose")
+    });
+
+    rx.recv().unwrap();
+    let job = get(id).unwrap();
+    assert_eq!(job.status, Status::Running);
+    assert_eq!(job.progress, vec![("working".to_owned(), 0.5)]);
+
+    rx.recv().unwrap();
+    while get(id).unwrap().status == Status::Running {
+      thread::sleep(Duration::from_millis(5));
+    }
+    let job = get(id).unwrap();
+    assert_eq!(job.status, Status::Failed("failed on purpose".into()));
+    assert!(job.progress.is_empty());
+  }
+}
@@ -0,0 +1,335 @@
+use crate::prelude::*;
+use std::{
+  collections::VecDeque,
+  fmt,
+  fs::OpenOptions,
+  sync::{Mutex, RwLock},
+};
+use tui::style::{Color, Modifier, Style};
+
+// records kept in memory for the logs tab
+const HISTORY: usize = 5000;
+const FILE_NAME: &str = "market_bomb.log";
+
+lazy_static! {
+  pub static ref LOG: (Sender<Record>, Receiver<Record>) = unbounded();
+  pub static ref FILTER: RwLock<Filter> = RwLock::new(Filter::default());
+  static ref FILE: Mutex<Sink> = Mutex::new(Sink::Unopened);
+}
+
+// the log file, opened on the first record and given up on after it fails
+enum Sink {
+  Unopened,
+  Open(LogFile),
+  Failed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+impl Level {
+  pub const ALL: [Level; 4] =
+    [Level::Debug, Level::Info, Level::Warn, Level::Error];
+  pub const NAMES: [&'static str; 4] = ["debug", "info", "warn", "error"];
+
+  pub fn name(&self) -> &'static str { Self::NAMES[*self as usize] }
+
+  pub fn parse(input: &str) -> Result<Self> {
+    match Self::NAMES
+      .iter()
+      .position(|n| n.eq_ignore_ascii_case(input))
+    {
+      Some(i) => Ok(Self::ALL[i]),
+      None => bail!("Unknown log level '{}'", input),
+    }
+  }
+
+  /// The next level up, wrapping around to debug.
+  pub fn next(&self) -> Self { Self::ALL[(*self as usize + 1) % 4] }
+
+  fn style(&self) -> Style {
+    match self {
+      Level::Debug => Style::default().fg(Color::DarkGray),
+      Level::Info => Style::default(),
+      Level::Warn => Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::BOLD),
+      Level::Error => {
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+      }
+    }
+  }
+}
+
+impl fmt::Display for Level {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.pad(&self.name().to_uppercase())
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+  pub level: Level,
+  pub ms: i64,
+  pub module: &'static str,
+  pub message: String,
+  pub style: Style,
+}
+
+impl Record {
+  pub fn new(level: Level, module: &'static str, message: String) -> Self {
+    Self {
+      level,
+      ms: now(),
+      module,
+      message,
+      style: level.style(),
+    }
+  }
+
+  /// Highlight a successful outcome.
+  // log! prints instead in tests
+  #[cfg_attr(test, allow(dead_code))]
+  pub fn ok(mut self) -> Self {
+    self.style = Style::default()
+      .fg(Color::Green)
+      .add_modifier(Modifier::BOLD);
+    self
+  }
+
+  /// Module path without the crate name.
+  pub fn short_module(&self) -> &'static str {
+    match self.module.split_once("::") {
+      Some((_, path)) => path,
+      None => self.module,
+    }
+  }
+
+  pub fn time(&self) -> String {
+    self.ms.to_datetime().format("%H:%M:%S").to_string()
+  }
+}
+
+impl fmt::Display for Record {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} {:<5} {} {}",
+      self.ms.to_datetime().format("%Y-%m-%d %H:%M:%S%.3f"),
+      self.level,
+      self.module,
+      self.message
+    )
+  }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub fn log(record: Record) {
+  let mut sink = FILE.lock().unwrap();
+  if let Sink::Unopened = *sink {
+    let config = &CONFIG.log;
+    *sink = match LogFile::open(
+      Path::new(&config.dir),
+      config.max_bytes,
+      config.keep,
+    ) {
+      Ok(file) => Sink::Open(file),
+      Err(e) => {
+        failed(format!("Could not open the log file: {:?}", e));
+        Sink::Failed
+      }
+    };
+  }
+  if let Sink::Open(file) = &mut *sink {
+    if let Err(e) = file.write(&record) {
+      failed(format!("Could not write the log file: {:?}", e));
+      *sink = Sink::Failed;
+    }
+  }
+  drop(sink);
+
+  let _ = LOG.0.send(record);
+}
+
+// reported once to the logs tab, which printing would draw over
+fn failed(message: String) {
+  let _ = LOG
+    .0
+    .send(Record::new(Level::Error, module_path!(), message));
+}
+
+/// What the logs tab shows.
+#[derive(Clone, Debug)]
+pub struct Filter {
+  pub level: Level,
+  // matched against any part of the module path
+  pub module: Option<String>,
+  pub search: Option<String>,
+}
+
+impl Default for Filter {
+  fn default() -> Self {
+    Self {
+      level: Level::Info,
+      module: None,
+      search: None,
+    }
+  }
+}
+
+impl Filter {
+  pub fn matches(&self, record: &Record) -> bool {
+    record.level >= self.level
+      && self
+        .module
+        .as_ref()
+        .is_none_or(|m| record.module.contains(m))
+      && self.search.as_ref().is_none_or(|s| {
+        record.message.to_lowercase().contains(&s.to_lowercase())
+      })
+  }
+}
+
+impl fmt::Display for Filter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}+", self.level.name())?;
+    if let Some(module) = &self.module {
+      write!(f, " module: {}", module)?;
+    }
+    if let Some(search) = &self.search {
+      write!(f, " search: \"{}\"", search)?;
+    }
+    Ok(())
+  }
+}
+
+/// Records received so far, newest first.
+#[derive(Default)]
+pub struct Logs {
+  records: VecDeque<Record>,
+}
+
+impl Logs {
+  pub fn receive(&mut self) {
+    for record in LOG.1.try_iter() {
+      self.records.push_front(record);
+    }
+    self.records.truncate(HISTORY);
+  }
+
+  /// Records that pass the current filter, newest first.
+  pub fn filtered(&self) -> Vec<&Record> {
+    let filter = FILTER.read().unwrap();
+    self.records.iter().filter(|r| filter.matches(r)).collect()
+  }
+}
+
+/// Size-capped log file. When full it is renamed to `.1`, `.1` to `.2`
+/// and so on, dropping anything past `keep`.
+struct LogFile {
+  path: PathBuf,
+  file: File,
+  size: u64,
+  max_bytes: u64,
+  keep: usize,
+}
+
+impl LogFile {
+  fn open(dir: &Path, max_bytes: u64, keep: usize) -> Result<Self> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(FILE_NAME);
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    Ok(Self {
+      size: file.metadata()?.len(),
+      path,
+      file,
+      max_bytes,
+      keep,
+    })
+  }
+
+  fn rotated(&self, n: usize) -> PathBuf {
+    self.path.with_extension(format!("log.{}", n))
+  }
+
+  fn rotate(&mut self) -> Result<()> {
+    let _ = fs::remove_file(self.rotated(self.keep));
+    for n in (1..self.keep).rev() {
+      let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+    }
+    match self.keep {
+      0 => fs::remove_file(&self.path)?,
+      _ => fs::rename(&self.path, self.rotated(1))?,
+    }
+    self.file = File::create(&self.path)?;
+    self.size = 0;
+    Ok(())
+  }
+
+  fn write(&mut self, record: &Record) -> Result<()> {
+    let line = format!("{}\n", record);
+    if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+      self.rotate()?;
+    }
+    self.file.write_all(line.as_bytes())?;
+    self.size += line.len() as u64;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn filter_by_level_module_and_search() {
+    let record = |level, module, message: &str| {
+      Record::new(level, module, message.to_owned())
+    };
+    let debug = record(Level::Debug, "market_bomb::api", "url: x");
+    let warn = record(Level::Warn, "market_bomb::api::binance", "Rate limit");
+    let error = record(Level::Error, "market_bomb::database", "Migrate db");
+
+    let mut filter = Filter::default();
+    assert!(!filter.matches(&debug));
+    assert!(filter.matches(&warn) && filter.matches(&error));
+
+    filter.module = Some("api".into());
+    assert!(filter.matches(&warn) && !filter.matches(&error));
+
+    filter.level = Level::Debug;
+    filter.search = Some("RATE".into());
+    assert!(filter.matches(&warn) && !filter.matches(&debug));
+
+    assert_eq!(Level::parse("Warn").unwrap(), Level::Warn);
+    assert!(Level::parse("loud").is_err());
+    assert_eq!(Level::Error.next(), Level::Debug);
+  }
+
+  #[test]
+  fn log_file_rotates() -> Result<()> {
+    let dir = std::env::temp_dir().join("market_bomb_log_test");
+    let _ = fs::remove_dir_all(&dir);
+
+    let record = Record::new(Level::Info, "market_bomb", "x".repeat(40));
+    let line_len = format!("{}\n", record).len() as u64;
+    let mut file = LogFile::open(&dir, line_len * 2, 2)?;
+    for _ in 0..7 {
+      file.write(&record)?;
+    }
+
+    // 7 lines, 2 per file: the current file and two rotations survive
+    assert_eq!(fs::metadata(dir.join(FILE_NAME))?.len(), line_len);
+    assert_eq!(fs::metadata(file.rotated(1))?.len(), line_len * 2);
+    assert_eq!(fs::metadata(file.rotated(2))?.len(), line_len * 2);
+    assert!(!file.rotated(3).exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,213 @@
+use super::jobs::{self, Status};
+use crate::prelude::*;
+use crate::strategy::{self, Signal};
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::time::Instant;
+use tui::{
+  backend::Backend,
+  layout::{Constraint, Rect},
+  style::{Color, Modifier, Style},
+  text::{Span, Spans},
+  widgets::{Block, Borders, Cell, List, ListItem, Row, Table},
+  Frame,
+};
+
+// how often the signals tab recalculates while open
+const SIGNALS_REFRESH: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tab {
+  Logs,
+  Chart,
+  Jobs,
+  Coverage,
+  Signals,
+}
+
+impl Tab {
+  pub const ALL: [Tab; 5] = [
+    Tab::Logs,
+    Tab::Chart,
+    Tab::Jobs,
+    Tab::Coverage,
+    Tab::Signals,
+  ];
+
+  pub fn title(&self) -> &'static str {
+    match self {
+      Tab::Logs => "Logs",
+      Tab::Chart => "Chart",
+      Tab::Jobs => "Jobs",
+      Tab::Coverage => "Coverage",
+      Tab::Signals => "Signals",
+    }
+  }
+}
+
+fn header<'a>(cells: &[&'a str]) -> Row<'a> {
+  Row::new(cells.to_vec()).style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+fn elapsed(ms: i64) -> String {
+  let s = ms / 1000;
+  format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+pub fn render_jobs<B: Backend>(f: &mut Frame<B>, area: Rect) {
+  let items: Vec<ListItem> = jobs::list()
+    .into_iter()
+    .rev()
+    .map(|job| {
+      let (status, color) = match &job.status {
+        Status::Running => ("running".to_owned(), Color::Yellow),
+        Status::Done => ("done".to_owned(), Color::Green),
+        Status::Cancelled => ("cancelled".to_owned(), Color::DarkGray),
+        Status::Failed(e) => (format!("failed: {}", e), Color::Red),
+      };
+      let mut lines = vec![Spans::from(vec![
+        Span::styled(
+          format!("#{:<4}", job.id),
+          Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(format!(
+          "{:<32} {} ",
+          job.name,
+          elapsed(job.finished.unwrap_or_else(now) - job.started)
+        )),
+        Span::styled(status, Style::default().fg(color)),
+      ])];
+      for (label, pct) in &job.progress {
+        lines.push(Spans::from(format!(
+          "      {:>5.1}% {}",
+          pct.min(1.) * 100.,
+          label
+        )));
+      }
+      ListItem::new(lines)
+    })
+    .collect();
+
+  f.render_widget(
+    List::new(items).block(Block::default().borders(Borders::TOP).title(
+      "Jobs (Ctrl-c cancels the latest command, `cancel <id>` any job)",
+    )),
+    area,
+  );
+}
+
+pub fn render_coverage<B: Backend>(f: &mut Frame<B>, area: Rect) {
+  database::show_coverage();
+  let coverage = database::COVERAGE.read().unwrap().clone();
+  let rows: Vec<Row> = coverage
+    .iter()
+    .map(|c| {
+      let derived = c.derived as f32 / c.count.max(1) as f32 * 100.;
+      let gaps = match c.gaps {
+        0 => Cell::from("0").style(Style::default().fg(Color::Green)),
+        n => Cell::from(format!("{} ({} candles)", n, c.missing))
+          .style(Style::default().fg(Color::Yellow)),
+      };
+      Row::new(vec![
+        Cell::from(c.symbol.clone()),
+        Cell::from(c.interval.clone()),
+        Cell::from(c.count.to_string()),
+        Cell::from(c.start.to_human()),
+        Cell::from(c.end.to_human()),
+        gaps,
+        Cell::from(format!("{:.2}%", derived)),
+      ])
+    })
+    .collect();
+
+  let table = Table::new(rows)
+    .header(header(&[
+      "Symbol", "Interval", "Candles", "From", "To", "Gaps", "Derived",
+    ]))
+    .block(Block::default().borders(Borders::TOP).title("Coverage"))
+    .widths(&[
+      Constraint::Length(10),
+      Constraint::Length(9),
+      Constraint::Length(10),
+      Constraint::Length(16),
+      Constraint::Length(16),
+      Constraint::Length(22),
+      Constraint::Length(8),
+    ]);
+  f.render_widget(table, area);
+}
+
+pub struct SignalsPane {
+  pub symbol: String,
+  // swapped in by the job `render` starts to load them
+  signals: Arc<Mutex<Vec<Signal>>>,
+  loading: Arc<AtomicBool>,
+  loaded_at: Option<Instant>,
+}
+
+impl SignalsPane {
+  pub fn new() -> Self {
+    Self {
+      symbol: "BTCUSDT".into(),
+      signals: Arc::default(),
+      loading: Arc::default(),
+      loaded_at: None,
+    }
+  }
+
+  /// Start loading the signals in the background when they're stale and
+  /// no load is running, the last ones are shown until it's done.
+  fn refresh(&mut self) {
+    match self.loaded_at {
+      Some(t) if t.elapsed() < SIGNALS_REFRESH => return,
+      _ if self.loading.swap(true, Relaxed) => return,
+      _ => {}
+    }
+    self.loaded_at = Some(Instant::now());
+    let symbol = self.symbol.clone();
+    let signals = self.signals.clone();
+    let loading = self.loading.clone();
+    jobs::spawn(format!("Loading signals for {}", symbol), move || {
+      let result = strategy::signals(&symbol);
+      loading.store(false, Relaxed);
+      *signals.lock().unwrap() = result?;
+      Ok(())
+    });
+  }
+
+  pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+    self.refresh();
+
+    let signals = self.signals.lock().unwrap().clone();
+    let rows: Vec<Row> = signals
+      .iter()
+      .map(|s| {
+        let color = match s.value >= 0. {
+          true => Color::Green,
+          false => Color::Red,
+        };
+        Row::new(vec![
+          Cell::from(s.interval.clone()),
+          Cell::from(s.name.clone()),
+          Cell::from(format!("{:+.2}%", s.value))
+            .style(Style::default().fg(color)),
+          Cell::from(s.note.clone()),
+        ])
+      })
+      .collect();
+
+    let table = Table::new(rows)
+      .header(header(&["Interval", "Signal", "Distance", ""]))
+      .block(
+        Block::default()
+          .borders(Borders::TOP)
+          .title(format!("Signals {}", self.symbol)),
+      )
+      .widths(&[
+        Constraint::Length(9),
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Min(10),
+      ]);
+    f.render_widget(table, area);
+  }
+}
@@ -1,26 +1,315 @@
+mod commands;
+pub mod push;
+mod rest;
+
+use crate::chart::{self, TrendLine};
+use crate::config::{StrongPointsConfig, TrendLinesConfig};
+use crate::core::confluence::{self, Level};
+use crate::core::strong_point::{generate_points_with, FuzzyPolicy, LimitBy};
+use crate::core::zone;
 use crate::prelude::*;
+use actix_files::Files;
 use actix_web::{
-  middleware, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+  error::BlockingError, http::StatusCode, web, App, HttpResponse, HttpServer,
+  ResponseError,
 };
-use qstring::QString;
-use std::{env, io, io::Error};
+use std::{fmt, io};
+
+// candles sent when the request doesn't ask for a range
+const CHART_CANDLES: usize = 500;
+
+/// An error sent back as `{"error": ".."}`.
+#[derive(Debug)]
+pub struct ApiError(StatusCode, String);
 
-async fn candles(req: HttpRequest) -> impl Responder {
-  // let qs = QString::from(req.query_string());
-  "hello"
+impl ApiError {
+  pub fn bad_request(e: impl fmt::Display) -> Self {
+    Self(StatusCode::BAD_REQUEST, e.to_string())
+  }
+  pub fn not_found(e: impl fmt::Display) -> Self {
+    Self(StatusCode::NOT_FOUND, e.to_string())
+  }
 }
 
-#[actix_web::main]
-pub async fn run() -> io::Result<()> {
-  env::set_var("RUST_LOG", "actix_web=debug,actix_server=info");
-  env_logger::init();
-
-  HttpServer::new(move || {
-    App::new()
-      .wrap(middleware::Logger::default())
-      .route("/candles", web::get().to(candles))
+impl fmt::Display for ApiError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.1)
+  }
+}
+
+impl ResponseError for ApiError {
+  fn status_code(&self) -> StatusCode { self.0 }
+  fn error_response(&self) -> HttpResponse {
+    HttpResponse::build(self.0).json(serde_json::json!({ "error": self.1 }))
+  }
+}
+
+impl From<anyhow::Error> for ApiError {
+  fn from(e: anyhow::Error) -> Self {
+    log!(error: "Web server: {:?}", e);
+    Self(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+  }
+}
+
+impl From<BlockingError> for ApiError {
+  fn from(e: BlockingError) -> Self {
+    Self(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+  }
+}
+
+pub type ApiResult = std::result::Result<HttpResponse, ApiError>;
+
+/// Symbols end up in SQL, so only letters and digits get through.
+pub fn valid_symbol(symbol: Option<&str>) -> Result<String, ApiError> {
+  let symbol = symbol.unwrap_or("BTCUSDT");
+  match !symbol.is_empty() && symbol.chars().all(|c| c.is_ascii_alphanumeric())
+  {
+    true => Ok(symbol.to_uppercase()),
+    false => Err(ApiError::bad_request(format!(
+      "Invalid symbol '{}'",
+      symbol
+    ))),
+  }
+}
+
+pub fn valid_interval(interval: Option<&str>) -> Result<String, ApiError> {
+  let interval = interval.unwrap_or("15m");
+  match interval.try_ms() {
+    Ok(ms) if ms > 0 => Ok(interval.to_owned()),
+    _ => Err(ApiError::bad_request(format!(
+      "Invalid interval '{}'",
+      interval
+    ))),
+  }
+}
+
+#[derive(Deserialize)]
+pub struct ChartParams {
+  symbol: Option<String>,
+  interval: Option<String>,
+  // strong point selection, each falling back to `CONFIG.strong_points`
+  limit_by: Option<LimitBy>,
+  min_domain: Option<i32>,
+  count: Option<usize>,
+  percent: Option<f32>,
+  fuzzy: Option<FuzzyPolicy>,
+  // trend lines scoring below this are left out
+  min_strength: Option<f32>,
+  start: Option<i64>,
+  end: Option<i64>,
+}
+
+impl ChartParams {
+  fn strong_points(&self) -> StrongPointsConfig {
+    let config = &CONFIG.strong_points;
+    StrongPointsConfig {
+      limit_by: self.limit_by.unwrap_or(config.limit_by),
+      min_domain: self.min_domain.unwrap_or(config.min_domain),
+      count: self.count.unwrap_or(config.count),
+      percent: self.percent.unwrap_or(config.percent),
+      fuzzy: self.fuzzy.unwrap_or(config.fuzzy),
+    }
+  }
+}
+
+#[derive(Serialize)]
+pub struct ChartCandles {
+  candles: Vec<Candle>,
+  high: f32,
+  low: f32,
+}
+
+#[derive(Serialize)]
+pub struct ChartMeta {
+  start: i64,
+  end: i64,
+  step: i64,
+}
+
+/// The payload `web/src/chartHook.js` loads.
+#[derive(Serialize)]
+pub struct ChartData {
+  candles: ChartCandles,
+  meta: ChartMeta,
+  strong_points: Vec<StrongPoint>,
+  trend_lines: Vec<TrendLine>,
+  zones: Vec<Zone>,
+  // higher timeframes agreeing on a price, as of the last candle
+  confluence: Vec<Level>,
+}
+
+fn chart_data(
+  symbol: &str,
+  interval: &str,
+  params: &ChartParams,
+) -> Result<ChartData> {
+  let mut query = Query::new(symbol, interval);
+  let end = params.end.unwrap_or_else(now);
+  let candles = match params.start {
+    Some(start) => {
+      query.set_all(vec![Start(start), End(end), Limit(CONFIG.query_limit)]);
+      query.query_candles()?
+    }
+    None => {
+      query.set_all(vec![End(end), Limit(CHART_CANDLES), Order(DESC)]);
+      let mut candles = query.query_candles()?;
+      candles.reverse();
+      candles
+    }
+  };
+
+  let (high, low) = candles.iter().fold((f32::MIN, f32::MAX), |(h, l), c| {
+    (h.max(c.high), l.min(c.low))
+  });
+
+  let strong_points = generate_points_with(&candles, &params.strong_points());
+
+  Ok(ChartData {
+    zones: zone::zones(interval, &candles, &strong_points, &CONFIG.zones),
+    confluence: confluence::levels_at(symbol, interval, end)?,
+    meta: ChartMeta {
+      start: candles.first().map_or(end, |c| c.open_time),
+      end: candles.last().map_or(end, |c| c.open_time),
+      step: query.step(),
+    },
+    strong_points,
+    trend_lines: chart::trend_lines_with(
+      &query,
+      candles.clone(),
+      &TrendLinesConfig {
+        min_strength: params
+          .min_strength
+          .unwrap_or(CONFIG.trend_lines.min_strength),
+        max_lines: CONFIG.trend_lines.max_lines,
+      },
+    )?,
+    candles: ChartCandles { candles, high, low },
   })
-  .bind("0.0.0.0:8080")?
-  .run()
-  .await
+}
+
+async fn chart(params: web::Query<ChartParams>) -> ApiResult {
+  let symbol = valid_symbol(params.symbol.as_deref())?;
+  let interval = valid_interval(params.interval.as_deref())?;
+  if let Some(percent) = params.percent {
+    if !(0. ..=1.).contains(&percent) {
+      return Err(ApiError::bad_request(format!(
+        "Percent must be between 0 and 1, got {}",
+        percent
+      )));
+    }
+  }
+  let data =
+    web::block(move || chart_data(&symbol, &interval, &params)).await??;
+  Ok(HttpResponse::Ok().json(data))
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+  cfg.route("/chart", web::get().to(chart));
+  rest::routes(cfg);
+  push::routes(cfg);
+  cfg.app_data(web::Data::new(commands::Token(CONFIG.web.token.clone())));
+  commands::routes(cfg);
+
+  let static_dir = &CONFIG.web.static_dir;
+  if Path::new(static_dir).exists() {
+    cfg.service(Files::new("/", static_dir).index_file("index.html"));
+  }
+}
+
+/// Run the web server on its own thread.
+pub fn start() {
+  push::stats_thread();
+  thread::spawn(|| {
+    if let Err(e) = run() {
+      log!(error: "Web server: {:?}", e);
+    }
+  });
+}
+
+#[actix_web::main]
+async fn run() -> io::Result<()> {
+  let bind = &CONFIG.web.bind;
+  let server = HttpServer::new(|| App::new().configure(routes))
+    // the terminal decides when to exit
+    .disable_signals()
+    .bind(bind)?;
+  log!("Web server listening on http://{}", bind);
+  server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::test::{call_service, init_service, TestRequest};
+
+  #[test]
+  fn chart_payload() -> Result<()> {
+    let mut query = Query::new("CHARTTEST", "4h");
+    let step = query.step();
+    let start = "10d".ago().round(step);
+    for i in 0..5 {
+      query.insert_candle(&Candle {
+        open_time: start + step * i,
+        close_time: start + step * (i + 1) - 1,
+        high: 10. + i as f32,
+        low: 5.,
+        ..Default::default()
+      })?;
+    }
+
+    let params = ChartParams {
+      symbol: None,
+      interval: None,
+      limit_by: None,
+      min_domain: Some(2),
+      count: None,
+      percent: None,
+      fuzzy: None,
+      min_strength: None,
+      start: None,
+      end: None,
+    };
+    let data = chart_data("CHARTTEST", "4h", &params)?;
+    let data = serde_json::to_value(&data)?;
+    assert_eq!(data["candles"]["candles"].as_array().unwrap().len(), 5);
+    assert_eq!(data["candles"]["high"], 14.);
+    assert_eq!(data["meta"]["start"], start);
+    assert_eq!(data["meta"]["end"], start + step * 4);
+    assert_eq!(data["meta"]["step"], step);
+    assert!(data["strong_points"].is_array());
+    assert!(data["trend_lines"].is_array());
+    assert!(data["zones"].is_array());
+    assert!(data["confluence"].is_array());
+
+    // once domains are in, the request picks how many points come back
+    crate::core::candle::update_domain(&query)?;
+    let params = ChartParams {
+      limit_by: Some(LimitBy::Fixed),
+      count: Some(1),
+      ..params
+    };
+    let data = chart_data("CHARTTEST", "4h", &params)?;
+    assert_eq!(data.strong_points.len(), 1);
+
+    Ok(())
+  }
+
+  #[actix_web::test]
+  async fn bad_chart_parameters_are_rejected() {
+    let app = init_service(App::new().configure(routes)).await;
+    for uri in [
+      "/chart?interval=4x",
+      "/chart?interval=-4h",
+      "/chart?symbol=BTC%27%3B--",
+      "/chart?min_domain=lots",
+      "/chart?min_strength=high",
+      "/chart?limit_by=SOME",
+      "/chart?percent=2",
+      "/chart?fuzzy=maybe",
+    ] {
+      let req = TestRequest::get().uri(uri).to_request();
+      let res = call_service(&app, req).await;
+      assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+  }
 }
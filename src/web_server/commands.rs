@@ -0,0 +1,163 @@
+use super::{ApiError, ApiResult};
+use crate::prelude::*;
+use crate::terminal::{command, jobs};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+
+/// The token clients must send as `Authorization: Bearer <token>`.
+pub struct Token(pub Option<String>);
+
+fn authorize(req: &HttpRequest) -> Result<(), ApiError> {
+  let expected = match req.app_data::<web::Data<Token>>() {
+    Some(token) => token.0.as_deref(),
+    None => None,
+  };
+  let expected = match expected {
+    Some(t) if !t.is_empty() => t,
+    _ => {
+      return Err(ApiError(
+        StatusCode::FORBIDDEN,
+        "Remote commands are disabled. Set web.token in config.json.".into(),
+      ))
+    }
+  };
+
+  let given = req
+    .headers()
+    .get("Authorization")
+    .and_then(|h| h.to_str().ok())
+    .and_then(|h| h.strip_prefix("Bearer "));
+  match given {
+    Some(given) if eq(given.as_bytes(), expected.as_bytes()) => Ok(()),
+    _ => Err(ApiError(StatusCode::UNAUTHORIZED, "Bad token".into())),
+  }
+}
+
+// compares every byte so the time taken doesn't leak the token
+fn eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len()
+    && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[derive(Deserialize)]
+pub struct CommandBody {
+  // a line as it would be typed into the terminal, e.g. "download 15m 1y"
+  command: String,
+}
+
+async fn run(req: HttpRequest, body: web::Json<CommandBody>) -> ApiResult {
+  authorize(&req)?;
+  match command::spawn(&body.command).map_err(ApiError::bad_request)? {
+    Some(id) => {
+      log!(
+        "Remote command '{}' started job #{}.",
+        body.command.trim(),
+        id
+      );
+      Ok(HttpResponse::Accepted().json(serde_json::json!({ "job": id })))
+    }
+    None => Err(ApiError::bad_request("Empty command")),
+  }
+}
+
+async fn list(req: HttpRequest) -> ApiResult {
+  authorize(&req)?;
+  Ok(HttpResponse::Ok().json(jobs::list()))
+}
+
+async fn get(req: HttpRequest, id: web::Path<usize>) -> ApiResult {
+  authorize(&req)?;
+  match jobs::get(*id) {
+    Some(job) => Ok(HttpResponse::Ok().json(job)),
+    None => Err(ApiError(StatusCode::NOT_FOUND, format!("No job #{}", id))),
+  }
+}
+
+async fn cancel(req: HttpRequest, id: web::Path<usize>) -> ApiResult {
+  authorize(&req)?;
+  if jobs::get(*id).is_none() {
+    return Err(ApiError(StatusCode::NOT_FOUND, format!("No job #{}", id)));
+  }
+  jobs::cancel(*id)
+    .map_err(|e| ApiError(StatusCode::CONFLICT, e.to_string()))?;
+  Ok(HttpResponse::Accepted().finish())
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/commands", web::post().to(run))
+    .route("/jobs", web::get().to(list))
+    .route("/jobs/{id}", web::get().to(get))
+    .route("/jobs/{id}/cancel", web::post().to(cancel));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::{
+    test::{call_service, init_service, read_body_json, TestRequest},
+    App,
+  };
+
+  #[actix_web::test]
+  async fn commands_need_a_token_and_return_jobs() {
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(Token(Some("secret".into()))))
+        .configure(routes),
+    )
+    .await;
+    let post = |token: &str, command: &str| {
+      TestRequest::post()
+        .uri("/commands")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "command": command }))
+        .to_request()
+    };
+
+    let res = call_service(&app, post("wrong", "help")).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = call_service(&app, post("secret", "download 15x 1d")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = call_service(&app, post("secret", "help")).await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let body: serde_json::Value = read_body_json(res).await;
+    let id = body["job"].as_u64().unwrap();
+
+    let req = TestRequest::get()
+      .uri(&format!("/jobs/{}", id))
+      .insert_header(("Authorization", "Bearer secret"))
+      .to_request();
+    let job: serde_json::Value =
+      read_body_json(call_service(&app, req).await).await;
+    assert_eq!(job["id"], id);
+    assert_eq!(job["name"], "help");
+
+    let req = TestRequest::get()
+      .uri("/jobs/999999")
+      .insert_header(("Authorization", "Bearer secret"))
+      .to_request();
+    assert_eq!(
+      call_service(&app, req).await.status(),
+      StatusCode::NOT_FOUND
+    );
+  }
+
+  #[actix_web::test]
+  async fn commands_are_disabled_without_a_token() {
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(Token(None)))
+        .configure(routes),
+    )
+    .await;
+    let req = TestRequest::get()
+      .uri("/jobs")
+      .insert_header(("Authorization", "Bearer "))
+      .to_request();
+    assert_eq!(
+      call_service(&app, req).await.status(),
+      StatusCode::FORBIDDEN
+    );
+  }
+}
@@ -0,0 +1,232 @@
+use super::{valid_interval, valid_symbol, ApiResult};
+use crate::prelude::*;
+use actix_web::{web, HttpResponse};
+use std::sync::Mutex;
+use tokio::sync::mpsc::{channel, Sender};
+
+const STATS_EVERY: Duration = Duration::from_secs(1);
+// events queued for a client before it's dropped as too slow
+const BACKLOG: usize = 256;
+
+lazy_static! {
+  static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(vec![]);
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+  Candle {
+    symbol: String,
+    interval: String,
+    candle: Candle,
+  },
+  // the series was recalculated
+  MovingAverage {
+    symbol: String,
+    interval: String,
+    len: i32,
+    exp: bool,
+  },
+  // domains were rebuilt, so strong points may have moved
+  StrongPoints {
+    symbol: String,
+    interval: String,
+  },
+  Stats(Stats),
+}
+
+/// The counters from the terminal's stats bar.
+#[derive(Clone, Debug, Serialize)]
+pub struct Stats {
+  candles: usize,
+  derived: usize,
+  unique_violations: usize,
+  ma_unique_violations: usize,
+  running_jobs: usize,
+}
+
+impl Event {
+  fn name(&self) -> &'static str {
+    match self {
+      Event::Candle { .. } => "candle",
+      Event::MovingAverage { .. } => "moving_average",
+      Event::StrongPoints { .. } => "strong_points",
+      Event::Stats(_) => "stats",
+    }
+  }
+
+  fn series(&self) -> Option<(&str, &str)> {
+    match self {
+      Event::Candle {
+        symbol, interval, ..
+      }
+      | Event::MovingAverage {
+        symbol, interval, ..
+      }
+      | Event::StrongPoints { symbol, interval } => Some((symbol, interval)),
+      Event::Stats(_) => None,
+    }
+  }
+}
+
+struct Subscriber {
+  symbol: Option<String>,
+  interval: Option<String>,
+  tx: Sender<Event>,
+}
+
+impl Subscriber {
+  fn wants(&self, event: &Event) -> bool {
+    match event.series() {
+      Some((symbol, interval)) => {
+        self.symbol.as_ref().is_none_or(|s| s == symbol)
+          && self.interval.as_ref().is_none_or(|i| i == interval)
+      }
+      None => true,
+    }
+  }
+}
+
+/// Send an event to every connected browser that is interested in it.
+pub fn publish(event: Event) {
+  let mut subscribers = SUBSCRIBERS.lock().unwrap();
+  // clients that went away, or aren't keeping up, are dropped
+  subscribers.retain(|s| {
+    !s.tx.is_closed()
+      && (!s.wants(&event) || s.tx.try_send(event.clone()).is_ok())
+  });
+}
+
+fn stats() -> Stats {
+  Stats {
+    candles: database::CANDLES.load(Relaxed),
+    derived: database::DERIVED_CANDLES.load(Relaxed),
+    unique_violations: database::UNIQUE_VIOLATIONS.load(Relaxed),
+    ma_unique_violations: moving_average::UNIQUE_VIOLATIONS.load(Relaxed),
+    running_jobs: terminal::jobs::list()
+      .iter()
+      .filter(|j| j.status == terminal::jobs::Status::Running)
+      .count(),
+  }
+}
+
+pub fn stats_thread() {
+  thread::spawn(|| loop {
+    thread::sleep(STATS_EVERY);
+    if !SUBSCRIBERS.lock().unwrap().is_empty() {
+      publish(Event::Stats(stats()));
+    }
+  });
+}
+
+#[derive(Deserialize)]
+pub struct EventParams {
+  // omit to hear about every symbol / interval
+  symbol: Option<String>,
+  interval: Option<String>,
+}
+
+/// Server-sent events. Each message is named after the event type, with the
+/// event as JSON for data.
+async fn events(params: web::Query<EventParams>) -> ApiResult {
+  let symbol = match &params.symbol {
+    Some(s) => Some(valid_symbol(Some(s))?),
+    None => None,
+  };
+  let interval = match &params.interval {
+    Some(i) => Some(valid_interval(Some(i))?),
+    None => None,
+  };
+
+  let (tx, rx) = channel(BACKLOG);
+  SUBSCRIBERS.lock().unwrap().push(Subscriber {
+    symbol,
+    interval,
+    tx,
+  });
+
+  let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+    let event = rx.recv().await?;
+    let message = format!(
+      "event: {}\ndata: {}\n\n",
+      event.name(),
+      serde_json::to_string(&event).ok()?
+    );
+    Some((Ok::<_, actix_web::Error>(web::Bytes::from(message)), rx))
+  });
+
+  Ok(
+    HttpResponse::Ok()
+      .content_type("text/event-stream")
+      .insert_header(("Cache-Control", "no-cache"))
+      .streaming(stream),
+  )
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+  cfg.route("/events", web::get().to(events));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::{
+    body::MessageBody,
+    test::{call_service, init_service, TestRequest},
+    App,
+  };
+
+  #[actix_web::test]
+  async fn events_are_filtered_and_streamed() {
+    let app = init_service(App::new().configure(routes)).await;
+    let req = TestRequest::get()
+      .uri("/events?symbol=PUSHTEST&interval=1h")
+      .to_request();
+    let res = call_service(&app, req).await;
+    assert_eq!(
+      res.headers().get("content-type").unwrap(),
+      "text/event-stream"
+    );
+
+    let candle = |interval: &str| Event::Candle {
+      symbol: "PUSHTEST".into(),
+      interval: interval.into(),
+      candle: Candle::default(),
+    };
+    publish(candle("4h"));
+    publish(candle("1h"));
+
+    let mut body = Box::pin(res.into_body());
+    let chunk = futures_util::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+      .await
+      .unwrap()
+      .unwrap();
+    let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(chunk.starts_with("event: candle\ndata: "));
+    assert!(chunk.contains(r#""interval":"1h""#));
+    assert!(chunk.ends_with("\n\n"));
+
+    // the client leaving unsubscribes it, whatever is published next
+    drop(body);
+    publish(candle("4h"));
+    let subscribers = SUBSCRIBERS.lock().unwrap();
+    assert!(subscribers
+      .iter()
+      .all(|s| s.symbol.as_deref() != Some("PUSHTEST")));
+  }
+  #[test]
+  fn slow_clients_are_dropped() {
+    let (tx, _rx) = channel(1);
+    SUBSCRIBERS.lock().unwrap().push(Subscriber {
+      symbol: Some("SLOWTEST".into()),
+      interval: None,
+      tx,
+    });
+    let event = || Event::Stats(stats());
+    publish(event());
+    let slow = |s: &Subscriber| s.symbol.as_deref() == Some("SLOWTEST");
+    assert!(SUBSCRIBERS.lock().unwrap().iter().any(slow));
+    publish(event());
+    assert!(!SUBSCRIBERS.lock().unwrap().iter().any(slow));
+  }
+}
@@ -0,0 +1,327 @@
+use super::{valid_interval, valid_symbol, ApiError, ApiResult};
+use crate::prelude::*;
+use actix_web::{web, HttpResponse};
+
+const DEFAULT_LIMIT: usize = 500;
+
+#[derive(Deserialize, Default)]
+pub struct SeriesParams {
+  symbol: Option<String>,
+  interval: Option<String>,
+  start: Option<i64>,
+  end: Option<i64>,
+  limit: Option<usize>,
+  // asc or desc
+  order: Option<String>,
+  // moving averages only
+  exp: Option<bool>,
+}
+
+impl SeriesParams {
+  /// A query for the symbol and interval, rejecting anything that would
+  /// panic further down.
+  fn query(&self) -> Result<Query, ApiError> {
+    let symbol = valid_symbol(self.symbol.as_deref())?;
+    let interval = valid_interval(self.interval.as_deref())?;
+    if let (Some(start), Some(end)) = (self.start, self.end) {
+      if start > end {
+        return Err(ApiError::bad_request("start must be before end"));
+      }
+    }
+    Ok(Query::new(&symbol, &interval))
+  }
+
+  fn order(&self) -> Result<database::Order, ApiError> {
+    match self.order.as_deref().map(str::to_lowercase).as_deref() {
+      None | Some("asc") => Ok(ASC),
+      Some("desc") => Ok(DESC),
+      Some(o) => Err(ApiError::bad_request(format!("Invalid order '{}'", o))),
+    }
+  }
+
+  fn limit(&self) -> usize {
+    self
+      .limit
+      .unwrap_or(DEFAULT_LIMIT)
+      .clamp(1, CONFIG.query_limit)
+  }
+}
+
+#[derive(Serialize)]
+pub struct CandlePage {
+  candles: Vec<Candle>,
+  // pass as `start` (asc) or `end` (desc) to get the next page
+  next: Option<i64>,
+}
+
+fn candle_page(mut query: Query, params: &SeriesParams) -> Result<CandlePage> {
+  let (limit, order) = (params.limit(), params.order().unwrap_or(ASC));
+  query.set_all(vec![Limit(limit), Order(order.clone())]);
+  if let Some(start) = params.start {
+    query.set(Start(start));
+  }
+  if let Some(end) = params.end {
+    query.set(End(end));
+  }
+
+  let candles = query.query_candles()?;
+  let next = match (candles.len() == limit, candles.last()) {
+    (true, Some(last)) => Some(match order {
+      ASC => last.open_time + query.step(),
+      DESC => last.open_time - query.step(),
+    }),
+    _ => None,
+  };
+  Ok(CandlePage { candles, next })
+}
+
+async fn candles(params: web::Query<SeriesParams>) -> ApiResult {
+  let query = params.query()?;
+  params.order()?;
+  let page = web::block(move || candle_page(query, &params)).await??;
+  Ok(HttpResponse::Ok().json(page))
+}
+
+#[derive(Serialize)]
+pub struct Available {
+  len: i32,
+  exp: bool,
+}
+
+async fn moving_averages(params: web::Query<SeriesParams>) -> ApiResult {
+  let query = params.query()?;
+  let available = web::block(move || {
+    MovingAverage::available(query.symbol(), query.interval())
+  })
+  .await??;
+  Ok(
+    HttpResponse::Ok().json(
+      available
+        .into_iter()
+        .map(|(len, exp)| Available { len, exp })
+        .collect::<Vec<_>>(),
+    ),
+  )
+}
+
+fn moving_average_series(
+  query: Query,
+  len: i32,
+  params: &SeriesParams,
+) -> Result<Vec<MovingAverage>> {
+  let range = match (params.start, params.end) {
+    (None, None) => None,
+    (start, end) => Some(start.unwrap_or(0)..end.unwrap_or_else(now)),
+  };
+  MovingAverage::query_page(
+    query.symbol(),
+    query.interval(),
+    len,
+    params.exp.unwrap_or(false),
+    range,
+    params.order().unwrap_or(ASC),
+    Some(params.limit()),
+  )
+}
+
+async fn moving_average(
+  len: web::Path<i32>,
+  params: web::Query<SeriesParams>,
+) -> ApiResult {
+  let query = params.query()?;
+  params.order()?;
+  let len = len.into_inner();
+  let series =
+    web::block(move || moving_average_series(query, len, &params)).await??;
+  Ok(HttpResponse::Ok().json(series))
+}
+
+#[derive(Serialize)]
+pub struct MissingRange {
+  start: i64,
+  end: i64,
+  candles: usize,
+}
+
+fn missing_ranges(
+  mut query: Query,
+  params: &SeriesParams,
+) -> Result<Vec<MissingRange>> {
+  let start = params.start.unwrap_or_else(|| "1w".ago());
+  query.set_range(start..params.end.unwrap_or_else(now));
+  Ok(
+    query
+      .missing_candles()?
+      .into_iter()
+      .map(|r| MissingRange {
+        candles: r.num_candles(query.interval()),
+        start: r.start,
+        end: r.end,
+      })
+      .collect(),
+  )
+}
+
+async fn missing(params: web::Query<SeriesParams>) -> ApiResult {
+  let query = params.query()?;
+  let ranges = web::block(move || missing_ranges(query, &params)).await??;
+  Ok(HttpResponse::Ok().json(ranges))
+}
+
+#[derive(Serialize)]
+pub struct Counts {
+  candles: usize,
+  derived: usize,
+  coverage: Vec<database::Coverage>,
+}
+
+async fn counts() -> ApiResult {
+  let coverage = web::block(database::coverage).await??;
+  Ok(HttpResponse::Ok().json(Counts {
+    candles: coverage.iter().map(|c| c.count).sum(),
+    derived: coverage.iter().map(|c| c.derived).sum(),
+    coverage,
+  }))
+}
+
+#[derive(Deserialize)]
+pub struct SymbolParams {
+  symbol: Option<String>,
+}
+
+async fn predict(params: web::Query<SymbolParams>) -> ApiResult {
+  let symbol = valid_symbol(params.symbol.as_deref())?;
+  let prediction = web::block({
+    let symbol = symbol.clone();
+    move || model::latest(&symbol)
+  })
+  .await??;
+  match prediction {
+    Some(p) => Ok(HttpResponse::Ok().json(p)),
+    None => Err(ApiError::not_found(format!(
+      "No model is registered for {}",
+      symbol
+    ))),
+  }
+}
+
+async fn models(params: web::Query<SymbolParams>) -> ApiResult {
+  let symbol = valid_symbol(params.symbol.as_deref())?;
+  let entries = web::block(move || model::registry::list(&symbol)).await??;
+  Ok(HttpResponse::Ok().json(entries))
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+  cfg
+    .app_data(
+      web::PathConfig::default()
+        .error_handler(|e, _| ApiError::bad_request(e).into()),
+    )
+    .route("/candles", web::get().to(candles))
+    .route("/moving_averages", web::get().to(moving_averages))
+    .route("/moving_averages/{len}", web::get().to(moving_average))
+    .route("/missing", web::get().to(missing))
+    .route("/counts", web::get().to(counts))
+    .route("/predict", web::get().to(predict))
+    .route("/models", web::get().to(models));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::{
+    http::StatusCode,
+    test::{call_service, init_service, TestRequest},
+    App,
+  };
+
+  #[test]
+  fn candles_are_paginated() -> Result<()> {
+    let mut query = Query::new("RESTTEST", "1h");
+    let step = query.step();
+    let start = "2d".ago().round(step);
+    for i in [0, 1, 2, 3, 4, 6] {
+      query.insert_candle(&Candle {
+        open_time: start + step * i,
+        close_time: start + step * (i + 1) - 1,
+        ..Default::default()
+      })?;
+    }
+
+    let mut params = SeriesParams {
+      start: Some(start),
+      limit: Some(4),
+      ..Default::default()
+    };
+    let page = candle_page(query.clone(), &params)?;
+    assert_eq!(page.candles.len(), 4);
+    assert_eq!(page.next, Some(start + step * 4));
+
+    params.start = page.next;
+    let page = candle_page(query.clone(), &params)?;
+    assert_eq!(page.candles.len(), 2);
+    assert_eq!(page.next, None);
+
+    let params = SeriesParams {
+      end: Some(start + step * 6),
+      limit: Some(2),
+      order: Some("desc".into()),
+      ..Default::default()
+    };
+    let page = candle_page(query.clone(), &params)?;
+    let times: Vec<i64> = page.candles.iter().map(|c| c.open_time).collect();
+    assert_eq!(times, vec![start + step * 6, start + step * 4]);
+    assert_eq!(page.next, Some(start + step * 3));
+
+    let params = SeriesParams {
+      start: Some(start),
+      end: Some(start + step * 7),
+      ..Default::default()
+    };
+    let missing = missing_ranges(query, &params)?;
+    assert_eq!(missing.len(), 1);
+    assert_eq!(missing[0].start, start + step * 5);
+    assert_eq!(missing[0].candles, 1);
+
+    Ok(())
+  }
+
+  #[test]
+  fn moving_averages_are_limited_and_ordered() -> Result<()> {
+    for ms in [1, 2, 3] {
+      con().execute(
+        "INSERT INTO moving_averages (symbol, interval, ms, len, val, exp) VALUES ('RESTTEST', '1h', $1, 20, 1, false)",
+        &[&(ms as i64)],
+      )?;
+    }
+    let params = SeriesParams {
+      limit: Some(2),
+      order: Some("desc".into()),
+      ..Default::default()
+    };
+    let series =
+      moving_average_series(Query::new("RESTTEST", "1h"), 20, &params)?;
+    let times: Vec<i64> = series.iter().map(|ma| ma.ms).collect();
+    assert_eq!(times, vec![3, 2]);
+    Ok(())
+  }
+
+  #[actix_web::test]
+  async fn bad_parameters_are_rejected() {
+    let app = init_service(App::new().configure(routes)).await;
+    for uri in [
+      "/candles?interval=fortnight",
+      "/candles?interval=1h&order=sideways",
+      "/candles?start=10&end=5",
+      "/moving_averages?interval=0m",
+      "/moving_averages/abc",
+      "/moving_averages/20?interval=1h&order=sideways",
+      "/missing?interval=1x",
+      "/missing?symbol=BTC%20USDT",
+    ] {
+      let req = TestRequest::get().uri(uri).to_request();
+      let res = call_service(&app, req).await;
+      assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+  }
+}
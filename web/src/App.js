@@ -63,7 +63,32 @@ function App() {
           }}
           on={hook.config.showTrendLines}
         />
+        <Switch
+          label="Zones"
+          onClick={() => {
+            hook.setConfig({
+              showZones: !hook.config.showZones,
+            })
+          }}
+          on={hook.config.showZones}
+        />
+        <Switch
+          label="Confluence"
+          onClick={() => {
+            hook.setConfig({
+              showConfluence: !hook.config.showConfluence,
+            })
+          }}
+          on={hook.config.showConfluence}
+        />
         <Config {...{ hook }} />
+        {hook.stats && (
+          <div className="ml-auto text-sm text-gray-500">
+            candles: {hook.stats.candles} derived: {hook.stats.derived} uniq
+            err: {hook.stats.unique_violations} jobs:{' '}
+            {hook.stats.running_jobs}
+          </div>
+        )}
       </div>
       <div id="chart-container" className="flex-grow w-full">
         <svg id="chart" />
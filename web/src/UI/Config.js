@@ -31,6 +31,7 @@ export default function Config({ hook }) {
           onKeyPress={(e) => {
             if (e.key === 'Enter') {
               console.log('Set min domain')
+              hook.setConfig('strong_point.limit_by', 'MIN_DOMAIN')
               hook.setConfig('strong_point.min_domain', minDomain)
             }
           }}
@@ -19,18 +19,28 @@ export default function ChartHook() {
     config: {
       showCrosses: false,
       showTrendLines: true,
+      showZones: true,
+      showConfluence: false,
       strong_point: {
-        limit_by: 'FIXED',
-        count: 200,
+        limit_by:
+          search.limit_by || (search.min_domain ? 'MIN_DOMAIN' : 'FIXED'),
+        count: search.count || 200,
         min_domain: search.min_domain,
       },
+      trend_line: {
+        min_strength: search.min_strength,
+      },
     },
     data: {
       candles: [],
       meta: {},
       strong_points: [],
       trend_lines: [],
+      zones: [],
+      confluence: [],
     },
+    stats: null,
+    reloads: 0,
   }
   let [state, dispatch] = useReducer(reducer, initState)
 
@@ -77,7 +87,10 @@ export default function ChartHook() {
     let search = queryString.stringify({
       symbol: state.symbol,
       interval: state.interval,
+      limit_by: state.config.strong_point.limit_by,
+      count: state.config.strong_point.count,
       min_domain: state.config.strong_point.min_domain,
+      min_strength: state.config.trend_line.min_strength,
     })
 
     window.history.replaceState(
@@ -99,9 +112,34 @@ export default function ChartHook() {
     state.symbol,
     state.interval,
     state.pointPercent,
+    state.config.strong_point.limit_by,
+    state.config.strong_point.count,
     state.config.strong_point.min_domain,
+    state.config.trend_line.min_strength,
+    state.reloads,
   ])
 
+  // pushed by the server as candles and indicators are written
+  useEffect(() => {
+    let search = queryString.stringify({
+      symbol: state.symbol,
+      interval: state.interval,
+    })
+    let events = new EventSource(`/events?${search}`)
+    events.addEventListener('candle', (e) => {
+      let { candle } = JSON.parse(e.data)
+      candle.Date = d3.timeParse('%Q')(candle.open_time)
+      dispatch({ type: 'appendCandles', candles: [candle] })
+    })
+    let reload = () => dispatch({ type: 'reload' })
+    events.addEventListener('moving_average', reload)
+    events.addEventListener('strong_points', reload)
+    events.addEventListener('stats', (e) =>
+      dispatch({ type: 'setStats', stats: JSON.parse(e.data) })
+    )
+    return () => events.close()
+  }, [state.symbol, state.interval])
+
   useEffect(() => {
     update({ state })
   }, [state.data, state.config])
@@ -134,6 +172,30 @@ function reducer(state, action) {
           ),
         },
       }
+    case 'appendCandles':
+      return {
+        ...state,
+        data: {
+          ...state.data,
+          candles: sortBy(
+            uniqBy(
+              [...action.candles, ...state.data.candles],
+              (c) => c.open_time
+            ),
+            (c) => c.open_time
+          ),
+        },
+      }
+    case 'reload':
+      return {
+        ...state,
+        reloads: state.reloads + 1,
+      }
+    case 'setStats':
+      return {
+        ...state,
+        stats: action.stats,
+      }
     case 'setIndicators':
       return {
         ...state,
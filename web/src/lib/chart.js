@@ -5,6 +5,8 @@ import Candles from './candles'
 import TrendLines from './trend_lines'
 import TrendLineCrosses from './trend_line_crosses'
 import StrongPoints from './strong_points'
+import Zones from './zones'
+import Confluence from './confluence'
 
 // const MONTHS = ['Jan', 'Feb', 'Mar', 'Apr', 'May', 'Jun', 'Jul', 'Aug', 'Sep', 'Oct', 'Nov', 'Dec']
 const TIME_FORMAT = 'M/D H:MM'
@@ -98,7 +100,16 @@ export function init({ setIndicators }) {
   let trendLines = TrendLines({ chart })
   let trendLineCrosses = TrendLineCrosses({ chart })
   let strongPoints = StrongPoints({ chart })
-  let indicators = [chartCandles, trendLines, trendLineCrosses, strongPoints]
+  let zones = Zones({ chart })
+  let confluence = Confluence({ chart })
+  let indicators = [
+    confluence,
+    zones,
+    chartCandles,
+    trendLines,
+    trendLineCrosses,
+    strongPoints,
+  ]
   setIndicators(indicators)
 
   chart.svg
@@ -0,0 +1,53 @@
+// levels where strong points from several higher timeframes agree, across
+// the whole chart, darker the higher they score
+export default function Confluence({ chart: { chartBody, y } }) {
+  let opacity = (l, levels) =>
+    0.15 + (0.5 * l.score) / Math.max(...levels.map((l) => l.score))
+
+  function update({
+    state: {
+      data,
+      config: { showConfluence },
+    },
+    chart: { x, y, t },
+  }) {
+    let levels = showConfluence ? data.confluence : []
+    chartBody
+      .selectAll('.confluence')
+      .data(levels)
+      .join(
+        (enter) =>
+          enter
+            .append('rect')
+            .attr('class', 'confluence')
+            .attr('x', x.range()[0])
+            .attr('width', x.range()[1] - x.range()[0])
+            .attr('y', (l) => y(l.high))
+            .attr('height', (l) => Math.max(y(l.low) - y(l.high), 1))
+            .attr('fill', 'rgb(128, 0, 255)')
+            .attr('fill-opacity', (l) => opacity(l, levels)),
+        (update) =>
+          update.call((update) =>
+            update
+              .transition(t)
+              .attr('y', (l) => y(l.high))
+              .attr('height', (l) => Math.max(y(l.low) - y(l.high), 1))
+              .attr('fill-opacity', (l) => opacity(l, levels))
+          ),
+        (exit) => exit.remove()
+      )
+  }
+
+  function zoomed() {}
+
+  function zoomEnd() {
+    chartBody
+      .selectAll('.confluence')
+      .transition()
+      .duration(200)
+      .attr('y', (l) => y(l.high))
+      .attr('height', (l) => Math.max(y(l.low) - y(l.high), 1))
+  }
+
+  return { zoomed, zoomEnd, update }
+}
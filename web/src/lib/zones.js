@@ -0,0 +1,61 @@
+// support / resistance zones, from the first touch until price broke out
+// of them, or to the right edge if they still hold
+export default function Zones({ chart: { chartBody, y } }) {
+  let end = (z, x) => (z.breakout.at ? x(z.breakout.at) : x.range()[1])
+  let color = (z) =>
+    z.breakout.status === 'held'
+      ? 'rgba(255, 165, 0, 0.15)'
+      : 'rgba(128, 128, 128, 0.1)'
+
+  function update({
+    state: {
+      data,
+      config: { showZones },
+    },
+    chart: { x, y, t },
+  }) {
+    chartBody
+      .selectAll('.zone')
+      .data(showZones ? data.zones : [])
+      .join(
+        (enter) =>
+          enter
+            .append('rect')
+            .attr('class', 'zone')
+            .attr('x', (z) => x(z.first_touch))
+            .attr('width', (z) => Math.max(end(z, x) - x(z.first_touch), 0))
+            .attr('y', (z) => y(z.high))
+            .attr('height', (z) => Math.max(y(z.low) - y(z.high), 1))
+            .attr('fill', color),
+        (update) =>
+          update.call((update) =>
+            update
+              .transition(t)
+              .attr('x', (z) => x(z.first_touch))
+              .attr('width', (z) => Math.max(end(z, x) - x(z.first_touch), 0))
+              .attr('y', (z) => y(z.high))
+              .attr('height', (z) => Math.max(y(z.low) - y(z.high), 1))
+              .attr('fill', color)
+          ),
+        (exit) => exit.remove()
+      )
+  }
+
+  function zoomed({ xz }) {
+    chartBody
+      .selectAll('.zone')
+      .attr('x', (z) => xz(z.first_touch))
+      .attr('width', (z) => Math.max(end(z, xz) - xz(z.first_touch), 0))
+  }
+
+  function zoomEnd() {
+    chartBody
+      .selectAll('.zone')
+      .transition()
+      .duration(200)
+      .attr('y', (z) => y(z.high))
+      .attr('height', (z) => Math.max(y(z.low) - y(z.high), 1))
+  }
+
+  return { zoomed, zoomEnd, update }
+}